@@ -0,0 +1,70 @@
+//! Measures decoding a large embeddings response the way the client decodes responses,
+//! with the embeddings as floats and in base64.
+//!
+//! Compare `cargo bench` with `cargo bench --features simd-json` to see whether `simd-json` is faster.
+
+use base64::{engine::general_purpose::STANDARD, Engine};
+use criterion::{black_box, criterion_group, criterion_main, BatchSize, Criterion};
+use openai::{embeddings::Embeddings, json};
+
+/// 100 embeddings of 1536 dimensions, like `text-embedding-ada-002`'s.
+fn embeddings() -> Vec<Vec<f32>> {
+    (0..100)
+        .map(|index| {
+            (0..1536)
+                .map(|dimension| ((index * 1536 + dimension) as f32).sin() * 0.05)
+                .collect()
+        })
+        .collect()
+}
+
+fn embeddings_body(encode: impl Fn(&[f32]) -> serde_json::Value) -> Vec<u8> {
+    let data: Vec<_> = embeddings()
+        .iter()
+        .enumerate()
+        .map(|(index, embedding)| {
+            serde_json::json!({"object": "embedding", "index": index, "embedding": encode(embedding)})
+        })
+        .collect();
+
+    serde_json::to_vec(&serde_json::json!({
+        "object": "list",
+        "data": data,
+        "model": "text-embedding-ada-002",
+        "usage": {"prompt_tokens": 800, "total_tokens": 800}
+    }))
+    .unwrap()
+}
+
+/// Decodes the body like a response, which the client owns, so it's cloned outside of the measurement.
+fn bench_decode(c: &mut Criterion, name: &str, body: &[u8]) {
+    c.bench_function(name, |b| {
+        b.iter_batched(
+            || body.to_vec(),
+            |body| json::decode::<Embeddings>(black_box(body)).unwrap(),
+            BatchSize::LargeInput,
+        )
+    });
+}
+
+fn decode_embeddings(c: &mut Criterion) {
+    let body = embeddings_body(|embedding| serde_json::json!(embedding));
+
+    bench_decode(c, "embeddings", &body);
+}
+
+fn decode_base64_embeddings(c: &mut Criterion) {
+    let body = embeddings_body(|embedding| {
+        let bytes: Vec<u8> = embedding
+            .iter()
+            .flat_map(|float| float.to_le_bytes())
+            .collect();
+
+        serde_json::json!(STANDARD.encode(bytes))
+    });
+
+    bench_decode(c, "base64 embeddings", &body);
+}
+
+criterion_group!(benches, decode_embeddings, decode_base64_embeddings);
+criterion_main!(benches);
@@ -1,4 +1,4 @@
-use serde::Deserialize;
+use serde::{Deserialize, Serialize};
 
 pub const BASE_URL: &str = "https://api.openai.com/v1/";
 
@@ -11,25 +11,61 @@ macro_rules! authorization {
 
         dotenv().ok();
 
-        let token =
-            env::var("OPENAI_KEY").expect("environment variable `OPENAI_KEY` should be defined");
+        let token = env::var("OPENAI_API_KEY")
+            .or_else(|_| env::var("OPENAI_KEY"))
+            .expect("environment variable `OPENAI_API_KEY` or `OPENAI_KEY` should be defined");
 
         $request.header(AUTHORIZATION, format!("Bearer {token}"))
     }};
 }
 
-#[derive(Deserialize, Debug, Clone)]
+#[derive(Deserialize, Serialize, Debug, Clone, PartialEq, Eq)]
 pub struct OpenAiError {
     pub message: String,
     #[serde(rename = "type")]
     pub error_type: String,
     pub param: Option<String>,
     pub code: Option<String>,
+    /// The `x-request-id` header of the response, which OpenAI's support asks for,
+    /// `None` if the response didn't have one, e.g. because a proxy stripped it.
+    #[serde(skip)]
+    pub request_id: Option<String>,
+}
+
+impl OpenAiError {
+    /// Creates an error like the API would respond with,
+    /// for example to simulate failures in tests.
+    pub fn new(message: impl Into<String>, error_type: impl Into<String>) -> Self {
+        OpenAiError {
+            message: message.into(),
+            error_type: error_type.into(),
+            param: None,
+            code: None,
+            request_id: None,
+        }
+    }
+
+    /// Sets the parameter the error is about.
+    pub fn param(mut self, param: impl Into<String>) -> Self {
+        self.param = Some(param.into());
+        self
+    }
+
+    /// Sets the machine-readable code of the error, e.g. `model_not_found`.
+    pub fn code(mut self, code: impl Into<String>) -> Self {
+        self.code = Some(code.into());
+        self
+    }
 }
 
 impl std::fmt::Display for OpenAiError {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        write!(f, "{}", self.message)
+        write!(f, "{}", self.message)?;
+
+        match &self.request_id {
+            Some(request_id) => write!(f, " (request ID {request_id})"),
+            None => Ok(()),
+        }
     }
 }
 
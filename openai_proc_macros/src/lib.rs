@@ -51,6 +51,7 @@ pub fn generate_model_id_enum(_input: TokenStream) -> TokenStream {
                 use serde::{ Serialize, de };
 
                 #[derive(Debug, PartialEq, Default, Clone)]
+                #[non_exhaustive]
                 pub enum ModelID {
                     #[default]
                     #(#model_id_idents),*,
@@ -87,9 +88,9 @@ pub fn generate_model_id_enum(_input: TokenStream) -> TokenStream {
                             where
                                 E: de::Error,
                             {
-                                match v {
+                                match canonical_model_id(v) {
                                     #( #model_ids => Ok(ModelID::#model_id_idents) ),*,
-                                    _ => Ok(ModelID::Custom(v.to_string())),
+                                    v => Ok(ModelID::Custom(v.to_string())),
                                 }
                             }
                         }
@@ -0,0 +1,250 @@
+//! Record the requests a client sends and the responses it gets to a JSON file, a cassette,
+//! and replay them later without a network connection or API key, e.g. to run tests offline.
+//!
+//! Streamed completions aren't recorded, as they don't go through the layers of the client,
+//! so they always need a connection.
+//!
+//! Requires the `cassettes` feature.
+
+use super::{
+    layer::{Layer, Next, Request, Response, REQUEST_ID},
+    RequestError,
+};
+use async_trait::async_trait;
+use reqwest::{
+    header::{HeaderMap, HeaderValue},
+    StatusCode,
+};
+use serde::{Deserialize, Serialize};
+use std::{
+    env, fs, io,
+    path::PathBuf,
+    sync::{Arc, Mutex},
+};
+
+/// The environment variable that makes [`CassetteLayer::from_env`] record, when set to `record`.
+pub const RECORD_VAR: &str = "OPENAI_CASSETTES";
+
+/// Records or replays the requests of a client, see the [module documentation](self).
+///
+/// Requests are matched by their method, route and body, with JSON bodies compared as values,
+/// so the order of their keys doesn't matter. Each recorded response is replayed once, in the order
+/// they were recorded. Requests that weren't recorded are answered with a `404 Not Found` error.
+///
+/// Streamed completions don't go through the layers, so they're neither recorded nor replayed.
+#[derive(Debug, Clone)]
+pub struct CassetteLayer {
+    path: PathBuf,
+    mode: CassetteMode,
+    interactions: Arc<Mutex<Vec<Interaction>>>,
+}
+
+/// Whether a [`CassetteLayer`] records or replays.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CassetteMode {
+    /// Sends the requests and saves them with their responses, replacing what the cassette held.
+    Record,
+    /// Answers the requests from the cassette, without sending them.
+    Replay,
+}
+
+#[derive(Deserialize, Serialize, Debug, Clone, PartialEq)]
+struct Interaction {
+    method: String,
+    route: String,
+    /// The JSON body of the request, a string if it isn't JSON, or null if there's none.
+    request: serde_json::Value,
+    status: u16,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    request_id: Option<String>,
+    /// The JSON body of the response, or a string if it isn't JSON.
+    response: serde_json::Value,
+}
+
+impl CassetteLayer {
+    /// Records to the cassette at `path`, which is written after every request.
+    pub fn record(path: impl Into<PathBuf>) -> Self {
+        CassetteLayer {
+            path: path.into(),
+            mode: CassetteMode::Record,
+            interactions: Arc::default(),
+        }
+    }
+
+    /// Replays the cassette at `path`, failing if it can't be read or isn't a cassette.
+    pub fn replay(path: impl Into<PathBuf>) -> io::Result<Self> {
+        let path = path.into();
+        let interactions = serde_json::from_slice(&fs::read(&path)?)?;
+
+        Ok(CassetteLayer {
+            path,
+            mode: CassetteMode::Replay,
+            interactions: Arc::new(Mutex::new(interactions)),
+        })
+    }
+
+    /// Records to `path` if [`RECORD_VAR`] is set to `record`, otherwise replays it if it exists.
+    ///
+    /// `None` if there's nothing to replay, so the requests are sent as if there was no cassette.
+    pub fn from_env(path: impl Into<PathBuf>) -> io::Result<Option<Self>> {
+        let path = path.into();
+
+        if env::var(RECORD_VAR).is_ok_and(|mode| mode == "record") {
+            Ok(Some(Self::record(path)))
+        } else if path.exists() {
+            Self::replay(path).map(Some)
+        } else {
+            Ok(None)
+        }
+    }
+
+    pub fn mode(&self) -> CassetteMode {
+        self.mode
+    }
+
+    fn save(&self, interactions: &[Interaction]) {
+        let json = serde_json::to_vec_pretty(interactions).unwrap();
+
+        if let Some(parent) = self.path.parent() {
+            fs::create_dir_all(parent).ok();
+        }
+
+        fs::write(&self.path, json)
+            .unwrap_or_else(|error| panic!("couldn't write {}: {error}", self.path.display()));
+    }
+}
+
+/// The body as JSON, falling back to a string.
+fn body_value(body: &[u8]) -> serde_json::Value {
+    serde_json::from_slice(body)
+        .unwrap_or_else(|_| serde_json::Value::String(String::from_utf8_lossy(body).into_owned()))
+}
+
+#[async_trait]
+impl Layer for CassetteLayer {
+    async fn call(&self, request: Request, next: Next<'_>) -> Result<Response, RequestError> {
+        let method = request.http.method().to_string();
+        let route = request.route.clone();
+        let body = match request.http.body().and_then(|body| body.as_bytes()) {
+            Some(body) => body_value(body),
+            None => serde_json::Value::Null,
+        };
+
+        match self.mode {
+            CassetteMode::Record => {
+                let response = next.run(request).await?;
+                let mut interactions = self.interactions.lock().unwrap();
+
+                interactions.push(Interaction {
+                    method,
+                    route,
+                    request: body,
+                    status: response.status.as_u16(),
+                    request_id: response.request_id().map(str::to_string),
+                    response: body_value(&response.body),
+                });
+                self.save(&interactions);
+
+                Ok(response)
+            }
+            CassetteMode::Replay => {
+                let mut interactions = self.interactions.lock().unwrap();
+                let position = interactions.iter().position(|interaction| {
+                    interaction.method == method
+                        && interaction.route == route
+                        && interaction.request == body
+                });
+                let Some(interaction) = position.map(|position| interactions.remove(position))
+                else {
+                    let error = serde_json::json!({"error": {
+                        "message": format!(
+                            "{method} {route} with this body isn't recorded in {}",
+                            self.path.display(),
+                        ),
+                        "type": "cassette_error",
+                    }});
+
+                    return Ok(Response {
+                        status: StatusCode::NOT_FOUND,
+                        headers: HeaderMap::new(),
+                        body: error.to_string().into_bytes(),
+                    });
+                };
+                let mut headers = HeaderMap::new();
+
+                if let Some(request_id) = interaction
+                    .request_id
+                    .and_then(|request_id| HeaderValue::from_str(&request_id).ok())
+                {
+                    headers.insert(REQUEST_ID, request_id);
+                }
+
+                Ok(Response {
+                    status: StatusCode::from_u16(interaction.status)
+                        .unwrap_or(StatusCode::INTERNAL_SERVER_ERROR),
+                    headers,
+                    body: match interaction.response {
+                        serde_json::Value::String(body) => body.into_bytes(),
+                        response => response.to_string().into_bytes(),
+                    },
+                })
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        chat::ChatCompletion,
+        models::ModelID,
+        tests::{chat_completion_json, StubLayer},
+        Client, Credentials,
+    };
+
+    #[tokio::test]
+    async fn record_and_replay() {
+        let path = env::temp_dir().join(format!("openai-cassette-{}.json", std::process::id()));
+        let create = |client: Client, content: &'static str| async move {
+            ChatCompletion::builder(
+                ModelID::Gpt3_5Turbo,
+                [crate::chat::ChatCompletionMessage::user(content)],
+            )
+            .create(&client)
+            .await
+            .unwrap()
+        };
+        let recording = Client::builder()
+            .credentials(Credentials::new("sk-test"))
+            .layer(CassetteLayer::record(&path))
+            // Answers with the length of the request's body
+            .layer(StubLayer::new(|_, body| {
+                let length = body.to_string().len().to_string();
+
+                (StatusCode::OK, chat_completion_json(&length, "stop"))
+            }))
+            .build()
+            .unwrap();
+        let recorded = create(recording, "Hello!").await.unwrap();
+        // Nothing below the cassette to send the request, so the key is never used
+        let replaying = || {
+            Client::builder()
+                .credentials(Credentials::new("sk-test"))
+                .layer(CassetteLayer::replay(&path).unwrap())
+                .build()
+                .unwrap()
+        };
+        let replayed = create(replaying(), "Hello!").await.unwrap();
+
+        assert_eq!(replayed.content(), recorded.content());
+        assert_eq!(replayed.id, recorded.id);
+
+        let error = create(replaying(), "Goodbye!").await.unwrap_err();
+
+        assert_eq!(error.error_type, "cassette_error");
+        assert!(error.message.starts_with("POST chat/completions"));
+
+        fs::remove_file(path).unwrap();
+    }
+}
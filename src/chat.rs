@@ -1,78 +1,537 @@
 //! Given a chat conversation, the model will return a chat completion response.
 
-use super::{models::ModelID, openai_post, ApiResponseOrError, Usage};
+use super::{
+    invalid_request, json, layer, models::ModelID, openai_delete, openai_dry_run, openai_get,
+    openai_post, openai_request, paginate, ApiResponseOrError, Client, DeletedObject, DryRun,
+    EndUserId, OpenAiError, Order, OutOfRangeError, Page, Penalty, RequestError, StreamError,
+    Temperature, TopP, Usage,
+};
 use derive_builder::Builder;
-use futures::{future, Stream, StreamExt};
-use openai_bootstrap::{authorization, BASE_URL};
-use reqwest::{Client, Method};
-use reqwest_eventsource::{Event, EventSource};
-use serde::{Deserialize, Serialize};
-use std::collections::HashMap;
-
-#[derive(Deserialize, Clone, Debug)]
+use eventsource_stream::{Event as MessageEvent, Eventsource};
+use futures::{
+    future::{self, Either},
+    stream, Stream, StreamExt, TryStreamExt,
+};
+use futures_timer::Delay;
+use reqwest::{
+    header::{HeaderMap, HeaderValue, CONTENT_TYPE},
+    Method, RequestBuilder, Response, StatusCode,
+};
+use reqwest_eventsource::Error as EventSourceError;
+use serde::{Deserialize, Serialize, Serializer};
+use std::{
+    collections::{hash_map::DefaultHasher, BTreeMap, HashMap},
+    hash::{Hash, Hasher},
+    marker::PhantomData,
+    pin::Pin,
+    sync::{Arc, OnceLock},
+    task::{Context, Poll},
+    time::{Duration, Instant},
+};
+use tokio::{
+    io::{AsyncWrite, AsyncWriteExt},
+    sync::mpsc::UnboundedSender,
+};
+#[cfg(feature = "tokio-runtime")]
+use tokio::{
+    sync::mpsc::{self, Sender},
+    task::JoinHandle,
+};
+
+#[derive(Deserialize, Serialize, Clone, Debug)]
 pub struct ChatCompletion {
     pub id: String,
     pub object: String,
     pub created: u64,
     pub model: ModelID,
+    /// The generated choices, which can be empty, for example if the response was filtered.
+    /// [`ChatCompletion::first_choice`] and [`ChatCompletion::content`] account for that.
     pub choices: Vec<ChatCompletionChoice>,
     pub usage: Option<Usage>,
+    /// Identifies the backend configuration the model ran with,
+    /// see [`ChatCompletion::compare_backend`].
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub system_fingerprint: Option<String>,
+    /// The service tier the request was processed with, which may differ from the requested one.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub service_tier: Option<ServiceTier>,
+}
+
+/// Whether two completions ran on the same backend configuration,
+/// see [`ChatCompletion::compare_backend`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Reproducibility {
+    /// Both completions report the same `system_fingerprint`,
+    /// so requests with the same `seed` and parameters should mostly return the same result.
+    SameBackend,
+    /// The `system_fingerprint`s differ, so results can differ despite the same `seed`.
+    DifferentBackend,
+    /// At least one of the completions doesn't report a `system_fingerprint`.
+    Unknown,
+}
+
+/// Filters and pagination for [`ChatCompletion::list`].
+#[derive(Clone, Debug, Default)]
+pub struct ChatCompletionListParams {
+    /// The ID of the last chat completion from the previous page, see [`Page::last_id`].
+    pub after: Option<String>,
+    /// Number of chat completions to retrieve, defaults to 20.
+    pub limit: Option<u32>,
+    /// Only retrieve chat completions with all of these metadata key-value pairs.
+    pub metadata: HashMap<String, String>,
+    /// Only retrieve chat completions generated by this model.
+    pub model: Option<ModelID>,
+    /// Order of the chat completions by creation time, defaults to ascending.
+    pub order: Option<Order>,
 }
 
-#[derive(Deserialize, Clone, Debug, PartialEq)]
+#[derive(Deserialize, Serialize, Clone, Debug, PartialEq)]
 pub struct ChatCompletionEvent {
     pub id: String,
     pub object: String,
     pub created: u64,
     pub model: ModelID,
     pub choices: Vec<ChatCompletionChoiceDelta>,
+    /// The usage of the whole request, sent in a last event without choices
+    /// when the request's `stream_options` has `include_usage` set.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub usage: Option<Usage>,
+    /// Sent with every event, see [`ChatCompletion::system_fingerprint`].
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub system_fingerprint: Option<String>,
+    /// Sent with every event, see [`ChatCompletion::service_tier`].
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub service_tier: Option<ServiceTier>,
 }
 
-#[derive(Deserialize, Clone, Debug)]
+#[derive(Deserialize, Serialize, Clone, Debug)]
+#[serde(try_from = "RawChatCompletionChoice")]
 pub struct ChatCompletionChoice {
+    /// The position of the choice among the `n` requested, 0 if the response leaves it out.
+    #[serde(default)]
     pub index: u64,
     pub message: ChatCompletionMessage,
+    /// Also read from `finish_details: {"type": ..}`, as some models and compatible services report it,
+    /// if `finish_reason` is missing or null.
     pub finish_reason: String,
+    /// Log probability information for the choice, if `logprobs` was requested.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub logprobs: Option<ChatCompletionLogprobs>,
+    /// Why the content was (or wasn't) filtered, reported by Azure OpenAI and sometimes OpenAI,
+    /// in particular when `finish_reason` is `content_filter`.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub content_filter_results: Option<ContentFilterResults>,
 }
 
-#[derive(Deserialize, Clone, Debug, PartialEq)]
+#[derive(Deserialize, Serialize, Clone, Debug, PartialEq)]
+#[serde(from = "RawChatCompletionChoiceDelta")]
 pub struct ChatCompletionChoiceDelta {
     pub index: u64,
     pub delta: Delta,
+    /// Also read from `finish_details`, see [`ChatCompletionChoice::finish_reason`].
     pub finish_reason: Option<String>,
+    /// Log probability information for the tokens of this delta, if `logprobs` was requested.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub logprobs: Option<ChatCompletionLogprobs>,
+    /// Why the content was (or wasn't) filtered, see [`ChatCompletionChoice::content_filter_results`].
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub content_filter_results: Option<ContentFilterResults>,
+}
+
+/// The outcome of content filtering for each category, where the service reports it.
+#[derive(Deserialize, Serialize, Clone, Debug, Default, PartialEq, Eq)]
+pub struct ContentFilterResults {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub hate: Option<ContentFilterSeverityResult>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub self_harm: Option<ContentFilterSeverityResult>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub sexual: Option<ContentFilterSeverityResult>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub violence: Option<ContentFilterSeverityResult>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub profanity: Option<ContentFilterDetectedResult>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub jailbreak: Option<ContentFilterDetectedResult>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub protected_material_text: Option<ContentFilterDetectedResult>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub protected_material_code: Option<ContentFilterDetectedResult>,
+}
+
+#[derive(Deserialize, Serialize, Clone, Copy, Debug, PartialEq, Eq)]
+pub struct ContentFilterSeverityResult {
+    pub filtered: bool,
+    pub severity: ContentFilterSeverity,
+}
+
+#[derive(Deserialize, Serialize, Clone, Copy, Debug, PartialEq, Eq)]
+pub struct ContentFilterDetectedResult {
+    pub filtered: bool,
+    pub detected: bool,
+}
+
+#[derive(Deserialize, Serialize, Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+#[serde(rename_all = "lowercase")]
+#[non_exhaustive]
+pub enum ContentFilterSeverity {
+    Safe,
+    Low,
+    Medium,
+    High,
+}
+
+#[derive(Deserialize, Serialize, Clone, Debug, PartialEq)]
+pub struct ChatCompletionLogprobs {
+    /// Log probability information for each of the message content tokens.
+    pub content: Option<Vec<TokenLogprob>>,
+}
+
+#[derive(Deserialize, Serialize, Clone, Debug, PartialEq)]
+pub struct TokenLogprob {
+    pub token: String,
+    /// The log probability of this token.
+    pub logprob: f64,
+    /// The UTF-8 bytes of the token.
+    /// A character may be split across the bytes of multiple tokens,
+    /// use a [`TokenBytesDecoder`] to reassemble them.
+    pub bytes: Option<Vec<u8>>,
+    /// The most likely tokens at this position, as many as `top_logprobs` requested.
+    pub top_logprobs: Vec<TopLogprob>,
+}
+
+#[derive(Deserialize, Serialize, Clone, Debug, PartialEq)]
+pub struct TopLogprob {
+    pub token: String,
+    pub logprob: f64,
+    pub bytes: Option<Vec<u8>>,
+}
+
+/// Reassembles text from the bytes of consecutive tokens,
+/// holding back characters split across tokens until all of their bytes have arrived.
+#[derive(Clone, Debug, Default)]
+pub struct TokenBytesDecoder {
+    pending: Vec<u8>,
+}
+
+/// The events of a streamed chat completion, see [`ChatCompletionBuilder::create_stream`].
+pub struct ChatCompletionStream {
+    events: Pin<Box<dyn Stream<Item = Result<ChatCompletionEvent, StreamError>> + Send>>,
+    connection: Arc<OnceLock<Connection>>,
+}
+
+/// The response a stream's events arrive in, from when it arrived.
+struct Connection {
+    headers: HeaderMap,
+    /// When the request was sent.
+    sent_at: Instant,
+    /// When the response arrived.
+    connected_at: Instant,
+}
+
+/// An item of the stream from [`ChatCompletionBuilder::create_restarting_stream`].
+#[derive(Clone, Debug, PartialEq)]
+pub enum RestartingStreamItem {
+    Event(ChatCompletionEvent),
+    /// The connection dropped and the completion was restarted from scratch,
+    /// the events that follow belong to a new completion.
+    Restarted {
+        /// How many times the completion has been restarted so far.
+        restarts: u32,
+    },
 }
 
-#[derive(Deserialize, Clone, Debug, PartialEq)]
+#[derive(Deserialize, Serialize, Clone, Debug, PartialEq)]
 #[serde(untagged)]
+#[non_exhaustive]
 pub enum Delta {
     Role { role: ChatCompletionMessageRole },
     Content { content: String },
     EndOfStream {},
 }
 
-#[derive(Deserialize, Serialize, Debug, Clone)]
+#[derive(Deserialize, Serialize, Debug, Clone, PartialEq, Eq, Hash)]
 pub struct ChatCompletionMessage {
     /// The role of the author of this message.
     pub role: ChatCompletionMessageRole,
     /// The contents of the message
-    pub content: String,
+    ///
+    /// `None` if the assistant responded with audio only, or refused to respond, see `refusal`.
+    #[serde(default)]
+    pub content: Option<String>,
+    /// Why the assistant refused to respond, in which case `content` is `None`.
+    ///
+    /// Lets a refusal be told apart from an empty reply.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub refusal: Option<String>,
+    /// Citations of the sources the content is based on, e.g. when searching the web,
+    /// see [`ChatCompletionBuilder::web_search_options`].
+    ///
+    /// `None` if the response didn't have any, `Some` of an empty list if it had an empty one.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub annotations: Option<Vec<Annotation>>,
     /// The name of the user in a multi-user chat
+    ///
+    /// Only letters, digits, underscores and hyphens are allowed, see [`ChatCompletionMessage::with_name`].
     #[serde(skip_serializing_if = "Option::is_none")]
     pub name: Option<String>,
+    /// The audio the assistant responded with, if the `audio` modality was requested.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub audio: Option<ChatCompletionAudio>,
+}
+
+#[derive(Deserialize, Serialize, Debug, Clone, PartialEq, Eq, Hash)]
+pub struct ChatCompletionAudio {
+    /// Identifies the audio, for referring to it in later turns of a conversation.
+    pub id: String,
+    /// The audio, encoded in base64 in the requested format.
+    pub data: String,
+    /// When the audio stops being available for later turns, as a Unix timestamp in seconds.
+    pub expires_at: u64,
+    /// Transcript of the audio.
+    pub transcript: String,
+}
+
+/// A note on part of a message's content.
+#[derive(Deserialize, Serialize, Debug, Clone, PartialEq, Eq, Hash)]
+#[serde(tag = "type", rename_all = "snake_case")]
+#[non_exhaustive]
+pub enum Annotation {
+    /// The content cites a web page.
+    UrlCitation { url_citation: UrlCitation },
+    /// The content cites an uploaded file, e.g. one searched by a retrieval tool.
+    FileCitation {
+        /// The placeholder in the content that stands for the citation.
+        text: String,
+        file_citation: FileCitation,
+        /// The index of the first character of the placeholder.
+        start_index: u32,
+        /// The index after the last character of the placeholder.
+        end_index: u32,
+    },
+    /// Any other kind of annotation, e.g. one added to the API after this library was released.
+    #[serde(other)]
+    Unknown,
+}
+
+#[derive(Deserialize, Serialize, Debug, Clone, PartialEq, Eq, Hash)]
+pub struct UrlCitation {
+    /// The index of the first character of the content the citation applies to.
+    pub start_index: u32,
+    /// The index after the last character of the content the citation applies to.
+    pub end_index: u32,
+    pub url: String,
+    /// The title of the web page.
+    pub title: String,
+}
+
+#[derive(Deserialize, Serialize, Debug, Clone, PartialEq, Eq, Hash)]
+pub struct FileCitation {
+    /// The ID of the cited file.
+    pub file_id: String,
+}
+
+/// Options for streaming a chat completion, see [`ChatCompletionBuilder::stream_options`].
+#[derive(Deserialize, Serialize, Debug, Clone, Copy, Default, PartialEq, Eq, Hash)]
+pub struct StreamOptions {
+    /// Sends the usage of the whole request in a last event, see [`ChatCompletionEvent::usage`].
+    pub include_usage: bool,
+}
+
+/// Known parts of the response, which the model can skip generating,
+/// e.g. the current version of a file when asking for a small change to it.
+///
+/// Speeds up the response if it mostly matches, but predicted tokens that don't end up in it are billed anyway,
+/// see [`CompletionTokensDetails::rejected_prediction_tokens`](crate::CompletionTokensDetails::rejected_prediction_tokens).
+#[derive(Deserialize, Serialize, Debug, Clone, PartialEq, Eq, Hash)]
+#[serde(tag = "type", rename_all = "snake_case")]
+#[non_exhaustive]
+pub enum Prediction {
+    /// The text the response is expected to match.
+    Content { content: String },
+}
+
+/// How the model searches the web before responding, see [`ChatCompletionBuilder::web_search_options`].
+#[derive(Deserialize, Serialize, Debug, Clone, Default, PartialEq, Eq, Hash)]
+pub struct WebSearchOptions {
+    /// How much context is retrieved from the web, `medium` by default.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub search_context_size: Option<SearchContextSize>,
+    /// Where the user is, to refine the search results.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub user_location: Option<UserLocation>,
+}
+
+/// How much context is retrieved from the web, trading quality for cost and latency.
+#[derive(Deserialize, Serialize, Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[serde(rename_all = "lowercase")]
+#[non_exhaustive]
+pub enum SearchContextSize {
+    Low,
+    Medium,
+    High,
+}
+
+#[derive(Deserialize, Serialize, Debug, Clone, PartialEq, Eq, Hash)]
+#[serde(tag = "type", rename_all = "lowercase")]
+#[non_exhaustive]
+pub enum UserLocation {
+    Approximate { approximate: ApproximateLocation },
+}
+
+/// Every part of the location is optional.
+#[derive(Deserialize, Serialize, Debug, Clone, Default, PartialEq, Eq, Hash)]
+pub struct ApproximateLocation {
+    /// The two-letter ISO country code, e.g. `GB`.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub country: Option<String>,
+    /// The region, e.g. `London`.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub region: Option<String>,
+    /// The city, e.g. `London`.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub city: Option<String>,
+    /// The IANA timezone, e.g. `Europe/London`.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub timezone: Option<String>,
+}
+
+/// The types of output the model can generate.
+#[derive(Deserialize, Serialize, Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[serde(rename_all = "lowercase")]
+#[non_exhaustive]
+pub enum Modality {
+    Text,
+    Audio,
+}
+
+/// How the model should respond with audio, required when requesting the `audio` modality.
+#[derive(Deserialize, Serialize, Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct AudioOutput {
+    pub voice: Voice,
+    pub format: AudioFormat,
+}
+
+/// The voices the model can respond with.
+#[derive(Deserialize, Serialize, Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[serde(rename_all = "lowercase")]
+#[non_exhaustive]
+pub enum Voice {
+    Alloy,
+    Ash,
+    Ballad,
+    Coral,
+    Echo,
+    Sage,
+    Shimmer,
+    Verse,
 }
 
-#[derive(Deserialize, Serialize, Debug, Clone, Copy, PartialEq)]
+#[derive(Deserialize, Serialize, Debug, Clone, Copy, PartialEq, Eq, Hash)]
 #[serde(rename_all = "lowercase")]
+#[non_exhaustive]
+pub enum AudioFormat {
+    Wav,
+    Mp3,
+    Flac,
+    Opus,
+    Pcm16,
+}
+
+/// A prompt with `{{name}}` placeholders for variables, see [`PromptTemplate::render`].
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct PromptTemplate {
+    template: String,
+}
+
+/// A line of a transcript that isn't a message, see [`parse_transcript`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ParseTranscriptError {
+    /// The number of the offending line, starting at 1.
+    pub line: usize,
+}
+
+/// A name the API doesn't accept for the author of a message, see [`ChatCompletionMessage::with_name`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct InvalidNameError {
+    pub name: String,
+}
+
+/// Why [`ChatCompletion::export_training_data`] failed.
+#[derive(Debug)]
+pub enum ExportError {
+    /// Fetching the stored completions or their messages failed.
+    Request(RequestError),
+    /// The API rejected fetching the stored completions or their messages.
+    Api(OpenAiError),
+    /// Writing the dataset failed.
+    Write(std::io::Error),
+}
+
+/// Everything [`ChatCompletionRequest::validate`] found wrong with a request.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ValidationError {
+    /// Never empty.
+    pub violations: Vec<Violation>,
+}
+
+/// A single problem with a request, see [`ChatCompletionRequest::validate`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Violation {
+    /// The name of the offending field, e.g. `temperature`.
+    pub field: &'static str,
+    pub message: String,
+}
+
+#[derive(Deserialize, Debug, Clone, PartialEq, Eq, Hash)]
+#[serde(from = "String")]
+#[non_exhaustive]
 pub enum ChatCompletionMessageRole {
+    /// Instructions for models before the reasoning models (the o-series), like `gpt-4o`.
     System,
+    /// Instructions for reasoning models (the o-series) and newer models,
+    /// which take precedence over those of the user. Replaces `system`, which those models treat differently.
+    Developer,
     User,
     Assistant,
+    /// A role this version of the library doesn't know yet, kept as it was named,
+    /// so it's sent back and written to transcripts under the same name.
+    Other(String),
+}
+
+/// How much effort reasoning models spend on reasoning before responding.
+#[derive(Deserialize, Serialize, Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[serde(rename_all = "lowercase")]
+#[non_exhaustive]
+pub enum ReasoningEffort {
+    Low,
+    Medium,
+    High,
+}
+
+/// The tier of service a request is processed with, trading latency for cost.
+#[derive(Deserialize, Serialize, Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[serde(rename_all = "lowercase")]
+#[non_exhaustive]
+pub enum ServiceTier {
+    /// The tier configured for the project, usually `default`.
+    Auto,
+    /// Standard pricing and latency.
+    Default,
+    /// Cheaper, but slower and sometimes unavailable, suited for batch workloads.
+    Flex,
+    /// Faster, at a higher price.
+    Priority,
+    /// A tier this version of the library doesn't know yet, which can't be requested.
+    #[serde(other)]
+    Unknown,
 }
 
-#[derive(Serialize, Builder, Debug, Clone)]
+#[derive(Deserialize, Serialize, Builder, Debug, Clone, PartialEq)]
 #[builder(pattern = "owned")]
 #[builder(name = "ChatCompletionBuilder")]
 #[builder(setter(strip_option, into))]
+#[builder(build_fn(private, name = "build_request"))]
 pub struct ChatCompletionRequest {
     /// ID of the model to use. Currently, only `gpt-3.5-turbo` and `gpt-3.5-turbo-0301` are supported.
     model: ModelID,
@@ -81,54 +540,238 @@ pub struct ChatCompletionRequest {
     /// What sampling temperature to use, between 0 and 2. Higher values like 0.8 will make the output more random, while lower values like 0.2 will make it more focused and deterministic.
     ///
     /// We generally recommend altering this or `top_p` but not both.
-    #[builder(default)]
+    #[builder(
+        setter(custom),
+        field(
+            type = "Option<Result<Temperature, OutOfRangeError>>",
+            build = "self.temperature.transpose().map_err(|error| error.to_string())?"
+        )
+    )]
     #[serde(skip_serializing_if = "Option::is_none")]
-    temperature: Option<f32>,
+    temperature: Option<Temperature>,
     /// An alternative to sampling with temperature, called nucleus sampling, where the model considers the results of the tokens with top_p probability mass. So 0.1 means only the tokens comprising the top 10% probability mass are considered.
     ///
     /// We generally recommend altering this or `temperature` but not both.
-    #[builder(default)]
+    #[builder(
+        setter(custom),
+        field(
+            type = "Option<Result<TopP, OutOfRangeError>>",
+            build = "self.top_p.transpose().map_err(|error| error.to_string())?"
+        )
+    )]
     #[serde(skip_serializing_if = "Option::is_none")]
-    top_p: Option<f32>,
+    top_p: Option<TopP>,
     /// How many chat completion choices to generate for each input message.
     #[builder(default)]
     #[serde(skip_serializing_if = "Option::is_none")]
     n: Option<u8>,
     /// If set, partial message deltas will be sent, like in ChatGPT. Tokens will be sent as data-only [server-sent events](https://developer.mozilla.org/en-US/docs/Web/API/Server-sent_events/Using_server-sent_events#Event_stream_format)
     /// as they become available, with the stream terminated by a `data: [DONE]` message.
-    #[builder(setter(skip), default = "Some(true)")] // skipped until properly implemented
+    ///
+    /// Set by [`ChatCompletionBuilder::create_stream`], rather than directly.
+    #[builder(setter(skip), default)]
     #[serde(skip_serializing_if = "Option::is_none")]
     stream: Option<bool>,
+    /// Options for streaming, which the API only accepts when streaming, see [`StreamOptions`].
+    #[builder(default)]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    stream_options: Option<StreamOptions>,
+    /// How long [`ChatCompletionBuilder::create_stream`] waits for the first content delta,
+    /// from when the stream is created, before ending with [`StreamError::FirstTokenTimeout`].
+    ///
+    /// The rest of the stream can take as long as it needs. Not sent to the API.
+    #[builder(default)]
+    #[serde(skip)]
+    first_token_timeout: Option<Duration>,
+    /// Whether [`ChatCompletion::create`] trims the content of the choices,
+    /// see [`ChatCompletionBuilder::trim_response`]. Not sent to the API.
+    #[builder(setter(custom), default)]
+    #[serde(skip)]
+    trim_response: bool,
     /// Up to 4 sequences where the API will stop generating further tokens.
     #[builder(default)]
-    #[serde(skip_serializing_if = "Vec::is_empty")]
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
     stop: Vec<String>,
     /// The maximum number of tokens allowed for the generated answer. By default, the number of tokens the model can return will be (4096 - prompt tokens).
-    #[builder(default)]
+    #[builder(
+        setter(custom),
+        field(
+            type = "Option<MaxTokens>",
+            build = "self.max_tokens.map(MaxTokens::resolved).transpose()?"
+        )
+    )]
     #[serde(skip_serializing_if = "Option::is_none")]
     max_tokens: Option<u64>,
+    /// An upper bound for the number of tokens that can be generated for a completion,
+    /// including visible output tokens and reasoning tokens.
+    ///
+    /// Supersedes `max_tokens`, which reasoning models (the o-series) reject.
+    #[builder(default)]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    max_completion_tokens: Option<u64>,
+    /// Constrains effort on reasoning for reasoning models (the o-series).
+    /// Reducing reasoning effort can result in faster responses and fewer tokens used on reasoning in a response.
+    ///
+    /// Only supported by reasoning models, other models will respond with an error if this is set.
+    #[builder(default)]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    reasoning_effort: Option<ReasoningEffort>,
     /// Number between -2.0 and 2.0. Positive values penalize new tokens based on whether they appear in the text so far, increasing the model's likelihood to talk about new topics.
     ///
     /// [See more information about frequency and presence penalties.](https://platform.openai.com/docs/api-reference/parameter-details)
-    #[builder(default)]
+    #[builder(
+        setter(custom),
+        field(
+            type = "Option<Result<Penalty, OutOfRangeError>>",
+            build = "self.presence_penalty.transpose().map_err(|error| error.to_string())?"
+        )
+    )]
     #[serde(skip_serializing_if = "Option::is_none")]
-    presence_penalty: Option<f32>,
+    presence_penalty: Option<Penalty>,
     /// Number between -2.0 and 2.0. Positive values penalize new tokens based on their existing frequency in the text so far, decreasing the model's likelihood to repeat the same line verbatim.
     ///
     /// [See more information about frequency and presence penalties.](https://platform.openai.com/docs/api-reference/parameter-details)
+    #[builder(
+        setter(custom),
+        field(
+            type = "Option<Result<Penalty, OutOfRangeError>>",
+            build = "self.frequency_penalty.transpose().map_err(|error| error.to_string())?"
+        )
+    )]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    frequency_penalty: Option<Penalty>,
+    /// Whether to return log probabilities of the output tokens or not.
+    /// If true, returns the log probabilities of each output token returned in the `content` of `message`.
+    #[builder(default)]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    logprobs: Option<bool>,
+    /// An integer between 0 and 20 specifying the number of most likely tokens to return at each token position,
+    /// each with an associated log probability. `logprobs` must be set to `true` if this parameter is used.
     #[builder(default)]
     #[serde(skip_serializing_if = "Option::is_none")]
-    frequency_penalty: Option<f32>,
+    top_logprobs: Option<u8>,
     /// Modify the likelihood of specified tokens appearing in the completion.
     ///
     /// Accepts a json object that maps tokens (specified by their token ID in the tokenizer) to an associated bias value from -100 to 100. Mathematically, the bias is added to the logits generated by the model prior to sampling. The exact effect will vary per model, but values between -1 and 1 should decrease or increase likelihood of selection; values like -100 or 100 should result in a ban or exclusive selection of the relevant token.
     #[builder(default)]
     #[serde(skip_serializing_if = "Option::is_none")]
     logit_bias: Option<HashMap<String, f32>>,
-    /// A unique identifier representing your end-user, which can help OpenAI to monitor and detect abuse. [Learn more](https://platform.openai.com/docs/guides/safety-best-practices/end-user-ids).
+    /// If specified, the system will make a best effort to sample deterministically,
+    /// such that repeated requests with the same `seed` and parameters should return the same result.
+    ///
+    /// Determinism is not guaranteed, see [`ChatCompletion::compare_backend`].
+    #[builder(default)]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    seed: Option<i64>,
+    /// A unique identifier representing your end-user, see [`EndUserId`].
+    #[builder(default)]
+    #[serde(default, skip_serializing_if = "EndUserId::is_empty")]
+    user: EndUserId,
+    /// Whether or not to store the output of this chat completion request,
+    /// so it can later be retrieved, or used in the model distillation or evals products.
+    #[builder(default)]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    store: Option<bool>,
+    /// Up to 16 developer-defined key-value pairs attached to a stored completion,
+    /// useful for filtering completions in the dashboard or correlating them with your own records.
+    #[builder(default)]
+    #[serde(default, skip_serializing_if = "HashMap::is_empty")]
+    metadata: HashMap<String, String>,
+    /// The types of output the model should generate, `text` by default.
+    /// Models like `gpt-4o-audio-preview` can also generate `audio`.
     #[builder(default)]
-    #[serde(skip_serializing_if = "String::is_empty")]
-    user: String,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    modalities: Vec<Modality>,
+    /// Parameters for audio output, required when `modalities` includes `audio`.
+    #[builder(default)]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    audio: Option<AudioOutput>,
+    /// The tier of service to process the request with, see [`ServiceTier`].
+    #[builder(default)]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    service_tier: Option<ServiceTier>,
+    /// Searches the web for sources before responding, which the message cites in its `annotations`.
+    /// Only supported by search models, like `gpt-4o-search-preview`.
+    #[builder(default)]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    web_search_options: Option<WebSearchOptions>,
+    /// Output the response is expected to mostly match, see [`Prediction`].
+    #[builder(default)]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    prediction: Option<Prediction>,
+}
+
+/// A finish reason, either as a plain string or as `{"type": "stop", "stop": "<|im_end|>"}`.
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum FinishReason {
+    Reason(String),
+    Details {
+        #[serde(rename = "type")]
+        reason: String,
+    },
+}
+
+impl From<FinishReason> for String {
+    fn from(finish_reason: FinishReason) -> Self {
+        match finish_reason {
+            FinishReason::Reason(reason) | FinishReason::Details { reason } => reason,
+        }
+    }
+}
+
+/// A choice as returned by the API, which can have both `finish_reason` and `finish_details`.
+#[derive(Deserialize)]
+struct RawChatCompletionChoice {
+    #[serde(default)]
+    index: u64,
+    message: ChatCompletionMessage,
+    finish_reason: Option<FinishReason>,
+    finish_details: Option<FinishReason>,
+    logprobs: Option<ChatCompletionLogprobs>,
+    content_filter_results: Option<ContentFilterResults>,
+}
+
+impl TryFrom<RawChatCompletionChoice> for ChatCompletionChoice {
+    type Error = &'static str;
+
+    fn try_from(raw: RawChatCompletionChoice) -> Result<Self, Self::Error> {
+        let finish_reason = raw
+            .finish_reason
+            .or(raw.finish_details)
+            .ok_or("missing field `finish_reason`")?;
+
+        Ok(ChatCompletionChoice {
+            index: raw.index,
+            message: raw.message,
+            finish_reason: finish_reason.into(),
+            logprobs: raw.logprobs,
+            content_filter_results: raw.content_filter_results,
+        })
+    }
+}
+
+/// A choice delta as returned by the API, like [`RawChatCompletionChoice`].
+#[derive(Deserialize)]
+struct RawChatCompletionChoiceDelta {
+    index: u64,
+    delta: Delta,
+    finish_reason: Option<FinishReason>,
+    finish_details: Option<FinishReason>,
+    logprobs: Option<ChatCompletionLogprobs>,
+    content_filter_results: Option<ContentFilterResults>,
+}
+
+impl From<RawChatCompletionChoiceDelta> for ChatCompletionChoiceDelta {
+    fn from(raw: RawChatCompletionChoiceDelta) -> Self {
+        ChatCompletionChoiceDelta {
+            index: raw.index,
+            delta: raw.delta,
+            finish_reason: raw.finish_reason.or(raw.finish_details).map(String::from),
+            logprobs: raw.logprobs,
+            content_filter_results: raw.content_filter_results,
+        }
+    }
 }
 
 impl ChatCompletion {
@@ -145,60 +788,3590 @@ impl ChatCompletion {
         client: &Client,
         request: &ChatCompletionRequest,
     ) -> ApiResponseOrError<Self> {
-        openai_post(client, "chat/completions", request).await
+        let mut response: ApiResponseOrError<Self> =
+            openai_post(client, "chat/completions", request).await;
+
+        if let (true, Ok(Ok(completion))) = (request.trim_response, &mut response) {
+            completion.trim_response();
+        }
+
+        response
     }
-}
 
-impl ChatCompletionBuilder {
-    pub async fn create(self, client: &Client) -> ApiResponseOrError<ChatCompletion> {
-        ChatCompletion::create(client, &self.build().unwrap()).await
+    /// Strips leading and trailing whitespace from the content of every choice,
+    /// like the line breaks some models start their replies with.
+    ///
+    /// The content no longer matches the tokens the model generated exactly,
+    /// e.g. when comparing it token by token or counting its tokens.
+    pub fn trim_response(&mut self) {
+        for choice in &mut self.choices {
+            let Some(content) = &mut choice.message.content else {
+                continue;
+            };
+
+            if content.trim().len() != content.len() {
+                *content = content.trim().to_string();
+            }
+        }
+    }
+
+    /// Starts a follow-up to `request`, which this completion was generated for,
+    /// with the first choice's message and then `user_message` appended to the conversation.
+    ///
+    /// The model and every other parameter of `request` are kept, and can be changed on the returned builder.
+    pub fn continue_request(
+        self,
+        request: ChatCompletionRequest,
+        user_message: ChatCompletionMessage,
+    ) -> ChatCompletionBuilder {
+        let mut builder = ChatCompletionBuilder::from(request);
+        let messages = builder.messages.get_or_insert_with(Vec::new);
+
+        if let Some(choice) = self.choices.into_iter().next() {
+            messages.push(choice.message);
+        }
+
+        messages.push(user_message);
+        builder
+    }
+
+    /// The first of the generated choices, `None` if there are none.
+    pub fn first_choice(&self) -> Option<&ChatCompletionChoice> {
+        self.choices.first()
+    }
+
+    /// The content of the first choice's message, `None` if there are no choices or the message has no content.
+    pub fn content(&self) -> Option<&str> {
+        self.first_choice()
+            .and_then(|choice| choice.message.content.as_deref())
     }
 
-    pub fn create_stream(self, client: &Client) -> impl Stream<Item = ChatCompletionEvent> + Unpin {
-        let request = client
-            .request(Method::POST, BASE_URL.to_owned() + "chat/completions")
-            .json(&self.build().unwrap());
+    /// Reassembles the completion a stream was made of from its events, `None` if there are none.
+    ///
+    /// The deltas of every choice are applied in order, keeping choices apart by their index.
+    /// The usage is only known if the request asked for it, see [`StreamOptions::include_usage`].
+    pub fn from_events<'a>(
+        events: impl IntoIterator<Item = &'a ChatCompletionEvent>,
+    ) -> Option<Self> {
+        let mut events = events.into_iter().peekable();
+        let first = *events.peek()?;
+        let mut choices = BTreeMap::new();
+        let mut usage = None;
+
+        for event in events {
+            for choice in &event.choices {
+                let (message, finish_reason) = choices
+                    .entry(choice.index)
+                    .or_insert_with(|| (ChatCompletionMessage::assistant(""), String::new()));
 
-        let events = EventSource::new(authorization!(request)).unwrap();
+                match &choice.delta {
+                    Delta::Role { role } => message.role = role.clone(),
+                    delta => delta.apply_to(message.content.get_or_insert_with(String::new)),
+                }
 
-        events.filter_map(|e| match e {
-            Ok(Event::Message(msg)) if msg.data != "[DONE]" => {
-                let x: ChatCompletionEvent = serde_json::from_str(&msg.data).unwrap();
-                future::ready(Some(x))
+                if let Some(reason) = &choice.finish_reason {
+                    *finish_reason = reason.clone();
+                }
             }
-            // TODO: don't swallow all the errors here
-            _ => future::ready(None),
+
+            usage = event.usage.or(usage);
+        }
+
+        Some(ChatCompletion {
+            id: first.id.clone(),
+            object: "chat.completion".to_string(),
+            created: first.created,
+            model: first.model.clone(),
+            choices: choices
+                .into_iter()
+                .map(|(index, (message, finish_reason))| {
+                    ChatCompletionChoice::new(index, message, finish_reason)
+                })
+                .collect(),
+            usage,
+            system_fingerprint: first.system_fingerprint.clone(),
+            service_tier: first.service_tier,
         })
     }
-}
 
-#[cfg(test)]
-mod tests {
-    use super::*;
-    use dotenvy::dotenv;
+    /// The choice the model was most confident in, by [`ChatCompletionChoice::total_logprob`],
+    /// e.g. to pick the best of several choices requested with `n`.
+    ///
+    /// Choices without log probabilities are skipped, so this is `None` if `logprobs` wasn't requested.
+    /// Of choices that are equally likely, the first one is returned.
+    ///
+    /// Longer choices tend to be less likely, since every token lowers the total.
+    pub fn best_by_logprob(&self) -> Option<&ChatCompletionChoice> {
+        self.choices
+            .iter()
+            .filter_map(|choice| Some((choice, choice.total_logprob()?)))
+            .reduce(|best, choice| if choice.1 > best.1 { choice } else { best })
+            .map(|(choice, _)| choice)
+    }
 
-    #[tokio::test]
-    async fn chat() {
-        dotenv().ok();
+    /// Compares the backends two completions ran on,
+    /// telling whether their results are comparable when both were requested with the same `seed`.
+    pub fn compare_backend(&self, other: &ChatCompletion) -> Reproducibility {
+        match (&self.system_fingerprint, &other.system_fingerprint) {
+            (Some(fingerprint), Some(other)) if fingerprint == other => {
+                Reproducibility::SameBackend
+            }
+            (Some(_), Some(_)) => Reproducibility::DifferentBackend,
+            _ => Reproducibility::Unknown,
+        }
+    }
 
-        let chat_completion = ChatCompletion::builder(
-            ModelID::Gpt3_5Turbo,
-            [ChatCompletionMessage {
-                role: ChatCompletionMessageRole::User,
-                content: "Hello!".to_string(),
-                name: None,
-            }],
-        )
-        .temperature(0.0)
-        .create(&Client::new())
+    /// The share of prompt tokens that were read from the prompt cache,
+    /// see [`Usage::cached_token_ratio`].
+    pub fn cached_token_ratio(&self) -> Option<f32> {
+        self.usage?.cached_token_ratio()
+    }
+
+    /// Retrieves a chat completion that was created with `store` set to `true`.
+    pub async fn retrieve(client: &Client, id: &str) -> ApiResponseOrError<Self> {
+        openai_get(client, &format!("chat/completions/{id}")).await
+    }
+
+    /// Lists chat completions that were created with `store` set to `true`.
+    pub async fn list(
+        client: &Client,
+        params: &ChatCompletionListParams,
+    ) -> ApiResponseOrError<Page<Self>> {
+        openai_request(client, Method::GET, "chat/completions", |request| {
+            request.query(&params.query())
+        })
         .await
-        .unwrap()
-        .unwrap();
+    }
 
-        assert_eq!(
-            chat_completion.choices.first().unwrap().message.content,
-            "\n\nHello there! How can I assist you today?"
-        );
+    /// Lists chat completions that were created with `store` set to `true`,
+    /// fetching further pages as the stream is consumed.
+    ///
+    /// `params.after` is only used for the first page.
+    pub fn list_all(
+        client: &Client,
+        params: ChatCompletionListParams,
+    ) -> impl Stream<Item = ApiResponseOrError<Self>> + Send {
+        let client = client.clone();
+
+        paginate(move |after| {
+            let client = client.clone();
+            let params = ChatCompletionListParams {
+                after: after.or_else(|| params.after.clone()),
+                ..params.clone()
+            };
+
+            async move { Self::list(&client, &params).await }
+        })
+    }
+
+    /// Deletes a chat completion that was created with `store` set to `true`.
+    pub async fn delete(client: &Client, id: &str) -> ApiResponseOrError<DeletedObject> {
+        openai_delete(client, &format!("chat/completions/{id}")).await
+    }
+
+    /// Lists the messages a stored chat completion was requested with, up to 100 per page,
+    /// starting after the message with the ID `after`.
+    pub async fn list_messages(
+        client: &Client,
+        id: &str,
+        after: Option<&str>,
+    ) -> ApiResponseOrError<Page<ChatCompletionMessage>> {
+        let route = format!("chat/completions/{id}/messages");
+
+        openai_request(client, Method::GET, &route, |request| {
+            let request = request.query(&[("limit", "100")]);
+
+            match after {
+                Some(after) => request.query(&[("after", after)]),
+                None => request,
+            }
+        })
+        .await
+    }
+
+    /// Lists the messages a stored chat completion was requested with,
+    /// fetching further pages as the stream is consumed.
+    pub fn list_all_messages(
+        client: &Client,
+        id: &str,
+    ) -> impl Stream<Item = ApiResponseOrError<ChatCompletionMessage>> + Send {
+        let client = client.clone();
+        let id = id.to_string();
+
+        paginate(move |after| {
+            let client = client.clone();
+            let id = id.clone();
+
+            async move { Self::list_messages(&client, &id, after.as_deref()).await }
+        })
+    }
+
+    /// Writes the stored chat completions matching `params` to `writer` as a dataset for fine-tuning or evals,
+    /// in JSON Lines: one `{"messages": [...]}` per completion, with the messages it was requested with
+    /// followed by the reply of its first choice. Completions without choices are skipped.
+    ///
+    /// Completions are fetched page by page while the dataset is written, so it's never held in memory as a whole.
+    /// Returns how many completions were written.
+    pub async fn export_training_data<W>(
+        client: &Client,
+        params: ChatCompletionListParams,
+        mut writer: W,
+    ) -> Result<u64, ExportError>
+    where
+        W: AsyncWrite + Unpin,
+    {
+        #[derive(Serialize)]
+        struct TrainingExample {
+            messages: Vec<ChatCompletionMessage>,
+        }
+
+        let mut completions = Box::pin(Self::list_all(client, params));
+        let mut written = 0;
+
+        while let Some(completion) = completions.next().await {
+            let completion = completion??;
+            let Some(reply) = completion.choices.into_iter().next() else {
+                continue;
+            };
+            let mut messages = Vec::new();
+            let mut requested = Box::pin(Self::list_all_messages(client, &completion.id));
+
+            while let Some(message) = requested.next().await {
+                messages.push(message??);
+            }
+
+            messages.push(reply.message);
+
+            let mut line = serde_json::to_vec(&TrainingExample { messages })
+                .expect("messages should be serializable");
+
+            line.push(b'\n');
+            writer.write_all(&line).await.map_err(ExportError::Write)?;
+            written += 1;
+        }
+
+        writer.flush().await.map_err(ExportError::Write)?;
+
+        Ok(written)
+    }
+}
+
+impl ChatCompletionChoice {
+    /// A choice without log probabilities or content filter results, e.g. for reassembling a streamed completion.
+    pub fn new(
+        index: u64,
+        message: ChatCompletionMessage,
+        finish_reason: impl Into<String>,
+    ) -> Self {
+        ChatCompletionChoice {
+            index,
+            message,
+            finish_reason: finish_reason.into(),
+            logprobs: None,
+            content_filter_results: None,
+        }
+    }
+
+    /// The log probability of the whole content, the sum of the log probabilities of its tokens,
+    /// `None` if `logprobs` wasn't requested.
+    pub fn total_logprob(&self) -> Option<f64> {
+        let tokens = self.logprobs.as_ref()?.content.as_ref()?;
+
+        Some(tokens.iter().map(|token| token.logprob).sum())
+    }
+}
+
+impl ContentFilterResults {
+    /// The names of the categories that caused the content to be filtered, e.g. `["violence"]`.
+    pub fn filtered(&self) -> Vec<&'static str> {
+        let severities = [
+            ("hate", &self.hate),
+            ("self_harm", &self.self_harm),
+            ("sexual", &self.sexual),
+            ("violence", &self.violence),
+        ]
+        .into_iter()
+        .filter(|(_, result)| result.is_some_and(|result| result.filtered));
+        let detections = [
+            ("profanity", &self.profanity),
+            ("jailbreak", &self.jailbreak),
+            ("protected_material_text", &self.protected_material_text),
+            ("protected_material_code", &self.protected_material_code),
+        ]
+        .into_iter()
+        .filter(|(_, result)| result.is_some_and(|result| result.filtered));
+
+        severities
+            .map(|(category, _)| category)
+            .chain(detections.map(|(category, _)| category))
+            .collect()
+    }
+}
+
+impl Delta {
+    /// Appends the delta's content to `buffer`, does nothing for other deltas.
+    pub fn apply_to(&self, buffer: &mut String) {
+        if let Delta::Content { content } = self {
+            buffer.push_str(content);
+        }
+    }
+}
+
+impl ChatCompletionEvent {
+    /// Applies the delta of every choice to the buffer for that choice's index, see [`Delta::apply_to`].
+    ///
+    /// A buffer is created for a choice when it first appears, even if its delta has no content yet.
+    pub fn apply_to(&self, buffers: &mut HashMap<u64, String>) {
+        for choice in &self.choices {
+            choice
+                .delta
+                .apply_to(buffers.entry(choice.index).or_default());
+        }
+    }
+
+    fn has_content(&self) -> bool {
+        self.choices
+            .iter()
+            .any(|choice| matches!(choice.delta, Delta::Content { .. }))
+    }
+}
+
+impl ChatCompletionListParams {
+    fn query(&self) -> Vec<(String, String)> {
+        let mut query = Vec::new();
+
+        if let Some(after) = &self.after {
+            query.push(("after".to_string(), after.clone()));
+        }
+
+        if let Some(limit) = self.limit {
+            query.push(("limit".to_string(), limit.to_string()));
+        }
+
+        for (key, value) in &self.metadata {
+            query.push((format!("metadata[{key}]"), value.clone()));
+        }
+
+        if let Some(model) = &self.model {
+            query.push(("model".to_string(), model.to_string()));
+        }
+
+        if let Some(order) = self.order {
+            let order = match order {
+                Order::Asc => "asc",
+                Order::Desc => "desc",
+            };
+
+            query.push(("order".to_string(), order.to_string()));
+        }
+
+        query
+    }
+}
+
+impl ChatCompletionRequest {
+    /// Hashes the request's JSON form with object keys taken in sorted order,
+    /// so requests that would send the same body share a hash.
+    ///
+    /// The options that aren't sent but change what [`ChatCompletion::create`] returns,
+    /// `first_token_timeout` and `trim_response`, are hashed too.
+    ///
+    /// The hash is only stable within a single build of a program,
+    /// it shouldn't be persisted.
+    pub fn request_hash(&self) -> u64 {
+        let mut hasher = DefaultHasher::new();
+
+        hash_canonical(&serde_json::to_value(self).unwrap(), &mut hasher);
+        self.first_token_timeout.hash(&mut hasher);
+        self.trim_response.hash(&mut hasher);
+        hasher.finish()
+    }
+
+    /// Checks the request for values the API would reject without sending it,
+    /// e.g. to lint stored request templates.
+    ///
+    /// Checks that there are messages, that numbers are within their ranges,
+    /// (besides the ones [`Temperature`], [`TopP`] and [`Penalty`] already guarantee),
+    /// that there are at most 4 `stop` sequences and 16 `metadata` pairs,
+    /// and that fields which exclude or require each other are set accordingly.
+    /// Every violation is returned, not just the first.
+    pub fn validate(&self) -> Result<(), ValidationError> {
+        let mut violations = Vec::new();
+        let mut violation = |field, message: &str| {
+            violations.push(Violation {
+                field,
+                message: message.to_string(),
+            })
+        };
+
+        if self.messages.is_empty() {
+            violation("messages", "there should be at least one message");
+        }
+
+        if self.messages.iter().any(|message| {
+            message
+                .name
+                .as_deref()
+                .is_some_and(|name| !is_valid_name(name))
+        }) {
+            violation(
+                "messages",
+                "names should only contain letters, digits, underscores and hyphens",
+            );
+        }
+
+        if self.n == Some(0) {
+            violation("n", "should be at least 1");
+        }
+
+        if self.stop.len() > 4 {
+            violation("stop", "there should be at most 4 sequences");
+        }
+
+        if let Some(top_logprobs) = self.top_logprobs {
+            if top_logprobs > 20 {
+                violation("top_logprobs", "should be between 0 and 20");
+            }
+
+            if self.logprobs != Some(true) {
+                violation("top_logprobs", "requires `logprobs` to be `true`");
+            }
+        }
+
+        if let Some(logit_bias) = &self.logit_bias {
+            if logit_bias
+                .values()
+                .any(|bias| !(-100.0..=100.0).contains(bias))
+            {
+                violation("logit_bias", "biases should be between -100 and 100");
+            }
+        }
+
+        if self.metadata.len() > 16 {
+            violation("metadata", "there should be at most 16 pairs");
+        }
+
+        match (self.modalities.contains(&Modality::Audio), &self.audio) {
+            (true, None) => violation("audio", "is required when `modalities` includes `audio`"),
+            (false, Some(_)) => violation("audio", "requires `modalities` to include `audio`"),
+            _ => {}
+        }
+
+        if violations.is_empty() {
+            Ok(())
+        } else {
+            Err(ValidationError { violations })
+        }
+    }
+
+    /// Streams the completion, whether or not the request has `stream` set.
+    pub(crate) fn create_stream_events(&self, client: &Client) -> ChatCompletionStream {
+        ChatCompletionRequest {
+            stream: Some(true),
+            ..self.clone()
+        }
+        .create_stream(client, None)
+    }
+
+    /// Sends the request, which should have `stream` set, streaming back its events.
+    ///
+    /// Every message received is also sent to `tee` as a raw server-sent event, if given.
+    fn create_stream(
+        &self,
+        client: &Client,
+        tee: Option<UnboundedSender<String>>,
+    ) -> ChatCompletionStream {
+        // Cleared once the first content delta arrives
+        let first_token_deadline = self.first_token_timeout.map(Delay::new);
+        let request = client.request(Method::POST, "chat/completions").json(self);
+        let connection = Arc::new(OnceLock::new());
+        let events = Box::pin(event_source(client.finish(request), connection.clone()));
+        let events = client.register_stream(Box::pin(stream::unfold(
+            Some((events, first_token_deadline, tee, connection.clone())),
+            |state| async move {
+                let (mut events, mut first_token_deadline, tee, connection) = state?;
+                let next = match &mut first_token_deadline {
+                    Some(deadline) => match future::select(events.next(), deadline).await {
+                        Either::Left((next, _)) => next?,
+                        Either::Right(_) => {
+                            return Some((Err(StreamError::FirstTokenTimeout), None));
+                        }
+                    },
+                    None => events.next().await?,
+                };
+
+                if let (Some(tee), Ok(message)) = (&tee, &next) {
+                    let raw: String = message
+                        .data
+                        .lines()
+                        .map(|line| format!("data: {line}\n"))
+                        .collect();
+
+                    // The log is only written as long as anyone is waiting for it
+                    tee.send(raw + "\n").ok();
+                }
+
+                match next {
+                    Ok(message) if message.data == "[DONE]" => None,
+                    Ok(message) => match decode_event(message.data) {
+                        Ok(event) => {
+                            if event.has_content() {
+                                first_token_deadline = None;
+                            }
+
+                            Some((
+                                Ok(event),
+                                Some((events, first_token_deadline, tee, connection)),
+                            ))
+                        }
+                        // Nothing follows an error from the API
+                        Err(StreamError::Api(mut error)) => {
+                            error.request_id = connection
+                                .get()
+                                .and_then(|connection| connection.headers.get(layer::REQUEST_ID))
+                                .and_then(|request_id| request_id.to_str().ok())
+                                .map(String::from);
+
+                            Some((Err(StreamError::Api(error)), None))
+                        }
+                        Err(error) => Some((
+                            Err(error),
+                            Some((events, first_token_deadline, tee, connection)),
+                        )),
+                    },
+                    Err(error) => Some((Err(error.into()), None)),
+                }
+            },
+        )));
+
+        ChatCompletionStream {
+            events: Box::pin(events),
+            connection,
+        }
+    }
+}
+
+/// Hashes a JSON value with the keys of each object in sorted order,
+/// which `serde_json` only guarantees without its `preserve_order` feature.
+fn hash_canonical(value: &serde_json::Value, hasher: &mut impl Hasher) {
+    match value {
+        serde_json::Value::Object(object) => {
+            let mut entries: Vec<_> = object.iter().collect();
+            entries.sort_unstable_by_key(|(key, _)| *key);
+
+            hasher.write_u8(0);
+            hasher.write_usize(entries.len());
+            for (key, value) in entries {
+                key.hash(hasher);
+                hash_canonical(value, hasher);
+            }
+        }
+        serde_json::Value::Array(values) => {
+            hasher.write_u8(1);
+            hasher.write_usize(values.len());
+            for value in values {
+                hash_canonical(value, hasher);
+            }
+        }
+        value => {
+            hasher.write_u8(2);
+            value.to_string().hash(hasher);
+        }
+    }
+}
+
+impl ChatCompletionStream {
+    /// A stream of just the error its request was rejected with, without sending it.
+    fn rejected(error: OpenAiError) -> Self {
+        ChatCompletionStream {
+            events: Box::pin(stream::iter([Err(StreamError::Api(error))])),
+            connection: Arc::default(),
+        }
+    }
+
+    /// The headers of the response, e.g. `x-ratelimit-remaining-tokens` to throttle requests by.
+    ///
+    /// `None` until the response has arrived,
+    /// which it has once the stream yielded its first event or error (unless the request couldn't be sent at all).
+    pub fn headers(&self) -> Option<&HeaderMap> {
+        self.connection.get().map(|connection| &connection.headers)
+    }
+
+    /// When the response arrived, which is when the stream connected,
+    /// e.g. to tell the time to connect apart from the time to the first token.
+    ///
+    /// `None` until the response has arrived, see [`headers`](Self::headers).
+    pub fn connected_at(&self) -> Option<Instant> {
+        self.connection
+            .get()
+            .map(|connection| connection.connected_at)
+    }
+
+    /// How long the response took to arrive after the request was sent,
+    /// `None` until it has, see [`connected_at`](Self::connected_at).
+    pub fn time_to_connect(&self) -> Option<Duration> {
+        self.connection
+            .get()
+            .map(|connection| connection.connected_at - connection.sent_at)
+    }
+
+    /// The `x-request-id` header of the response, which OpenAI's support asks for.
+    ///
+    /// `None` until the response has arrived, see [`headers`](Self::headers),
+    /// or if it doesn't have one, e.g. because a proxy stripped it.
+    pub fn request_id(&self) -> Option<&str> {
+        self.headers()?.get(layer::REQUEST_ID)?.to_str().ok()
+    }
+}
+
+impl Stream for ChatCompletionStream {
+    type Item = Result<ChatCompletionEvent, StreamError>;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        self.events.as_mut().poll_next(cx)
+    }
+}
+
+/// Sends a request for server-sent events, streaming back their messages.
+///
+/// Unlike [`reqwest_eventsource::EventSource`], it never reconnects,
+/// and it stores the headers of the response in `connection` as soon as it arrives, whatever its status.
+/// The stream ends with [`EventSourceError::StreamEnded`] if the connection closes.
+fn event_source(
+    request: RequestBuilder,
+    connection: Arc<OnceLock<Connection>>,
+) -> impl Stream<Item = Result<MessageEvent, EventSourceError>> + Send {
+    stream::once(async move {
+        let sent_at = Instant::now();
+        let response = request.send().await.map_err(EventSourceError::Transport)?;
+
+        connection
+            .set(Connection {
+                headers: response.headers().clone(),
+                sent_at,
+                connected_at: Instant::now(),
+            })
+            .ok();
+        check_event_stream(&response)?;
+
+        Ok::<_, EventSourceError>(
+            response
+                .bytes_stream()
+                .eventsource()
+                .map(|event| event.map_err(EventSourceError::from))
+                .chain(stream::once(future::ready(Err(
+                    EventSourceError::StreamEnded,
+                )))),
+        )
+    })
+    .try_flatten()
+}
+
+/// Decodes the data of a server-sent event,
+/// which is either an event or, if something went wrong mid-stream, an error object like `{"error": {...}}`.
+fn decode_event(data: String) -> Result<ChatCompletionEvent, StreamError> {
+    #[derive(Deserialize)]
+    #[serde(untagged)]
+    enum EventData {
+        Event(ChatCompletionEvent),
+        Error { error: OpenAiError },
+    }
+
+    match json::decode_as::<ChatCompletionEvent, _>(data.into_bytes())? {
+        EventData::Event(event) => Ok(event),
+        EventData::Error { error } => Err(StreamError::Api(error)),
+    }
+}
+
+/// Checks a response is a successful stream of server-sent events.
+fn check_event_stream(response: &Response) -> Result<(), EventSourceError> {
+    if response.status() != StatusCode::OK {
+        return Err(EventSourceError::InvalidStatusCode(response.status()));
+    }
+
+    let content_type = response
+        .headers()
+        .get(CONTENT_TYPE)
+        .cloned()
+        .unwrap_or_else(|| HeaderValue::from_static(""));
+    let is_event_stream = content_type.to_str().is_ok_and(|content_type| {
+        content_type
+            .split(';')
+            .next()
+            .is_some_and(|mime| mime.trim().eq_ignore_ascii_case("text/event-stream"))
+    });
+
+    if is_event_stream {
+        Ok(())
+    } else {
+        Err(EventSourceError::InvalidContentType(content_type))
+    }
+}
+
+impl From<ChatCompletionRequest> for ChatCompletionBuilder {
+    /// Starts a builder with every parameter of the request, e.g. to send a variation of it.
+    fn from(request: ChatCompletionRequest) -> Self {
+        ChatCompletionBuilder {
+            model: Some(request.model),
+            messages: Some(request.messages),
+            temperature: request.temperature.map(Ok),
+            top_p: request.top_p.map(Ok),
+            n: Some(request.n),
+            // Not settable, streaming requests set it when they're sent
+            stream: PhantomData,
+            stream_options: Some(request.stream_options),
+            first_token_timeout: Some(request.first_token_timeout),
+            trim_response: Some(request.trim_response),
+            stop: Some(request.stop),
+            max_tokens: request.max_tokens.map(MaxTokens::Fixed),
+            max_completion_tokens: Some(request.max_completion_tokens),
+            reasoning_effort: Some(request.reasoning_effort),
+            presence_penalty: request.presence_penalty.map(Ok),
+            frequency_penalty: request.frequency_penalty.map(Ok),
+            logprobs: Some(request.logprobs),
+            top_logprobs: Some(request.top_logprobs),
+            logit_bias: Some(request.logit_bias),
+            seed: Some(request.seed),
+            user: Some(request.user),
+            store: Some(request.store),
+            metadata: Some(request.metadata),
+            modalities: Some(request.modalities),
+            audio: Some(request.audio),
+            service_tier: Some(request.service_tier),
+            web_search_options: Some(request.web_search_options),
+            prediction: Some(request.prediction),
+        }
+    }
+}
+
+/// How [`ChatCompletionBuilder`] sets `max_tokens`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum MaxTokens {
+    Fixed(u64),
+    /// See [`ChatCompletionBuilder::max_tokens_auto`].
+    #[cfg(feature = "tokenizer")]
+    Auto,
+}
+
+impl MaxTokens {
+    fn resolved(self) -> Result<u64, String> {
+        match self {
+            MaxTokens::Fixed(max_tokens) => Ok(max_tokens),
+            #[cfg(feature = "tokenizer")]
+            MaxTokens::Auto => Err("`max_tokens` should be resolved before building".to_string()),
+        }
+    }
+}
+
+/// Tokens [`ChatCompletionBuilder::max_tokens_auto`] leaves unused,
+/// in case the prompt takes up more tokens than estimated.
+#[cfg(feature = "tokenizer")]
+const MAX_TOKENS_AUTO_MARGIN: usize = 64;
+
+/// What [`ChatCompletionBuilder::create_with_continuations`] asks a model whose reply was cut off.
+const CONTINUATION_PROMPT: &str =
+    "Continue exactly where you left off, without repeating anything.";
+
+impl ChatCompletionBuilder {
+    /// Requests that fail to build, e.g. with an out of range `temperature`,
+    /// fail with an `invalid_request_error` without being sent.
+    pub async fn create(self, client: &Client) -> ApiResponseOrError<ChatCompletion> {
+        match self.build() {
+            Ok(request) => ChatCompletion::create(client, &request).await,
+            Err(error) => Ok(Err(invalid_request(error))),
+        }
+    }
+
+    /// Like [`create`](Self::create), but if the reply is cut off by `max_tokens` (its `finish_reason` is `length`),
+    /// requests up to `max_continuations` continuations of it and stitches them into the first choice's message.
+    ///
+    /// A continuation repeats the conversation with the reply so far appended as an assistant message,
+    /// followed by a user message asking the model to carry on. Only the first choice is continued.
+    /// Continuing stops early once a reply isn't cut off, or if a continuation adds nothing,
+    /// so a model that keeps replying with nothing doesn't use up every request.
+    ///
+    /// The returned completion has the last continuation's `finish_reason` and the `usage` of all requests combined.
+    /// If a continuation fails, its error is returned and the reply so far is lost.
+    pub async fn create_with_continuations(
+        self,
+        client: &Client,
+        max_continuations: u32,
+    ) -> ApiResponseOrError<ChatCompletion> {
+        let request = match self.build() {
+            Ok(request) => request,
+            Err(error) => return Ok(Err(invalid_request(error))),
+        };
+        let trim_response = request.trim_response;
+        // Trimming the parts would also trim the whitespace between them
+        let request = ChatCompletionRequest {
+            trim_response: false,
+            ..request
+        };
+        let mut completion = match ChatCompletion::create(client, &request).await? {
+            Ok(completion) => completion,
+            Err(error) => return Ok(Err(error)),
+        };
+        let conversation_length = request.messages.len();
+        let mut continuation = ChatCompletionRequest { n: None, ..request };
+
+        for _ in 0..max_continuations {
+            let Some(choice) = completion.choices.first_mut() else {
+                break;
+            };
+
+            if choice.finish_reason != "length" {
+                break;
+            }
+
+            continuation.messages.truncate(conversation_length);
+            continuation.messages.push(ChatCompletionMessage::assistant(
+                choice.message.content.clone().unwrap_or_default(),
+            ));
+            continuation
+                .messages
+                .push(ChatCompletionMessage::user(CONTINUATION_PROMPT));
+
+            let next = match ChatCompletion::create(client, &continuation).await? {
+                Ok(next) => next,
+                Err(error) => return Ok(Err(error)),
+            };
+            let Some(next_choice) = next.choices.into_iter().next() else {
+                break;
+            };
+
+            let next_content = next_choice.message.content.unwrap_or_default();
+
+            choice.finish_reason = next_choice.finish_reason;
+            choice
+                .message
+                .content
+                .get_or_insert_with(String::new)
+                .push_str(&next_content);
+
+            match (&mut completion.usage, next.usage) {
+                (Some(usage), Some(next_usage)) => *usage += next_usage,
+                (usage, next_usage) => *usage = usage.or(next_usage),
+            }
+
+            if next_content.is_empty() {
+                break;
+            }
+        }
+
+        if trim_response {
+            completion.trim_response();
+        }
+
+        Ok(Ok(completion))
+    }
+
+    /// Returns the request [`create`](Self::create) would send, without sending it.
+    pub fn dry_run(self, client: &Client) -> ApiResponseOrError<DryRun> {
+        match self.build() {
+            Ok(request) => Ok(Ok(openai_dry_run(client, "chat/completions", &request)?)),
+            Err(error) => Ok(Err(invalid_request(error))),
+        }
+    }
+
+    /// Streams the chat completion as it is generated.
+    ///
+    /// The stream ends once the API signals the completion is done,
+    /// or after yielding the first error.
+    ///
+    /// The stream is `Send` and doesn't borrow the client,
+    /// so it can be moved into a task spawned on a multi-threaded runtime.
+    ///
+    /// Requests that fail to build yield a single [`StreamError::Api`] with an `invalid_request_error`.
+    pub fn create_stream(self, client: &Client) -> ChatCompletionStream {
+        match self.build_stream() {
+            Ok(request) => request.create_stream(client, None),
+            Err(error) => ChatCompletionStream::rejected(error),
+        }
+    }
+
+    /// Like [`ChatCompletionBuilder::create_stream`],
+    /// but also writes every event to `log` in its raw server-sent event form, e.g. for audit logging.
+    ///
+    /// Events are written by a spawned task, so a slow log doesn't hold back the stream.
+    /// The task finishes once the stream has ended (or was dropped) and everything has been written,
+    /// returning the log after flushing it.
+    ///
+    /// Requires the `tokio-runtime` feature, and must be called within a Tokio runtime.
+    #[cfg(feature = "tokio-runtime")]
+    pub fn create_stream_tee<W>(
+        self,
+        client: &Client,
+        mut log: W,
+    ) -> (ChatCompletionStream, JoinHandle<std::io::Result<W>>)
+    where
+        W: AsyncWrite + Unpin + Send + 'static,
+    {
+        let (sender, mut receiver) = mpsc::unbounded_channel::<String>();
+        let writer = tokio::spawn(async move {
+            while let Some(event) = receiver.recv().await {
+                log.write_all(event.as_bytes()).await?;
+            }
+
+            log.flush().await?;
+
+            Ok(log)
+        });
+
+        let stream = match self.build_stream() {
+            Ok(request) => request.create_stream(client, Some(sender)),
+            Err(error) => ChatCompletionStream::rejected(error),
+        };
+
+        (stream, writer)
+    }
+
+    /// Like [`ChatCompletionBuilder::create_stream`],
+    /// but restarts the completion up to `max_restarts` times if the connection drops before it's done.
+    ///
+    /// The API can't resume a stream, so a restart requests a whole new completion,
+    /// and the events received so far should be discarded.
+    /// Restarts are signalled by [`RestartingStreamItem::Restarted`] for that reason.
+    /// Tokens used by abandoned attempts are billed as usual.
+    pub fn create_restarting_stream(
+        self,
+        client: &Client,
+        max_restarts: u32,
+    ) -> impl Stream<Item = Result<RestartingStreamItem, StreamError>> + Send + Unpin {
+        let request = match self.build_stream() {
+            Ok(request) => request,
+            Err(error) => return Either::Right(stream::iter([Err(StreamError::Api(error))])),
+        };
+        let client = client.clone();
+        let events = request.create_stream(&client, None);
+
+        Either::Left(Box::pin(stream::unfold(
+            Some((client, request, events, 0)),
+            move |state| async move {
+                let (client, request, mut events, restarts) = state?;
+
+                match events.next().await? {
+                    Ok(event) => Some((
+                        Ok(RestartingStreamItem::Event(event)),
+                        Some((client, request, events, restarts)),
+                    )),
+                    Err(error) if error.is_disconnect() && restarts < max_restarts => {
+                        let events = request.create_stream(&client, None);
+                        let restarts = restarts + 1;
+
+                        Some((
+                            Ok(RestartingStreamItem::Restarted { restarts }),
+                            Some((client, request, events, restarts)),
+                        ))
+                    }
+                    Err(error) => Some((Err(error), None)),
+                }
+            },
+        )))
+    }
+
+    /// Drives the stream from [`ChatCompletionBuilder::create_stream`] in a spawned task,
+    /// forwarding every event (or error) to the given channel.
+    ///
+    /// The channel is closed once the stream ends.
+    /// The task also stops early if the receiving half of the channel is dropped.
+    ///
+    /// Requires the `tokio-runtime` feature, and must be called within a Tokio runtime.
+    #[cfg(feature = "tokio-runtime")]
+    pub fn create_stream_to_channel(
+        self,
+        client: &Client,
+        sender: Sender<Result<ChatCompletionEvent, StreamError>>,
+    ) -> JoinHandle<()> {
+        let mut stream = self.create_stream(client);
+
+        tokio::spawn(async move {
+            while let Some(event) = stream.next().await {
+                if sender.send(event).await.is_err() {
+                    break;
+                }
+            }
+        })
+    }
+
+    /// Streams the chat completion into `writer`, writing and flushing the content of every delta as it arrives,
+    /// e.g. to pipe it to a file or socket.
+    ///
+    /// Returns how many bytes were written, and the usage of the request,
+    /// which is asked for by setting `include_usage` in `stream_options`.
+    /// The content of every choice is written, so `n` should usually be left unset.
+    ///
+    /// Ends with [`StreamError::Write`] if writing fails, dropping the rest of the stream.
+    pub async fn create_stream_to_writer<W>(
+        self,
+        client: &Client,
+        mut writer: W,
+    ) -> Result<(u64, Option<Usage>), StreamError>
+    where
+        W: AsyncWrite + Unpin,
+    {
+        let request = ChatCompletionRequest {
+            stream_options: Some(StreamOptions {
+                include_usage: true,
+            }),
+            ..self.build_stream().map_err(StreamError::Api)?
+        };
+        let mut events = request.create_stream(client, None);
+        let mut written = 0;
+        let mut usage = None;
+
+        while let Some(event) = events.next().await {
+            let event = event?;
+
+            for choice in &event.choices {
+                if let Delta::Content { content } = &choice.delta {
+                    writer
+                        .write_all(content.as_bytes())
+                        .await
+                        .map_err(StreamError::Write)?;
+                    writer.flush().await.map_err(StreamError::Write)?;
+                    written += content.len() as u64;
+                }
+            }
+
+            usage = event.usage.or(usage);
+        }
+
+        Ok((written, usage))
+    }
+
+    /// Strips leading and trailing whitespace from the content of the choices [`create`](Self::create) returns,
+    /// see [`ChatCompletion::trim_response`]. Off by default, so the content is exactly what the model generated.
+    ///
+    /// Streams aren't trimmed.
+    pub fn trim_response(mut self) -> Self {
+        self.trim_response = Some(true);
+        self
+    }
+
+    /// Builds the request sent by [`ChatCompletionBuilder::create_stream`].
+    fn build_stream(self) -> Result<ChatCompletionRequest, OpenAiError> {
+        Ok(ChatCompletionRequest {
+            stream: Some(true),
+            ..self.build().map_err(invalid_request)?
+        })
+    }
+
+    /// The maximum number of tokens allowed for the generated answer.
+    ///
+    /// Deprecated by the API in favor of [`max_completion_tokens`](Self::max_completion_tokens),
+    /// which newer models, like the reasoning models, require. Still sent as `max_tokens` for older models
+    /// and compatible services that don't know the newer parameter.
+    #[deprecated(note = "use `max_completion_tokens`, which newer models require")]
+    pub fn max_tokens(mut self, max_tokens: impl Into<u64>) -> Self {
+        self.max_tokens = Some(MaxTokens::Fixed(max_tokens.into()));
+        self
+    }
+
+    /// Sets `max_tokens` when building the request to as many tokens as the model can generate
+    /// after the prompt, so the completion is never cut off early, nor rejected for overflowing the context window.
+    ///
+    /// The prompt's tokens are estimated with the [`tokenizer`](crate::tokenizer),
+    /// leaving a margin of 64 tokens in case the estimate is short.
+    /// Building fails if the prompt doesn't leave room for a completion,
+    /// or the model's context window isn't known, see [`ModelID::context_window`],
+    /// in which case [`create`](Self::create) fails with an `invalid_request_error` without sending the request.
+    #[cfg(feature = "tokenizer")]
+    pub fn max_tokens_auto(mut self) -> Self {
+        self.max_tokens = Some(MaxTokens::Auto);
+        self
+    }
+
+    /// Builds the request, failing if a parameter is missing or invalid.
+    #[cfg_attr(not(feature = "tokenizer"), allow(unused_mut))]
+    pub fn build(mut self) -> Result<ChatCompletionRequest, ChatCompletionBuilderError> {
+        #[cfg(feature = "tokenizer")]
+        if self.max_tokens == Some(MaxTokens::Auto) {
+            let max_tokens = self
+                .auto_max_tokens()
+                .map_err(ChatCompletionBuilderError::ValidationError)?;
+
+            self.max_tokens = Some(MaxTokens::Fixed(max_tokens));
+        }
+
+        self.build_request()
+    }
+
+    #[cfg(feature = "tokenizer")]
+    fn auto_max_tokens(&self) -> Result<u64, String> {
+        let model = self.model.as_ref().ok_or("`model` must be initialized")?;
+        let context_window = model.context_window().ok_or_else(|| {
+            format!("the context window of `{model}` isn't known, set `max_tokens` instead")
+        })?;
+        let prompt_tokens = crate::tokenizer::count_message_tokens(
+            model,
+            self.messages.as_deref().unwrap_or_default(),
+        );
+        let available = (context_window as usize)
+            .checked_sub(prompt_tokens + MAX_TOKENS_AUTO_MARGIN)
+            .filter(|&available| available > 0)
+            .ok_or_else(|| {
+                format!(
+                    "the prompt takes about {prompt_tokens} tokens, which leaves no room for a \
+                     completion in the context window of `{model}` ({context_window} tokens)"
+                )
+            })?;
+        let max_output_tokens = model.max_output_tokens().unwrap_or(context_window);
+
+        Ok(available.min(max_output_tokens as usize) as u64)
+    }
+
+    /// Sets the sampling temperature, see [`Temperature`].
+    ///
+    /// Accepts a [`Temperature`], or a float which makes building the request fail if it's out of range.
+    pub fn temperature<T>(mut self, temperature: T) -> Self
+    where
+        T: TryInto<Temperature>,
+        T::Error: Into<OutOfRangeError>,
+    {
+        self.temperature = Some(temperature.try_into().map_err(Into::into));
+        self
+    }
+
+    /// Sets the probability mass for nucleus sampling, see [`TopP`].
+    ///
+    /// Accepts a [`TopP`], or a float which makes building the request fail if it's out of range.
+    pub fn top_p<T>(mut self, top_p: T) -> Self
+    where
+        T: TryInto<TopP>,
+        T::Error: Into<OutOfRangeError>,
+    {
+        self.top_p = Some(top_p.try_into().map_err(Into::into));
+        self
+    }
+
+    /// Sets the presence penalty, see [`Penalty`].
+    ///
+    /// Accepts a [`Penalty`], or a float which makes building the request fail if it's out of range.
+    pub fn presence_penalty<T>(mut self, presence_penalty: T) -> Self
+    where
+        T: TryInto<Penalty>,
+        T::Error: Into<OutOfRangeError>,
+    {
+        self.presence_penalty = Some(presence_penalty.try_into().map_err(Into::into));
+        self
+    }
+
+    /// Sets the frequency penalty, see [`Penalty`].
+    ///
+    /// Accepts a [`Penalty`], or a float which makes building the request fail if it's out of range.
+    pub fn frequency_penalty<T>(mut self, frequency_penalty: T) -> Self
+    where
+        T: TryInto<Penalty>,
+        T::Error: Into<OutOfRangeError>,
+    {
+        self.frequency_penalty = Some(frequency_penalty.try_into().map_err(Into::into));
+        self
+    }
+
+    /// Prevents the given tokens from being generated, by setting their `logit_bias` to -100.
+    ///
+    /// Biases set for other tokens are kept, so this can be combined with
+    /// [`logit_bias`](Self::logit_bias) (set before) and [`force_tokens`](Self::force_tokens).
+    pub fn ban_tokens(self, tokens: impl IntoIterator<Item = u32>) -> Self {
+        self.bias_tokens(tokens, -100.0)
+    }
+
+    /// Makes the given tokens the only ones that can be generated, by setting their `logit_bias` to 100.
+    ///
+    /// Biases set for other tokens are kept, like with [`ban_tokens`](Self::ban_tokens).
+    pub fn force_tokens(self, tokens: impl IntoIterator<Item = u32>) -> Self {
+        self.bias_tokens(tokens, 100.0)
+    }
+
+    fn bias_tokens(mut self, tokens: impl IntoIterator<Item = u32>, bias: f32) -> Self {
+        self.logit_bias
+            .get_or_insert(None)
+            .get_or_insert_with(HashMap::new)
+            .extend(tokens.into_iter().map(|token| (token.to_string(), bias)));
+        self
+    }
+
+    /// Checks the request for settings that are allowed, but almost always a mistake,
+    /// returning the builder unchanged if there are none.
+    ///
+    /// Call it after setting everything else, e.g.
+    /// `builder.temperature(0.2).strict()?.create(&client)`.
+    /// Currently, it's an error to set both `temperature` and `top_p`.
+    pub fn strict(self) -> Result<Self, ChatCompletionBuilderError> {
+        if self.temperature.is_some() && self.top_p.is_some() {
+            return Err(ChatCompletionBuilderError::ValidationError(
+                "both `temperature` and `top_p` are set, \
+                 it's recommended to alter one or the other but not both"
+                    .to_string(),
+            ));
+        }
+
+        Ok(self)
+    }
+}
+
+impl ChatCompletionMessage {
+    /// A message from the system, usually instructing the assistant how to behave.
+    pub fn system(content: impl Into<String>) -> Self {
+        Self::new(ChatCompletionMessageRole::System, content)
+    }
+
+    /// Instructions from the developer, which reasoning models (the o-series) and newer models expect
+    /// in place of a [`system`](Self::system) message.
+    pub fn developer(content: impl Into<String>) -> Self {
+        Self::new(ChatCompletionMessageRole::Developer, content)
+    }
+
+    /// A message from the user.
+    pub fn user(content: impl Into<String>) -> Self {
+        Self::new(ChatCompletionMessageRole::User, content)
+    }
+
+    /// A message from the assistant, for example one of its previous responses.
+    pub fn assistant(content: impl Into<String>) -> Self {
+        Self::new(ChatCompletionMessageRole::Assistant, content)
+    }
+
+    /// Sets the name of the message's author, to tell apart the users of a multi-user chat.
+    ///
+    /// Fails unless the name is made up of ASCII letters, digits, underscores and hyphens only,
+    /// which the API would otherwise reject with a less helpful error.
+    pub fn with_name(mut self, name: impl Into<String>) -> Result<Self, InvalidNameError> {
+        let name = name.into();
+
+        if !is_valid_name(&name) {
+            return Err(InvalidNameError { name });
+        }
+
+        self.name = Some(name);
+
+        Ok(self)
+    }
+
+    fn new(role: ChatCompletionMessageRole, content: impl Into<String>) -> Self {
+        ChatCompletionMessage {
+            role,
+            content: Some(content.into()),
+            refusal: None,
+            annotations: None,
+            name: None,
+            audio: None,
+        }
+    }
+
+    /// Renders the message as a line of a transcript, see [`parse_transcript`] for the format.
+    pub fn to_transcript_line(&self) -> String {
+        let mut line = self.role.as_str().to_string();
+
+        if let Some(name) = &self.name {
+            line += &format!(" ({name})");
+        }
+
+        line += ": ";
+
+        for character in self.content.as_deref().unwrap_or_default().chars() {
+            match character {
+                '\\' => line += "\\\\",
+                '\n' => line += "\\n",
+                '\r' => line += "\\r",
+                character => line.push(character),
+            }
+        }
+
+        line
+    }
+}
+
+/// A message with the given role and content, e.g. `(ChatCompletionMessageRole::User, "Hi!").into()`.
+impl<S: Into<String>> From<(ChatCompletionMessageRole, S)> for ChatCompletionMessage {
+    fn from((role, content): (ChatCompletionMessageRole, S)) -> Self {
+        Self::new(role, content)
+    }
+}
+
+/// A message from the user, like [`ChatCompletionMessage::user`].
+impl From<String> for ChatCompletionMessage {
+    fn from(content: String) -> Self {
+        Self::user(content)
+    }
+}
+
+/// A message from the user, like [`ChatCompletionMessage::user`].
+impl From<&str> for ChatCompletionMessage {
+    fn from(content: &str) -> Self {
+        Self::user(content)
+    }
+}
+
+/// Whether the API accepts `name` as the name of a message's author, see [`ChatCompletionMessage::with_name`].
+fn is_valid_name(name: &str) -> bool {
+    !name.is_empty()
+        && name
+            .chars()
+            .all(|character| character.is_ascii_alphanumeric() || matches!(character, '_' | '-'))
+}
+
+impl PromptTemplate {
+    pub fn new(template: impl Into<String>) -> Self {
+        PromptTemplate {
+            template: template.into(),
+        }
+    }
+
+    /// Replaces every `{{name}}` placeholder with the variable of that name,
+    /// spaces around the name, like in `{{ name }}`, are allowed.
+    ///
+    /// Placeholders without a variable are left as they are, which makes them easy to spot.
+    /// Variables are inserted as is, placeholders in them aren't replaced.
+    pub fn render(&self, vars: &HashMap<&str, &str>) -> String {
+        render_template(&self.template, vars)
+    }
+
+    /// Renders a template of a whole conversation, written as a transcript (see [`parse_transcript`]),
+    /// into its messages, replacing placeholders in their content like [`PromptTemplate::render`].
+    ///
+    /// The transcript is parsed before placeholders are replaced,
+    /// so variables can contain line breaks without escaping them.
+    pub fn render_messages(
+        &self,
+        vars: &HashMap<&str, &str>,
+    ) -> Result<Vec<ChatCompletionMessage>, ParseTranscriptError> {
+        let mut messages = parse_transcript(&self.template)?;
+
+        for message in &mut messages {
+            if let Some(content) = &mut message.content {
+                *content = render_template(content, vars);
+            }
+        }
+
+        Ok(messages)
+    }
+}
+
+fn render_template(template: &str, vars: &HashMap<&str, &str>) -> String {
+    let mut rendered = String::with_capacity(template.len());
+    let mut rest = template;
+
+    while let Some(start) = rest.find("{{") {
+        let Some(length) = rest[start + 2..].find("}}") else {
+            break;
+        };
+        let placeholder = &rest[start..start + 2 + length + 2];
+
+        rendered += &rest[..start];
+
+        match vars.get(placeholder[2..placeholder.len() - 2].trim()) {
+            Some(value) => rendered += value,
+            None => rendered += placeholder,
+        }
+
+        rest = &rest[start + placeholder.len()..];
+    }
+
+    rendered + rest
+}
+
+impl ChatCompletionMessageRole {
+    pub(crate) fn as_str(&self) -> &str {
+        match self {
+            ChatCompletionMessageRole::System => "system",
+            ChatCompletionMessageRole::Developer => "developer",
+            ChatCompletionMessageRole::User => "user",
+            ChatCompletionMessageRole::Assistant => "assistant",
+            ChatCompletionMessageRole::Other(role) => role,
+        }
+    }
+}
+
+impl From<String> for ChatCompletionMessageRole {
+    fn from(role: String) -> Self {
+        match role.as_str() {
+            "system" => ChatCompletionMessageRole::System,
+            "developer" => ChatCompletionMessageRole::Developer,
+            "user" => ChatCompletionMessageRole::User,
+            "assistant" => ChatCompletionMessageRole::Assistant,
+            _ => ChatCompletionMessageRole::Other(role),
+        }
+    }
+}
+
+impl Serialize for ChatCompletionMessageRole {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        serializer.serialize_str(self.as_str())
+    }
+}
+
+/// Parses a transcript of messages, like one made of [`ChatCompletionMessage::to_transcript_line`]s.
+///
+/// Each message takes up exactly one line, in the form `role: content`,
+/// or `role (name): content` for messages with a name:
+///
+/// ```text
+/// system: You are a helpful assistant.
+/// user (alice): Hi!\nHow are you?
+/// assistant: I'm fine, thank you.
+/// ```
+///
+/// The role is `system`, `developer`, `user` or `assistant`,
+/// lines of messages with [other roles](ChatCompletionMessageRole::Other) are rejected.
+/// Line breaks in the content are escaped as `\n` and `\r`, and backslashes as `\\`.
+/// Empty lines are skipped.
+pub fn parse_transcript(
+    transcript: &str,
+) -> Result<Vec<ChatCompletionMessage>, ParseTranscriptError> {
+    transcript
+        .lines()
+        .enumerate()
+        .filter(|(_, line)| !line.trim().is_empty())
+        .map(|(index, line)| {
+            parse_transcript_line(line).ok_or(ParseTranscriptError { line: index + 1 })
+        })
+        .collect()
+}
+
+/// Parses the content of a streamed chat completion as JSON while it arrives,
+/// for the model's answer to be rendered before it's complete, e.g. when the model is asked for JSON.
+///
+/// Whenever new content arrives, the content of the first choice so far is completed into a value,
+/// closing the strings, arrays and objects that are still open,
+/// and leaving out keys and numbers or literals that may not be complete yet.
+/// The value is yielded if it changed.
+/// Content that can't be completed into valid JSON, like prose before the JSON, is skipped,
+/// as it may still turn out to be valid once more content arrives.
+///
+/// Once the events end, the whole content is parsed as is, and yielded if it's different from the last value,
+/// or yielded as [`StreamError::Deserialization`] if it isn't valid JSON.
+/// Errors from the events end the stream.
+pub fn parse_partial_json<S>(
+    events: S,
+) -> impl Stream<Item = Result<serde_json::Value, StreamError>> + Unpin
+where
+    S: Stream<Item = Result<ChatCompletionEvent, StreamError>> + Unpin,
+{
+    Box::pin(stream::unfold(
+        Some((events, String::new(), None)),
+        |state| async move {
+            let (mut events, mut content, mut last): (_, String, Option<serde_json::Value>) =
+                state?;
+
+            loop {
+                let event = match events.next().await {
+                    Some(Ok(event)) => event,
+                    Some(Err(error)) => return Some((Err(error), None)),
+                    None => {
+                        return match json::decode::<serde_json::Value>(content.into_bytes()) {
+                            Ok(value) if last.as_ref() == Some(&value) => None,
+                            Ok(value) => Some((Ok(value), None)),
+                            Err(error) => Some((Err(error.into()), None)),
+                        }
+                    }
+                };
+
+                for choice in event.choices.iter().filter(|choice| choice.index == 0) {
+                    if let Delta::Content { content: delta } = &choice.delta {
+                        content.push_str(delta);
+                    }
+                }
+
+                if let Some(value) = complete_partial_json(&content) {
+                    if last.as_ref() != Some(&value) {
+                        last = Some(value.clone());
+
+                        return Some((Ok(value), Some((events, content, last))));
+                    }
+                }
+            }
+        },
+    ))
+}
+
+/// Completes the start of a JSON document into the most complete value it can be parsed as,
+/// `None` if there isn't one yet.
+fn complete_partial_json(json: &str) -> Option<serde_json::Value> {
+    // Characters closing the arrays and objects that are open
+    let mut closers = Vec::new();
+    // Where the last complete value ends, and the closers needed after it
+    let mut complete = None;
+    let mut expect_key = false;
+    // Whether the string being read is an object key, `None` outside of strings
+    let mut string: Option<bool> = None;
+    // Where the escape sequence being read starts, and how many characters of it are left
+    let mut escape = None;
+    let mut literal = false;
+
+    for (index, character) in json.char_indices() {
+        if let Some(is_key) = string {
+            match escape {
+                Some((start, 0)) if character == 'u' => escape = Some((start, 4)),
+                Some((start, left)) if left > 1 => escape = Some((start, left - 1)),
+                Some(_) => escape = None,
+                None if character == '\\' => escape = Some((index, 0)),
+                None if character == '"' => {
+                    string = None;
+
+                    if !is_key {
+                        complete = Some((index + 1, closers.clone()));
+                    }
+                }
+                None => {}
+            }
+
+            continue;
+        }
+
+        if literal {
+            if character.is_ascii_alphanumeric() || "+-.".contains(character) {
+                continue;
+            }
+
+            literal = false;
+            complete = Some((index, closers.clone()));
+        }
+
+        match character {
+            '"' => string = Some(expect_key),
+            '{' | '[' => {
+                closers.push(if character == '{' { '}' } else { ']' });
+                expect_key = character == '{';
+                complete = Some((index + 1, closers.clone()));
+            }
+            '}' | ']' => {
+                closers.pop();
+                complete = Some((index + 1, closers.clone()));
+            }
+            ':' => expect_key = false,
+            ',' => expect_key = closers.last() == Some(&'}'),
+            character if character.is_whitespace() => {}
+            _ => literal = true,
+        }
+    }
+
+    let completed = match string {
+        // A string value can be shown as far as it arrived, without an incomplete escape sequence
+        Some(false) => {
+            let end = escape.map_or(json.len(), |(start, _)| start);
+
+            format!(
+                "{}\"{}",
+                &json[..end],
+                closers.iter().rev().collect::<String>()
+            )
+        }
+        _ => {
+            let (end, closers) = complete?;
+
+            format!(
+                "{}{}",
+                &json[..end],
+                closers.iter().rev().collect::<String>()
+            )
+        }
+    };
+
+    serde_json::from_str(&completed).ok()
+}
+
+fn parse_transcript_line(line: &str) -> Option<ChatCompletionMessage> {
+    let (author, content) = line.split_once(':')?;
+    let (role, name) = match author.split_once(" (") {
+        Some((role, name)) => (role, Some(name.strip_suffix(')')?.to_string())),
+        None => (author, None),
+    };
+    let role = [
+        ChatCompletionMessageRole::System,
+        ChatCompletionMessageRole::Developer,
+        ChatCompletionMessageRole::User,
+        ChatCompletionMessageRole::Assistant,
+    ]
+    .into_iter()
+    .find(|candidate| candidate.as_str() == role)?;
+    let content = content.strip_prefix(' ').unwrap_or(content);
+    let mut unescaped = String::with_capacity(content.len());
+    let mut characters = content.chars();
+
+    while let Some(character) = characters.next() {
+        unescaped.push(match character {
+            '\\' => match characters.next()? {
+                '\\' => '\\',
+                'n' => '\n',
+                'r' => '\r',
+                _ => return None,
+            },
+            character => character,
+        });
+    }
+
+    Some(ChatCompletionMessage {
+        role,
+        content: Some(unescaped),
+        refusal: None,
+        annotations: None,
+        name,
+        audio: None,
+    })
+}
+
+impl std::fmt::Display for ParseTranscriptError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "line {} of the transcript should be a message, like `user: Hello!`",
+            self.line,
+        )
+    }
+}
+
+impl std::error::Error for ParseTranscriptError {}
+
+impl std::fmt::Display for InvalidNameError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "`{}` isn't a valid name, names should be made up of only ASCII letters, digits, \
+             underscores and hyphens (`^[a-zA-Z0-9_-]+$`)",
+            self.name,
+        )
+    }
+}
+
+impl std::error::Error for InvalidNameError {}
+
+impl std::fmt::Display for ExportError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ExportError::Request(error) => write!(f, "{error}"),
+            ExportError::Api(error) => write!(f, "{error}"),
+            ExportError::Write(error) => write!(f, "failed to write the dataset: {error}"),
+        }
+    }
+}
+
+impl std::error::Error for ExportError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            ExportError::Request(error) => Some(error),
+            ExportError::Api(error) => Some(error),
+            ExportError::Write(error) => Some(error),
+        }
+    }
+}
+
+impl From<RequestError> for ExportError {
+    fn from(error: RequestError) -> Self {
+        ExportError::Request(error)
+    }
+}
+
+impl From<OpenAiError> for ExportError {
+    fn from(error: OpenAiError) -> Self {
+        ExportError::Api(error)
+    }
+}
+
+impl std::fmt::Display for ValidationError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "the request is invalid: ")?;
+
+        for (index, violation) in self.violations.iter().enumerate() {
+            if index > 0 {
+                write!(f, "; ")?;
+            }
+
+            write!(f, "{violation}")?;
+        }
+
+        Ok(())
+    }
+}
+
+impl std::error::Error for ValidationError {}
+
+impl std::fmt::Display for Violation {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "`{}` {}", self.field, self.message)
+    }
+}
+
+impl TokenBytesDecoder {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Adds the bytes of the next token, returning the text they complete.
+    ///
+    /// Invalid bytes are replaced with `U+FFFD REPLACEMENT CHARACTER`.
+    pub fn push(&mut self, bytes: &[u8]) -> String {
+        self.pending.extend_from_slice(bytes);
+
+        let mut text = String::new();
+        let mut rest = self.pending.as_slice();
+
+        loop {
+            match std::str::from_utf8(rest) {
+                Ok(valid) => {
+                    text.push_str(valid);
+                    rest = &[];
+
+                    break;
+                }
+                Err(error) => {
+                    let (valid, after_valid) = rest.split_at(error.valid_up_to());
+
+                    // `valid_up_to` guarantees this part is valid UTF-8
+                    text.push_str(std::str::from_utf8(valid).unwrap());
+
+                    match error.error_len() {
+                        // An incomplete character at the end, wait for the next token
+                        None => {
+                            rest = after_valid;
+
+                            break;
+                        }
+                        Some(invalid_len) => {
+                            text.push(char::REPLACEMENT_CHARACTER);
+                            rest = &after_valid[invalid_len..];
+                        }
+                    }
+                }
+            }
+        }
+
+        self.pending = rest.to_vec();
+
+        text
+    }
+
+    /// Returns whatever is left once there are no more tokens,
+    /// replacing an incomplete character with `U+FFFD REPLACEMENT CHARACTER`.
+    pub fn finish(self) -> String {
+        String::from_utf8_lossy(&self.pending).into_owned()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        tests::{chat_completion_json, stub_server, StubLayer},
+        Credentials,
+    };
+    use dotenvy::dotenv;
+
+    #[tokio::test]
+    async fn chat() {
+        dotenv().ok();
+
+        let chat_completion = ChatCompletion::builder(
+            ModelID::Gpt3_5Turbo,
+            [ChatCompletionMessage::user("Hello!")],
+        )
+        .temperature(0.0)
+        .create(&crate::tests::live_client("chat"))
+        .await
+        .unwrap()
+        .unwrap();
+
+        assert_eq!(
+            chat_completion.content().unwrap(),
+            "\n\nHello there! How can I assist you today?"
+        );
+    }
+
+    #[tokio::test]
+    #[cfg(feature = "tokio-runtime")]
+    async fn chat_stream_to_channel() {
+        dotenv().ok();
+
+        let (sender, mut receiver) = tokio::sync::mpsc::channel(16);
+        let task = ChatCompletion::builder(
+            ModelID::Gpt3_5Turbo,
+            [ChatCompletionMessage::user("Hello!")],
+        )
+        .temperature(0.0)
+        .create_stream_to_channel(&Client::new(), sender);
+        let mut content = String::new();
+
+        while let Some(event) = receiver.recv().await {
+            if let Delta::Content { content: delta } = &event.unwrap().choices[0].delta {
+                content.push_str(delta);
+            }
+        }
+
+        task.await.unwrap();
+        assert_eq!(content, "\n\nHello there! How can I assist you today?");
+    }
+
+    #[test]
+    fn request_equality() {
+        let request = || {
+            ChatCompletion::builder(
+                ModelID::Gpt3_5Turbo,
+                [ChatCompletionMessage::user("Hello!")],
+            )
+            .temperature(0.0)
+        };
+        let metadata =
+            ["user", "session", "locale", "tier"].map(|key| (key.to_string(), key.to_string()));
+        let first = request()
+            .logit_bias(HashMap::from([
+                ("50256".to_string(), -100.0),
+                ("1".to_string(), 1.0),
+            ]))
+            .metadata(HashMap::from(metadata.clone()))
+            .build()
+            .unwrap();
+        let second = request()
+            .logit_bias(HashMap::from([
+                ("1".to_string(), 1.0),
+                ("50256".to_string(), -100.0),
+            ]))
+            .metadata(metadata.into_iter().rev().collect::<HashMap<_, _>>())
+            .build()
+            .unwrap();
+
+        assert_eq!(first, second);
+        assert_eq!(first.request_hash(), second.request_hash());
+        assert_ne!(
+            first.request_hash(),
+            request().temperature(1.0).build().unwrap().request_hash(),
+        );
+
+        // Not sent, but still distinguished
+        let hash = |builder: ChatCompletionBuilder| builder.build().unwrap().request_hash();
+
+        assert_ne!(hash(request()), hash(request().trim_response()));
+        assert_ne!(
+            hash(request()),
+            hash(request().first_token_timeout(Duration::from_secs(1))),
+        );
+    }
+
+    #[test]
+    fn ban_tokens() {
+        let request = ChatCompletion::builder(
+            ModelID::Gpt3_5Turbo,
+            [ChatCompletionMessage::user("Hello!")],
+        )
+        .logit_bias(HashMap::from([("1".to_string(), 1.0)]))
+        .ban_tokens([50256, 2])
+        .force_tokens([3])
+        .build()
+        .unwrap();
+
+        assert_eq!(
+            serde_json::to_value(&request).unwrap()["logit_bias"],
+            serde_json::json!({ "1": 1.0, "2": -100.0, "3": 100.0, "50256": -100.0 }),
+        );
+    }
+
+    #[test]
+    fn reasoning_effort_serialization() {
+        let request = |builder: ChatCompletionBuilder| {
+            serde_json::to_value(builder.build().unwrap()).unwrap()
+        };
+        let builder = || ChatCompletion::builder(ModelID::Custom("o1".to_string()), []);
+
+        assert!(request(builder()).get("reasoning_effort").is_none());
+        assert_eq!(
+            request(builder().reasoning_effort(ReasoningEffort::Low))["reasoning_effort"],
+            "low",
+        );
+    }
+
+    #[test]
+    fn metadata_serialization() {
+        let builder = || ChatCompletion::builder(ModelID::Gpt3_5Turbo, []);
+        let request = serde_json::to_value(builder().build().unwrap()).unwrap();
+        let stored_request = serde_json::to_value(
+            builder()
+                .store(true)
+                .metadata(HashMap::from([(
+                    "conversation_id".to_string(),
+                    "42".to_string(),
+                )]))
+                .build()
+                .unwrap(),
+        )
+        .unwrap();
+
+        assert!(request.get("store").is_none());
+        assert!(request.get("metadata").is_none());
+        assert_eq!(stored_request["store"], true);
+        assert_eq!(stored_request["metadata"]["conversation_id"], "42");
+    }
+
+    #[test]
+    fn list_params() {
+        let params = ChatCompletionListParams {
+            after: Some("chatcmpl-6wBU7HGxEXqdShNC81ZlfkOLDM0MF".to_string()),
+            limit: Some(10),
+            metadata: HashMap::from([("conversation_id".to_string(), "42".to_string())]),
+            model: Some(ModelID::Gpt3_5Turbo),
+            order: Some(Order::Desc),
+        };
+
+        assert_eq!(
+            params.query(),
+            [
+                ("after", "chatcmpl-6wBU7HGxEXqdShNC81ZlfkOLDM0MF"),
+                ("limit", "10"),
+                ("metadata[conversation_id]", "42"),
+                ("model", "gpt-3.5-turbo"),
+                ("order", "desc"),
+            ]
+            .map(|(key, value)| (key.to_string(), value.to_string())),
+        );
+        assert!(ChatCompletionListParams::default().query().is_empty());
+    }
+
+    #[test]
+    fn list_deserialization() {
+        let page: Page<ChatCompletion> = serde_json::from_str(
+            r#"{
+                "object": "list",
+                "data": [{
+                    "id": "chatcmpl-6wBU7HGxEXqdShNC81ZlfkOLDM0MF",
+                    "object": "chat.completion",
+                    "created": 1679325191,
+                    "model": "gpt-3.5-turbo",
+                    "choices": []
+                }],
+                "first_id": "chatcmpl-6wBU7HGxEXqdShNC81ZlfkOLDM0MF",
+                "last_id": "chatcmpl-6wBU7HGxEXqdShNC81ZlfkOLDM0MF",
+                "has_more": true
+            }"#,
+        )
+        .unwrap();
+
+        assert_eq!(page.data.len(), 1);
+        assert_eq!(page.last_id.as_deref(), Some(page.data[0].id.as_str()));
+        assert!(page.has_more);
+    }
+
+    fn assert_send<T: Send>(_: &T) {}
+
+    #[tokio::test]
+    async fn streams_are_send() {
+        let client = Client::builder()
+            .credentials(Credentials::new("sk-test"))
+            .build()
+            .unwrap();
+        let builder = || ChatCompletion::builder(ModelID::Gpt3_5Turbo, []);
+
+        // Checked at compile time, the streams are never polled
+        assert_send(&builder().create_stream(&client));
+        assert_send(&builder().create_restarting_stream(&client, 1));
+        assert_send(&ChatCompletion::list_all(
+            &client,
+            ChatCompletionListParams::default(),
+        ));
+    }
+
+    #[test]
+    fn stream_serialization() {
+        let builder = || ChatCompletion::builder(ModelID::Gpt3_5Turbo, []);
+        let request = serde_json::to_value(builder().build().unwrap()).unwrap();
+        let stream_request = serde_json::to_value(builder().build_stream().unwrap()).unwrap();
+
+        assert!(request.get("stream").is_none());
+        assert_eq!(stream_request["stream"], true);
+    }
+
+    #[tokio::test]
+    async fn export_training_data() {
+        let page = |data: serde_json::Value, last_id: &str| serde_json::json!({"object": "list", "data": data, "last_id": last_id, "has_more": false});
+        let stub = StubLayer::responses(vec![
+            page(
+                serde_json::json!([chat_completion_json("Paris.", "stop")]),
+                "chatcmpl-123",
+            ),
+            page(
+                serde_json::json!([
+                    {"id": "chatcmpl-123-0", "role": "system", "content": "Be brief."},
+                    {"id": "chatcmpl-123-1", "role": "user", "content": "Capital of France?"}
+                ]),
+                "chatcmpl-123-1",
+            ),
+        ]);
+        let client = stub.client();
+        let mut output = Vec::new();
+        let params = ChatCompletionListParams {
+            metadata: HashMap::from([("use".to_string(), "eval".to_string())]),
+            ..Default::default()
+        };
+        let written = ChatCompletion::export_training_data(&client, params, &mut output)
+            .await
+            .unwrap();
+
+        assert_eq!(written, 1);
+        assert_eq!(
+            String::from_utf8(output).unwrap(),
+            concat!(
+                r#"{"messages":[{"role":"system","content":"Be brief."},"#,
+                r#"{"role":"user","content":"Capital of France?"},"#,
+                r#"{"role":"assistant","content":"Paris."}]}"#,
+                "\n",
+            ),
+        );
+        assert_eq!(
+            stub.requests()
+                .into_iter()
+                .map(|(route, _)| route)
+                .collect::<Vec<_>>(),
+            [
+                "chat/completions?metadata%5Buse%5D=eval",
+                "chat/completions/chatcmpl-123/messages?limit=100",
+            ],
+        );
+    }
+
+    #[test]
+    fn developer_role() {
+        let message = ChatCompletionMessage::developer("Be terse.");
+        let json = serde_json::to_value(&message).unwrap();
+
+        assert_eq!(json["role"], "developer");
+        assert_eq!(
+            serde_json::from_value::<ChatCompletionMessage>(json).unwrap(),
+            message,
+        );
+        assert_eq!(
+            parse_transcript(&message.to_transcript_line()).unwrap(),
+            [message],
+        );
+    }
+
+    #[test]
+    fn message_conversions() {
+        let messages: Vec<ChatCompletionMessage> = vec![
+            (ChatCompletionMessageRole::System, "Be brief.").into(),
+            "Hi!".into(),
+            (ChatCompletionMessageRole::Assistant, "Hello!".to_string()).into(),
+            String::from("Bye!").into(),
+        ];
+
+        assert_eq!(
+            messages,
+            [
+                ChatCompletionMessage::system("Be brief."),
+                ChatCompletionMessage::user("Hi!"),
+                ChatCompletionMessage::assistant("Hello!"),
+                ChatCompletionMessage::user("Bye!"),
+            ],
+        );
+    }
+
+    #[test]
+    fn message_names() {
+        assert_eq!(
+            ChatCompletionMessage::user("Hi!")
+                .with_name("alice_2-b")
+                .unwrap()
+                .name
+                .as_deref(),
+            Some("alice_2-b"),
+        );
+
+        for name in ["", "Alice Smith", "zoë", "bob!"] {
+            assert_eq!(
+                ChatCompletionMessage::assistant("Hi!").with_name(name),
+                Err(InvalidNameError {
+                    name: name.to_string()
+                }),
+            );
+        }
+
+        let mut message = ChatCompletionMessage::user("Hi!");
+
+        message.name = Some("Alice Smith".to_string());
+
+        let request = ChatCompletion::builder(ModelID::Gpt3_5Turbo, [message])
+            .build()
+            .unwrap();
+
+        assert_eq!(
+            request.validate().unwrap_err().violations[0].field,
+            "messages"
+        );
+    }
+
+    #[test]
+    fn prediction() {
+        let builder = || {
+            ChatCompletion::builder(
+                ModelID::Gpt3_5Turbo,
+                [ChatCompletionMessage::user("Rename `x` to `count`.")],
+            )
+        };
+        let request = builder()
+            .prediction(Prediction::Content {
+                content: "let count = 1;".to_string(),
+            })
+            .build()
+            .unwrap();
+
+        assert_eq!(
+            serde_json::to_value(&request).unwrap()["prediction"],
+            serde_json::json!({"type": "content", "content": "let count = 1;"}),
+        );
+        assert!(serde_json::to_value(builder().build().unwrap())
+            .unwrap()
+            .get("prediction")
+            .is_none());
+    }
+
+    #[test]
+    fn fully_modeled() {
+        assert_eq!(
+            crate::tests::unmodeled_fields::<ChatCompletion>(include_str!(
+                "../fixtures/chat_completion.json"
+            )),
+            Vec::<String>::new(),
+        );
+        assert_eq!(
+            crate::tests::unmodeled_fields::<ChatCompletionEvent>(include_str!(
+                "../fixtures/chat_completion_chunk.json"
+            )),
+            Vec::<String>::new(),
+        );
+    }
+
+    #[test]
+    fn field_names() {
+        assert_eq!(
+            crate::tests::misnamed_fields::<ChatCompletion>(include_str!(
+                "../fixtures/chat_completion.json"
+            )),
+            Vec::<String>::new(),
+        );
+        assert_eq!(
+            crate::tests::misnamed_fields::<ChatCompletionEvent>(include_str!(
+                "../fixtures/chat_completion_chunk.json"
+            )),
+            Vec::<String>::new(),
+        );
+
+        let request = ChatCompletion::builder(
+            ModelID::Gpt3_5Turbo,
+            [
+                ChatCompletionMessage::system("You are a helpful assistant."),
+                ChatCompletionMessage::user("Hello!"),
+            ],
+        )
+        .temperature(0.5)
+        .top_p(1.0)
+        .n(2)
+        .stop(vec!["\n".to_string()])
+        .max_completion_tokens(256u64)
+        .presence_penalty(0.5)
+        .frequency_penalty(0.25)
+        .logprobs(true)
+        .top_logprobs(2)
+        .logit_bias(HashMap::from([("50256".to_string(), -100.0)]))
+        .seed(42)
+        .user("user-1234")
+        .store(true)
+        .metadata(HashMap::from([(
+            "topic".to_string(),
+            "greeting".to_string(),
+        )]))
+        .service_tier(ServiceTier::Default)
+        .build()
+        .unwrap();
+
+        assert_eq!(
+            serde_json::to_value(&request).unwrap(),
+            serde_json::from_str::<serde_json::Value>(include_str!(
+                "../fixtures/chat_completion_request.json"
+            ))
+            .unwrap(),
+        );
+    }
+
+    #[test]
+    fn best_by_logprob() {
+        let choice = |index: u64, logprobs: Option<&[f64]>| {
+            serde_json::json!({
+                "index": index,
+                "message": {"role": "assistant", "content": "Hi!"},
+                "finish_reason": "stop",
+                "logprobs": logprobs.map(|logprobs| serde_json::json!({
+                    "content": logprobs
+                        .iter()
+                        .map(|logprob| serde_json::json!({
+                            "token": "Hi",
+                            "logprob": logprob,
+                            "bytes": null,
+                            "top_logprobs": []
+                        }))
+                        .collect::<Vec<_>>()
+                })),
+            })
+        };
+        let completion = |choices: Vec<serde_json::Value>| -> ChatCompletion {
+            serde_json::from_value(serde_json::json!({
+                "id": "chatcmpl-123",
+                "object": "chat.completion",
+                "created": 1679325191,
+                "model": "gpt-3.5-turbo",
+                "choices": choices,
+            }))
+            .unwrap()
+        };
+        let best =
+            |completion: &ChatCompletion| completion.best_by_logprob().map(|choice| choice.index);
+
+        assert_eq!(
+            best(&completion(vec![
+                choice(0, Some(&[-0.5, -1.0])),
+                choice(1, None),
+                choice(2, Some(&[-0.25, -0.25])),
+                choice(3, Some(&[-0.5])),
+            ])),
+            Some(2),
+        );
+        // The first of equally likely choices wins
+        assert_eq!(
+            best(&completion(vec![
+                choice(0, Some(&[-1.0])),
+                choice(1, Some(&[-0.5])),
+                choice(2, Some(&[-0.25, -0.25])),
+            ])),
+            Some(1),
+        );
+        assert_eq!(
+            best(&completion(vec![choice(0, None), choice(1, None)])),
+            None
+        );
+        assert_eq!(best(&completion(vec![])), None);
+    }
+
+    #[test]
+    fn prompt_template() {
+        let vars = HashMap::from([("name", "Alice"), ("topic", "Rust\nand C")]);
+
+        assert_eq!(
+            PromptTemplate::new("Hi {{name}}, let's talk about {{ topic }}.").render(&vars),
+            "Hi Alice, let's talk about Rust\nand C.",
+        );
+        assert_eq!(
+            PromptTemplate::new("{{missing}} {{name}}{{name}} {{ unclosed").render(&vars),
+            "{{missing}} AliceAlice {{ unclosed",
+        );
+        assert_eq!(
+            PromptTemplate::new("system: Be brief.\nuser ({{name}}): Tell me about {{topic}}.")
+                .render_messages(&vars)
+                .unwrap(),
+            [
+                ChatCompletionMessage::system("Be brief."),
+                ChatCompletionMessage {
+                    name: Some("{{name}}".to_string()),
+                    ..ChatCompletionMessage::user("Tell me about Rust\nand C.")
+                },
+            ],
+        );
+    }
+
+    #[test]
+    fn transcript() {
+        let messages = vec![
+            ChatCompletionMessage::system("You are a helpful assistant."),
+            ChatCompletionMessage {
+                role: ChatCompletionMessageRole::User,
+                content: Some("Hi!\nWhat does C:\\Windows contain?".to_string()),
+                refusal: None,
+                annotations: None,
+                name: Some("alice".to_string()),
+                audio: None,
+            },
+            ChatCompletionMessage::assistant(""),
+        ];
+        let transcript = messages
+            .iter()
+            .map(ChatCompletionMessage::to_transcript_line)
+            .collect::<Vec<_>>()
+            .join("\n");
+
+        assert_eq!(
+            transcript,
+            "system: You are a helpful assistant.\n\
+             user (alice): Hi!\\nWhat does C:\\\\Windows contain?\n\
+             assistant: ",
+        );
+        assert_eq!(parse_transcript(&transcript).unwrap(), messages);
+        assert_eq!(
+            parse_transcript("user: Hi!\n\nnarrator: Hello.\n"),
+            Err(ParseTranscriptError { line: 3 }),
+        );
+    }
+
+    #[tokio::test]
+    async fn invalid_parameters() {
+        let client = Client::builder()
+            .credentials(Credentials::new("sk-test"))
+            .build()
+            .unwrap();
+        let builder = || {
+            ChatCompletion::builder(
+                ModelID::Gpt3_5Turbo,
+                [ChatCompletionMessage::user("Hello!")],
+            )
+            .temperature(2.5)
+        };
+        let assert_invalid = |error: OpenAiError| {
+            assert_eq!(error.error_type, "invalid_request_error");
+            assert!(error.message.contains("temperature"), "{}", error.message);
+        };
+
+        assert_invalid(builder().create(&client).await.unwrap().unwrap_err());
+        assert_invalid(builder().dry_run(&client).unwrap().unwrap_err());
+
+        let mut stream = builder().create_stream(&client);
+
+        match stream.next().await {
+            Some(Err(StreamError::Api(error))) => assert_invalid(error),
+            other => panic!("expected an API error, got {other:?}"),
+        }
+        assert!(stream.next().await.is_none());
+        assert!(stream.headers().is_none());
+    }
+
+    #[test]
+    fn dry_run() {
+        let client = Client::builder()
+            .credentials(Credentials::new("sk-0123456789abcdef"))
+            .build()
+            .unwrap();
+        let dry_run = ChatCompletion::builder(
+            ModelID::Gpt3_5Turbo,
+            [ChatCompletionMessage::user("Hello!")],
+        )
+        .temperature(0.0)
+        .dry_run(&client)
+        .unwrap()
+        .unwrap();
+        let authorization = dry_run.headers[reqwest::header::AUTHORIZATION]
+            .to_str()
+            .unwrap();
+
+        assert_eq!(dry_run.method, Method::POST);
+        assert_eq!(
+            dry_run.url.as_str(),
+            "https://api.openai.com/v1/chat/completions",
+        );
+        assert_eq!(authorization, "Bearer [REDACTED]");
+        assert!(!authorization.contains("0123456789abcdef"));
+        assert_eq!(
+            serde_json::from_str::<serde_json::Value>(&dry_run.body.unwrap()).unwrap(),
+            serde_json::json!({
+                "model": "gpt-3.5-turbo",
+                "messages": [{"role": "user", "content": "Hello!"}],
+                "temperature": 0.0
+            }),
+        );
+    }
+
+    /// Serves a connection for each of the given lists of event data, in order,
+    /// then either closes the connection or leaves it hanging.
+    async fn event_stream_stub(connections: Vec<Vec<&'static str>>, hang: bool) -> Client {
+        let connections = std::sync::Mutex::new(connections.into_iter());
+        let address = stub_server(move |_, mut stream| {
+            let events = connections.lock().unwrap().next().unwrap();
+
+            async move {
+                let mut response = "HTTP/1.1 200 OK\r\ncontent-type: text/event-stream\r\n\
+                                    x-ratelimit-remaining-requests: 59\r\n\
+                                    x-request-id: req_stream\r\n\r\n"
+                    .to_string();
+
+                for event in events {
+                    response += &format!("data: {event}\n\n");
+                }
+
+                stream.write_all(response.as_bytes()).await.unwrap();
+
+                if hang {
+                    tokio::time::sleep(Duration::from_secs(10)).await;
+                }
+            }
+        })
+        .await;
+
+        Client::builder()
+            .base_url(format!("http://{address}"))
+            .credentials(Credentials::new("sk-test"))
+            .build()
+            .unwrap()
+    }
+
+    #[tokio::test]
+    async fn continuations() {
+        let stub = StubLayer::responses(vec![
+            chat_completion_json("Once upon", "length"),
+            chat_completion_json(" a time", "length"),
+            chat_completion_json(" there was", "stop"),
+        ]);
+        let client = stub.client();
+        let completion = ChatCompletion::builder(
+            ModelID::Gpt3_5Turbo,
+            [ChatCompletionMessage::user("Tell me a story.")],
+        )
+        .n(1)
+        .create_with_continuations(&client, 5)
+        .await
+        .unwrap()
+        .unwrap();
+
+        assert_eq!(completion.content(), Some("Once upon a time there was"));
+        assert_eq!(completion.choices[0].finish_reason, "stop");
+        assert_eq!(completion.usage.unwrap().total_tokens, 45);
+
+        let requests: Vec<_> = stub.requests().into_iter().map(|(_, body)| body).collect();
+
+        assert_eq!(requests.len(), 3);
+        assert_eq!(requests[0]["n"], 1);
+        assert!(requests[2].get("n").is_none());
+        assert_eq!(
+            requests[2]["messages"],
+            serde_json::json!([
+                {"role": "user", "content": "Tell me a story."},
+                {"role": "assistant", "content": "Once upon a time"},
+                {"role": "user", "content": CONTINUATION_PROMPT}
+            ]),
+        );
+
+        // Stops at the limit, and once a continuation adds nothing
+        let stub = StubLayer::responses(vec![
+            chat_completion_json("Once upon", "length"),
+            chat_completion_json(" a time", "length"),
+        ]);
+        let client = stub.client();
+        let completion = ChatCompletion::builder(
+            ModelID::Gpt3_5Turbo,
+            [ChatCompletionMessage::user("Tell me a story.")],
+        )
+        .create_with_continuations(&client, 1)
+        .await
+        .unwrap()
+        .unwrap();
+
+        assert_eq!(completion.content(), Some("Once upon a time"));
+        assert_eq!(completion.choices[0].finish_reason, "length");
+        assert_eq!(stub.requests().len(), 2);
+
+        let stub = StubLayer::responses(vec![
+            chat_completion_json("Once upon", "length"),
+            chat_completion_json("", "length"),
+        ]);
+        let client = stub.client();
+        let completion = ChatCompletion::builder(
+            ModelID::Gpt3_5Turbo,
+            [ChatCompletionMessage::user("Tell me a story.")],
+        )
+        .create_with_continuations(&client, 5)
+        .await
+        .unwrap()
+        .unwrap();
+
+        assert_eq!(completion.content(), Some("Once upon"));
+        assert_eq!(stub.requests().len(), 2);
+    }
+
+    const ROLE_EVENT: &str = r#"{"id": "chatcmpl-123", "object": "chat.completion.chunk", "created": 1679325191, "model": "gpt-3.5-turbo", "choices": [{"delta": {"role": "assistant"}, "index": 0, "finish_reason": null}]}"#;
+    const CONTENT_EVENT: &str = r#"{"id": "chatcmpl-123", "object": "chat.completion.chunk", "created": 1679325191, "model": "gpt-3.5-turbo", "choices": [{"delta": {"content": "Hi!"}, "index": 0, "finish_reason": null}]}"#;
+
+    #[test]
+    fn validate() {
+        let builder = || {
+            ChatCompletion::builder(
+                ModelID::Gpt3_5Turbo,
+                [ChatCompletionMessage::user("Hello!")],
+            )
+        };
+
+        assert_eq!(
+            builder().temperature(0.7).build().unwrap().validate(),
+            Ok(())
+        );
+        // The API accepts both, only `strict` refuses them
+        assert_eq!(
+            builder()
+                .temperature(0.7)
+                .top_p(0.9)
+                .build()
+                .unwrap()
+                .validate(),
+            Ok(())
+        );
+
+        let request = builder()
+            .messages(Vec::new())
+            .temperature(0.5)
+            .top_p(0.5)
+            .stop(vec!["1".to_string(); 5])
+            .top_logprobs(5)
+            .modalities(vec![Modality::Audio])
+            .build()
+            .unwrap();
+        let fields: Vec<_> = request
+            .validate()
+            .unwrap_err()
+            .violations
+            .iter()
+            .map(|violation| violation.field)
+            .collect();
+
+        assert_eq!(fields, ["messages", "stop", "top_logprobs", "audio"],);
+    }
+
+    #[test]
+    fn validate_template() {
+        let template: ChatCompletionRequest = serde_json::from_value(serde_json::json!({
+            "model": "gpt-3.5-turbo",
+            "messages": [{"role": "system", "content": "Answer in one word."}],
+            "temperature": 0.5,
+            "stop": ["1", "2", "3", "4", "5"],
+        }))
+        .unwrap();
+        let fields: Vec<_> = template
+            .validate()
+            .unwrap_err()
+            .violations
+            .iter()
+            .map(|violation| violation.field)
+            .collect();
+
+        assert_eq!(fields, ["stop"]);
+        // Fields left out get the same defaults as with the builder
+        assert_eq!(
+            serde_json::from_value::<ChatCompletionRequest>(serde_json::json!({
+                "model": "gpt-3.5-turbo",
+                "messages": [{"role": "user", "content": "Hello!"}],
+            }))
+            .unwrap(),
+            ChatCompletion::builder(
+                ModelID::Gpt3_5Turbo,
+                [ChatCompletionMessage::user("Hello!")]
+            )
+            .build()
+            .unwrap(),
+        );
+        assert!(
+            serde_json::from_value::<ChatCompletionRequest>(serde_json::json!({
+                "model": "gpt-3.5-turbo",
+                "messages": [],
+                "temperature": 3.0,
+            }))
+            .is_err()
+        );
+    }
+
+    #[test]
+    fn ranged_parameters() {
+        let builder = || {
+            ChatCompletion::builder(
+                ModelID::Gpt3_5Turbo,
+                [ChatCompletionMessage::user("Hello!")],
+            )
+        };
+
+        assert_eq!(Temperature::try_from(2.0).unwrap().get(), 2.0);
+        assert_eq!(
+            Penalty::try_from(-2.5),
+            Err(OutOfRangeError {
+                parameter: "penalty",
+                value: -2.5,
+                min: -2.0,
+                max: 2.0,
+            }),
+        );
+        assert!(TopP::try_from(f32::NAN).is_err());
+        assert!(serde_json::from_str::<TopP>("1.5").is_err());
+
+        let request = builder()
+            .temperature(Temperature::try_from(0.5).unwrap())
+            .presence_penalty(1.0)
+            .build()
+            .unwrap();
+
+        assert_eq!(
+            serde_json::to_value(&request).unwrap()["presence_penalty"],
+            1.0,
+        );
+        assert!(matches!(
+            builder().frequency_penalty(3.0).build(),
+            Err(ChatCompletionBuilderError::ValidationError(message))
+                if message.contains("`penalty` should be between -2 and 2"),
+        ));
+    }
+
+    #[test]
+    fn zero_temperature() {
+        let body = |builder: ChatCompletionBuilder| {
+            serde_json::to_string(&builder.build().unwrap()).unwrap()
+        };
+        let builder = || ChatCompletion::builder(ModelID::Gpt3_5Turbo, []);
+
+        assert!(body(builder().temperature(0.0)).contains(r#""temperature":0.0"#));
+        assert!(body(builder().temperature(0.0).top_p(0.0)).contains(r#""top_p":0.0"#));
+        assert!(!body(builder()).contains("temperature"));
+    }
+
+    #[test]
+    fn partial_json_completion() {
+        for (json, expected) in [
+            ("", None),
+            ("{", Some(serde_json::json!({}))),
+            // An incomplete key is left out
+            (r#"{"na"#, Some(serde_json::json!({}))),
+            (r#"{"name": "Ad"#, Some(serde_json::json!({ "name": "Ad" }))),
+            (
+                r#"{"name": "Ada", "tags": ["math", "co"#,
+                Some(serde_json::json!({ "name": "Ada", "tags": ["math", "co"] })),
+            ),
+            (
+                r#"{"name": "Ada", "about": {"#,
+                Some(serde_json::json!({ "name": "Ada", "about": {} })),
+            ),
+            // Numbers and literals may not be complete yet
+            (
+                r#"{"name": "Ada", "age": 3"#,
+                Some(serde_json::json!({ "name": "Ada" })),
+            ),
+            (
+                r#"{"name": "Ada", "age": 36,"#,
+                Some(serde_json::json!({ "name": "Ada", "age": 36 })),
+            ),
+            (
+                r#"{"name": "Ada", "ok": tr"#,
+                Some(serde_json::json!({ "name": "Ada" })),
+            ),
+            // An incomplete escape sequence is left out
+            (
+                r#"{"name": "Ada", "bio": "caf\u00"#,
+                Some(serde_json::json!({ "name": "Ada", "bio": "caf" })),
+            ),
+            (
+                r#"{"name": "Ada", "bio": "caf\u00e9"#,
+                Some(serde_json::json!({ "name": "Ada", "bio": "caf\u{e9}" })),
+            ),
+            ("Sure! {", None),
+        ] {
+            assert_eq!(complete_partial_json(json), expected, "{json}");
+        }
+    }
+
+    #[tokio::test]
+    async fn partial_json() {
+        let event = |content: &str| {
+            let mut event: ChatCompletionEvent = serde_json::from_str(CONTENT_EVENT).unwrap();
+
+            event.choices[0].delta = Delta::Content {
+                content: content.to_string(),
+            };
+
+            Ok(event)
+        };
+        let values: Vec<_> = parse_partial_json(stream::iter([
+            event(r#"{"steps": ["#),
+            event(r#""one"#),
+            event(r#"", "#),
+            event(r#""two"], "done": tr"#),
+            event("ue}"),
+        ]))
+        .map(Result::unwrap)
+        .collect()
+        .await;
+
+        assert_eq!(
+            values,
+            [
+                serde_json::json!({ "steps": [] }),
+                serde_json::json!({ "steps": ["one"] }),
+                serde_json::json!({ "steps": ["one", "two"] }),
+                serde_json::json!({ "steps": ["one", "two"], "done": true }),
+            ],
+        );
+
+        let mut values = parse_partial_json(stream::iter([event("Sure! "), event("{}")]));
+
+        assert!(matches!(
+            values.next().await,
+            Some(Err(StreamError::Deserialization(_))),
+        ));
+        assert!(values.next().await.is_none());
+    }
+
+    #[tokio::test]
+    async fn first_token_timeout() {
+        let client = event_stream_stub(vec![vec![ROLE_EVENT]], true).await;
+        let mut stream = ChatCompletion::builder(
+            ModelID::Gpt3_5Turbo,
+            [ChatCompletionMessage::user("Hello!")],
+        )
+        .first_token_timeout(Duration::from_millis(200))
+        .create_stream(&client);
+
+        assert_eq!(
+            stream.next().await.unwrap().unwrap().choices[0].delta,
+            Delta::Role {
+                role: ChatCompletionMessageRole::Assistant,
+            },
+        );
+        assert!(matches!(
+            stream.next().await,
+            Some(Err(StreamError::FirstTokenTimeout)),
+        ));
+        assert!(stream.next().await.is_none());
+    }
+
+    #[tokio::test]
+    #[cfg(feature = "tokio-runtime")]
+    async fn stream_tee() {
+        let client =
+            event_stream_stub(vec![vec![ROLE_EVENT, CONTENT_EVENT, "[DONE]"]], false).await;
+        let (stream, log) = ChatCompletion::builder(
+            ModelID::Gpt3_5Turbo,
+            [ChatCompletionMessage::user("Hello!")],
+        )
+        .create_stream_tee(&client, Vec::new());
+        let events: Vec<_> = stream.map(Result::unwrap).collect().await;
+
+        assert_eq!(events.len(), 2);
+        assert_eq!(
+            String::from_utf8(log.await.unwrap().unwrap()).unwrap(),
+            format!("data: {ROLE_EVENT}\n\ndata: {CONTENT_EVENT}\n\ndata: [DONE]\n\n"),
+        );
+    }
+
+    #[tokio::test]
+    async fn mid_stream_error() {
+        const ERROR_EVENT: &str = r#"{"error": {"message": "Rate limit reached", "type": "requests", "param": null, "code": "rate_limit_exceeded"}}"#;
+        let client = event_stream_stub(
+            vec![vec![ROLE_EVENT, ERROR_EVENT, CONTENT_EVENT, "[DONE]"]],
+            false,
+        )
+        .await;
+        let mut stream = ChatCompletion::builder(
+            ModelID::Gpt3_5Turbo,
+            [ChatCompletionMessage::user("Hello!")],
+        )
+        .create_stream(&client);
+
+        assert!(matches!(stream.next().await, Some(Ok(_))));
+        assert!(matches!(
+            stream.next().await,
+            Some(Err(StreamError::Api(error))) if error == OpenAiError {
+                request_id: Some("req_stream".to_string()),
+                ..OpenAiError::new("Rate limit reached", "requests").code("rate_limit_exceeded")
+            },
+        ));
+        assert!(stream.next().await.is_none());
+    }
+
+    #[tokio::test]
+    async fn stream_to_writer() {
+        const USAGE_EVENT: &str = r#"{"id": "chatcmpl-123", "object": "chat.completion.chunk", "created": 1679325191, "model": "gpt-3.5-turbo", "choices": [], "usage": {"prompt_tokens": 9, "completion_tokens": 4, "total_tokens": 13}}"#;
+        let events = vec![
+            ROLE_EVENT,
+            CONTENT_EVENT,
+            CONTENT_EVENT,
+            USAGE_EVENT,
+            "[DONE]",
+        ];
+        let client = event_stream_stub(vec![events.clone(), events], false).await;
+        let builder = || {
+            ChatCompletion::builder(
+                ModelID::Gpt3_5Turbo,
+                [ChatCompletionMessage::user("Hello!")],
+            )
+        };
+        let mut output = Vec::new();
+        let (written, usage) = builder()
+            .create_stream_to_writer(&client, &mut output)
+            .await
+            .unwrap();
+
+        assert_eq!(output, b"Hi!Hi!");
+        assert_eq!(written, 6);
+        assert_eq!(usage.map(|usage| usage.total_tokens), Some(13));
+
+        let mut full = [0; 4];
+        let result = builder()
+            .create_stream_to_writer(&client, std::io::Cursor::new(&mut full[..]))
+            .await;
+
+        assert!(matches!(result, Err(StreamError::Write(_))));
+    }
+
+    #[tokio::test]
+    async fn abort_all_streams() {
+        let client = event_stream_stub(vec![vec![ROLE_EVENT]], true).await;
+        let mut stream = ChatCompletion::builder(
+            ModelID::Gpt3_5Turbo,
+            [ChatCompletionMessage::user("Hello!")],
+        )
+        .create_stream(&client);
+
+        assert!(stream.next().await.unwrap().is_ok());
+
+        // The stub doesn't send anything else, so only aborting ends the stream
+        let next = tokio::spawn(async move { stream.next().await.is_none() });
+
+        tokio::time::sleep(Duration::from_millis(50)).await;
+        assert_eq!(client.clone().abort_all_streams(), 1);
+        assert!(tokio::time::timeout(Duration::from_secs(1), next)
+            .await
+            .unwrap()
+            .unwrap());
+        assert_eq!(client.abort_all_streams(), 0);
+    }
+
+    #[tokio::test]
+    async fn stream_headers() {
+        let client = event_stream_stub(vec![vec![ROLE_EVENT, "[DONE]"]], false).await;
+        let mut stream = ChatCompletion::builder(
+            ModelID::Gpt3_5Turbo,
+            [ChatCompletionMessage::user("Hello!")],
+        )
+        .create_stream(&client);
+
+        assert!(stream.headers().is_none());
+        assert!(stream.connected_at().is_none());
+        assert!(stream.next().await.unwrap().is_ok());
+        assert!(stream.connected_at().unwrap() <= Instant::now());
+        assert!(stream.time_to_connect().is_some());
+        assert_eq!(
+            stream.headers().unwrap()["x-ratelimit-remaining-requests"],
+            "59",
+        );
+        assert_eq!(stream.request_id(), Some("req_stream"));
+        assert!(stream.next().await.is_none());
+    }
+
+    #[tokio::test]
+    async fn multi_line_event_data() {
+        // The stub prefixes every line with `data: `
+        let client = event_stream_stub(
+            vec![vec![
+                "{\"id\": \"chatcmpl-123\", \"object\": \"chat.completion.chunk\",\n\
+                 data: \"created\": 1679325191, \"model\": \"gpt-3.5-turbo\", \"choices\": [\n\
+                 data: {\"delta\": {\"content\": \"Hi!\"}, \"index\": 0, \"finish_reason\": null}]}",
+                "[DONE]",
+            ]],
+            false,
+        )
+        .await;
+        let mut stream = ChatCompletion::builder(
+            ModelID::Gpt3_5Turbo,
+            [ChatCompletionMessage::user("Hello!")],
+        )
+        .create_stream(&client);
+
+        assert_eq!(
+            stream.next().await.unwrap().unwrap(),
+            serde_json::from_str(CONTENT_EVENT).unwrap(),
+        );
+        assert!(stream.next().await.is_none());
+    }
+
+    #[tokio::test]
+    async fn restarting_stream() {
+        let role: ChatCompletionEvent = serde_json::from_str(ROLE_EVENT).unwrap();
+        let content: ChatCompletionEvent = serde_json::from_str(CONTENT_EVENT).unwrap();
+        let client = event_stream_stub(
+            vec![
+                vec![ROLE_EVENT],
+                vec![ROLE_EVENT, CONTENT_EVENT, "[DONE]"],
+                vec![ROLE_EVENT],
+            ],
+            false,
+        )
+        .await;
+        let builder = || {
+            ChatCompletion::builder(
+                ModelID::Gpt3_5Turbo,
+                [ChatCompletionMessage::user("Hello!")],
+            )
+        };
+        let items: Vec<_> = builder()
+            .create_restarting_stream(&client, 1)
+            .map(Result::unwrap)
+            .collect()
+            .await;
+
+        assert_eq!(
+            items,
+            [
+                RestartingStreamItem::Event(role.clone()),
+                RestartingStreamItem::Restarted { restarts: 1 },
+                RestartingStreamItem::Event(role.clone()),
+                RestartingStreamItem::Event(content),
+            ],
+        );
+
+        // The third connection drops again, with no restarts left
+        let mut stream = builder().create_restarting_stream(&client, 0);
+
+        assert_eq!(
+            stream.next().await.unwrap().unwrap(),
+            RestartingStreamItem::Event(role),
+        );
+        assert!(matches!(
+            stream.next().await,
+            Some(Err(StreamError::EventSource(
+                reqwest_eventsource::Error::StreamEnded
+            ))),
+        ));
+        assert!(stream.next().await.is_none());
+    }
+
+    #[test]
+    fn content_filter_results() {
+        let choice: ChatCompletionChoice = serde_json::from_str(
+            r#"{
+                "index": 0,
+                "message": {"role": "assistant", "content": null},
+                "finish_reason": "content_filter",
+                "content_filter_results": {
+                    "hate": {"filtered": false, "severity": "safe"},
+                    "self_harm": {"filtered": false, "severity": "safe"},
+                    "sexual": {"filtered": false, "severity": "low"},
+                    "violence": {"filtered": true, "severity": "high"},
+                    "jailbreak": {"filtered": true, "detected": true}
+                }
+            }"#,
+        )
+        .unwrap();
+        let results = choice.content_filter_results.unwrap();
+
+        assert_eq!(
+            results.violence.unwrap().severity,
+            ContentFilterSeverity::High
+        );
+        assert!(results.profanity.is_none());
+        assert_eq!(results.filtered(), ["violence", "jailbreak"]);
+
+        let choice: ChatCompletionChoice = serde_json::from_str(
+            r#"{"index": 0, "message": {"role": "assistant", "content": "Hi!"}, "finish_reason": "stop"}"#,
+        )
+        .unwrap();
+
+        assert!(choice.content_filter_results.is_none());
+    }
+
+    #[test]
+    fn finish_details() {
+        let choice: ChatCompletionChoice = serde_json::from_str(
+            r#"{
+                "index": 0,
+                "message": {"role": "assistant", "content": "A cat."},
+                "finish_details": {"type": "stop", "stop": "<|fim_suffix|>"}
+            }"#,
+        )
+        .unwrap();
+
+        assert_eq!(choice.finish_reason, "stop");
+
+        let delta = |finish: &str| {
+            serde_json::from_str::<ChatCompletionChoiceDelta>(&format!(
+                r#"{{"index": 0, "delta": {{}}{finish}}}"#
+            ))
+            .unwrap()
+            .finish_reason
+        };
+
+        assert_eq!(
+            delta(r#", "finish_details": {"type": "max_tokens"}"#).as_deref(),
+            Some("max_tokens"),
+        );
+        assert_eq!(
+            delta(r#", "finish_reason": "length""#).as_deref(),
+            Some("length")
+        );
+        assert_eq!(delta(r#", "finish_reason": null"#), None);
+        assert_eq!(delta(""), None);
+        // Both, with whichever isn't null taken, `finish_reason` first
+        assert_eq!(
+            delta(r#", "finish_reason": null, "finish_details": {"type": "stop"}"#).as_deref(),
+            Some("stop"),
+        );
+        assert_eq!(
+            delta(r#", "finish_reason": "length", "finish_details": {"type": "stop"}"#).as_deref(),
+            Some("length"),
+        );
+
+        let choice: ChatCompletionChoice = serde_json::from_str(
+            r#"{
+                "index": 0,
+                "message": {"role": "assistant", "content": "A cat."},
+                "finish_reason": null,
+                "finish_details": {"type": "stop"}
+            }"#,
+        )
+        .unwrap();
+
+        assert_eq!(choice.finish_reason, "stop");
+        assert!(serde_json::from_str::<ChatCompletionChoice>(
+            r#"{"index": 0, "message": {"role": "assistant", "content": "A cat."}}"#,
+        )
+        .is_err());
+    }
+
+    #[test]
+    fn strict() {
+        let builder = || ChatCompletion::builder(ModelID::Gpt3_5Turbo, []);
+
+        assert!(builder().temperature(0.2).strict().is_ok());
+        assert!(builder().top_p(0.1).strict().is_ok());
+        assert!(matches!(
+            builder().temperature(0.2).top_p(0.1).strict(),
+            Err(ChatCompletionBuilderError::ValidationError(message))
+                if message.contains("`temperature` and `top_p`"),
+        ));
+        // Strict mode is opt-in
+        assert!(builder().temperature(0.2).top_p(0.1).build().is_ok());
+    }
+
+    #[test]
+    fn audio() {
+        let request = ChatCompletion::builder(
+            ModelID::Gpt3_5Turbo,
+            [ChatCompletionMessage::user(
+                "Is a golden retriever a good family dog?",
+            )],
+        )
+        .modalities([Modality::Text, Modality::Audio])
+        .audio(AudioOutput {
+            voice: Voice::Alloy,
+            format: AudioFormat::Wav,
+        })
+        .build()
+        .unwrap();
+        let request = serde_json::to_value(request).unwrap();
+        let text_request = serde_json::to_value(
+            ChatCompletion::builder(ModelID::Gpt3_5Turbo, [])
+                .build()
+                .unwrap(),
+        )
+        .unwrap();
+
+        assert_eq!(request["modalities"], serde_json::json!(["text", "audio"]));
+        assert_eq!(
+            request["audio"],
+            serde_json::json!({"voice": "alloy", "format": "wav"}),
+        );
+        assert!(text_request.get("modalities").is_none());
+        assert!(text_request.get("audio").is_none());
+
+        let message: ChatCompletionMessage = serde_json::from_str(
+            r#"{
+                "role": "assistant",
+                "content": null,
+                "audio": {
+                    "id": "audio_abc123",
+                    "data": "UklGRg==",
+                    "expires_at": 1729018505,
+                    "transcript": "Yes, golden retrievers are known to be a great family dog."
+                }
+            }"#,
+        )
+        .unwrap();
+
+        assert!(message.content.is_none());
+        assert_eq!(message.audio.unwrap().id, "audio_abc123");
+    }
+
+    #[test]
+    fn web_search() {
+        let request = ChatCompletion::builder(
+            ModelID::Gpt3_5Turbo,
+            [ChatCompletionMessage::user("What's new in Rust?")],
+        )
+        .web_search_options(WebSearchOptions {
+            search_context_size: Some(SearchContextSize::Low),
+            user_location: Some(UserLocation::Approximate {
+                approximate: ApproximateLocation {
+                    country: Some("GB".to_string()),
+                    ..Default::default()
+                },
+            }),
+        })
+        .build()
+        .unwrap();
+
+        assert_eq!(
+            serde_json::to_value(&request).unwrap()["web_search_options"],
+            serde_json::json!({
+                "search_context_size": "low",
+                "user_location": {"type": "approximate", "approximate": {"country": "GB"}}
+            }),
+        );
+
+        let message: ChatCompletionMessage = serde_json::from_str(
+            r#"{
+                "role": "assistant",
+                "content": "Rust 1.80 was released.",
+                "annotations": [
+                    {
+                        "type": "url_citation",
+                        "url_citation": {
+                            "end_index": 23,
+                            "start_index": 0,
+                            "title": "Announcing Rust 1.80.0",
+                            "url": "https://blog.rust-lang.org/2024/07/25/Rust-1.80.0.html"
+                        }
+                    },
+                    {
+                        "type": "file_citation",
+                        "text": "【4:0†source】",
+                        "file_citation": {"file_id": "file-abc123"},
+                        "start_index": 23,
+                        "end_index": 35
+                    },
+                    {"type": "file_path"}
+                ]
+            }"#,
+        )
+        .unwrap();
+
+        assert_eq!(
+            message.annotations.unwrap(),
+            [
+                Annotation::UrlCitation {
+                    url_citation: UrlCitation {
+                        start_index: 0,
+                        end_index: 23,
+                        url: "https://blog.rust-lang.org/2024/07/25/Rust-1.80.0.html".to_string(),
+                        title: "Announcing Rust 1.80.0".to_string(),
+                    },
+                },
+                Annotation::FileCitation {
+                    text: "【4:0†source】".to_string(),
+                    file_citation: FileCitation {
+                        file_id: "file-abc123".to_string(),
+                    },
+                    start_index: 23,
+                    end_index: 35,
+                },
+                Annotation::Unknown,
+            ],
+        );
+
+        let annotations = |message: &str| {
+            serde_json::from_str::<ChatCompletionMessage>(message)
+                .unwrap()
+                .annotations
+        };
+
+        assert_eq!(
+            annotations(r#"{"role": "assistant", "content": "Hi."}"#),
+            None
+        );
+        assert_eq!(
+            annotations(r#"{"role": "assistant", "content": "Hi.", "annotations": []}"#),
+            Some(Vec::new()),
+        );
+    }
+
+    #[test]
+    fn continue_request() {
+        let request = ChatCompletion::builder(
+            ModelID::Gpt3_5Turbo,
+            [ChatCompletionMessage::user("Name a color.")],
+        )
+        .temperature(0.5)
+        .seed(42)
+        .build()
+        .unwrap();
+        let completion: ChatCompletion =
+            serde_json::from_value(chat_completion_json("Blue.", "stop")).unwrap();
+        let follow_up = completion
+            .continue_request(request.clone(), ChatCompletionMessage::user("Another one."))
+            .build()
+            .unwrap();
+
+        assert_eq!(
+            follow_up,
+            ChatCompletionRequest {
+                messages: vec![
+                    ChatCompletionMessage::user("Name a color."),
+                    ChatCompletionMessage::assistant("Blue."),
+                    ChatCompletionMessage::user("Another one."),
+                ],
+                ..request.clone()
+            },
+        );
+        assert_eq!(
+            ChatCompletionBuilder::from(request.clone())
+                .build()
+                .unwrap(),
+            request,
+        );
+    }
+
+    #[test]
+    #[cfg(feature = "tokenizer")]
+    fn max_tokens_auto() {
+        let build = |model: ModelID, content: String| {
+            ChatCompletion::builder(model, [ChatCompletionMessage::user(content)])
+                .max_tokens_auto()
+                .build()
+        };
+
+        assert_eq!(
+            build(ModelID::Gpt3_5Turbo, "Hello!".to_string())
+                .unwrap()
+                .max_tokens,
+            Some(4096),
+        );
+
+        let long = build(ModelID::Gpt3_5Turbo, "hello ".repeat(16_300))
+            .unwrap()
+            .max_tokens
+            .unwrap();
+
+        assert!(long > 0 && long < 4096, "{long}");
+        assert!(build(ModelID::Gpt3_5Turbo, "hello ".repeat(17_000)).is_err());
+        assert!(build(
+            ModelID::Custom("my-model".to_string()),
+            "Hello!".to_string()
+        )
+        .is_err());
+        #[allow(deprecated)]
+        let fixed = ChatCompletion::builder(ModelID::Gpt3_5Turbo, []).max_tokens(10u64);
+
+        assert_eq!(fixed.build().unwrap().max_tokens, Some(10));
+    }
+
+    #[tokio::test]
+    #[cfg(feature = "tokenizer")]
+    async fn max_tokens_auto_errors() {
+        let stub = StubLayer::responses(vec![]);
+        let client = stub.client();
+        let create = |model: ModelID, content: String| {
+            ChatCompletion::builder(model, [ChatCompletionMessage::user(content)])
+                .max_tokens_auto()
+                .create(&client)
+        };
+        let unknown = create(
+            ModelID::Custom("my-model".to_string()),
+            "Hello!".to_string(),
+        )
+        .await
+        .unwrap()
+        .unwrap_err();
+        let overflow = create(ModelID::Gpt3_5Turbo, "hello ".repeat(17_000))
+            .await
+            .unwrap()
+            .unwrap_err();
+
+        assert_eq!(unknown.error_type, "invalid_request_error");
+        assert!(unknown.message.contains("my-model"), "{}", unknown.message);
+        assert_eq!(overflow.error_type, "invalid_request_error");
+        assert!(stub.requests().is_empty());
+    }
+
+    #[tokio::test]
+    async fn trim_response() {
+        let stub = StubLayer::responses(vec![
+            chat_completion_json("\n\nHello there!\n", "stop"),
+            chat_completion_json("\n\nHello there!\n", "stop"),
+        ]);
+        let client = stub.client();
+        let builder = || {
+            ChatCompletion::builder(
+                ModelID::Gpt3_5Turbo,
+                [ChatCompletionMessage::user("Hello!")],
+            )
+        };
+        let exact = builder().create(&client).await.unwrap().unwrap();
+        let trimmed = builder()
+            .trim_response()
+            .create(&client)
+            .await
+            .unwrap()
+            .unwrap();
+
+        assert_eq!(exact.content().unwrap(), "\n\nHello there!\n");
+        assert_eq!(trimmed.content().unwrap(), "Hello there!");
+    }
+
+    #[test]
+    fn max_completion_tokens() {
+        #[allow(deprecated)]
+        let legacy = ChatCompletion::builder(ModelID::Gpt3_5Turbo, [])
+            .max_tokens(10u64)
+            .build()
+            .unwrap();
+        let current = ChatCompletion::builder(ModelID::Gpt3_5Turbo, [])
+            .max_completion_tokens(10u64)
+            .build()
+            .unwrap();
+        let legacy = serde_json::to_value(legacy).unwrap();
+        let current = serde_json::to_value(current).unwrap();
+
+        assert_eq!(legacy["max_tokens"], 10);
+        assert!(legacy.get("max_completion_tokens").is_none());
+        assert_eq!(current["max_completion_tokens"], 10);
+        assert!(current.get("max_tokens").is_none());
+    }
+
+    #[test]
+    fn apply_deltas() {
+        let events = [
+            ROLE_EVENT,
+            CONTENT_EVENT,
+            CONTENT_EVENT,
+            r#"{"id": "chatcmpl-123", "object": "chat.completion.chunk", "created": 1679325191, "model": "gpt-3.5-turbo", "choices": [{"delta": {"content": "Bye!"}, "index": 1, "finish_reason": null}]}"#,
+            r#"{"id": "chatcmpl-123", "object": "chat.completion.chunk", "created": 1679325191, "model": "gpt-3.5-turbo", "choices": [{"delta": {}, "index": 0, "finish_reason": "stop"}]}"#,
+        ];
+        let mut buffers = HashMap::new();
+
+        for event in events {
+            serde_json::from_str::<ChatCompletionEvent>(event)
+                .unwrap()
+                .apply_to(&mut buffers);
+        }
+
+        assert_eq!(
+            buffers,
+            HashMap::from([(0, "Hi!Hi!".to_string()), (1, "Bye!".to_string())]),
+        );
+
+        let mut buffer = "Hi!".to_string();
+
+        Delta::EndOfStream {}.apply_to(&mut buffer);
+        Delta::Role {
+            role: ChatCompletionMessageRole::Assistant,
+        }
+        .apply_to(&mut buffer);
+
+        assert_eq!(buffer, "Hi!");
+    }
+
+    #[test]
+    fn from_events() {
+        let events: Vec<ChatCompletionEvent> = [
+            r#"{"id": "chatcmpl-123", "object": "chat.completion.chunk", "created": 1679325191, "model": "gpt-3.5-turbo", "choices": [{"delta": {"role": "assistant"}, "index": 1, "finish_reason": null}]}"#,
+            ROLE_EVENT,
+            CONTENT_EVENT,
+            r#"{"id": "chatcmpl-123", "object": "chat.completion.chunk", "created": 1679325191, "model": "gpt-3.5-turbo", "choices": [{"delta": {"content": "Bye!"}, "index": 1, "finish_reason": null}]}"#,
+            r#"{"id": "chatcmpl-123", "object": "chat.completion.chunk", "created": 1679325191, "model": "gpt-3.5-turbo", "choices": [{"delta": {}, "index": 1, "finish_reason": "stop"}, {"delta": {}, "index": 0, "finish_reason": "length"}]}"#,
+            r#"{"id": "chatcmpl-123", "object": "chat.completion.chunk", "created": 1679325191, "model": "gpt-3.5-turbo", "choices": [], "usage": {"prompt_tokens": 9, "completion_tokens": 4, "total_tokens": 13}}"#,
+        ]
+        .into_iter()
+        .map(|event| serde_json::from_str(event).unwrap())
+        .collect();
+        let completion = ChatCompletion::from_events(&events).unwrap();
+        let choices: Vec<_> = completion
+            .choices
+            .iter()
+            .map(|choice| {
+                (
+                    choice.index,
+                    choice.message.content.as_deref().unwrap(),
+                    choice.finish_reason.as_str(),
+                )
+            })
+            .collect();
+
+        assert_eq!(completion.id, "chatcmpl-123");
+        assert_eq!(choices, [(0, "Hi!", "length"), (1, "Bye!", "stop")]);
+        assert_eq!(completion.usage.unwrap().total_tokens, 13);
+        assert!(ChatCompletion::from_events(&[]).is_none());
+
+        let choice: ChatCompletionChoice = serde_json::from_value(serde_json::json!({
+            "message": {"role": "assistant", "content": "Hi!"},
+            "finish_reason": "stop",
+        }))
+        .unwrap();
+
+        assert_eq!(choice.index, 0);
+    }
+
+    #[test]
+    fn unknown_variants() {
+        let message: ChatCompletionMessage =
+            serde_json::from_str(r#"{"role": "critic", "content": "Be terse."}"#).unwrap();
+
+        assert_eq!(
+            message.role,
+            ChatCompletionMessageRole::Other("critic".to_string())
+        );
+        assert_eq!(
+            serde_json::to_value(&message).unwrap()["role"],
+            serde_json::json!("critic"),
+        );
+        assert_eq!(message.to_transcript_line(), "critic: Be terse.");
+        assert_eq!(
+            serde_json::from_str::<ServiceTier>(r#""scale""#).unwrap(),
+            ServiceTier::Unknown,
+        );
+    }
+
+    #[test]
+    fn refusal() {
+        let message: ChatCompletionMessage = serde_json::from_str(
+            r#"{
+                "role": "assistant",
+                "content": null,
+                "refusal": "I'm sorry, I can't help with that."
+            }"#,
+        )
+        .unwrap();
+
+        assert!(message.content.is_none());
+        assert_eq!(
+            message.refusal.as_deref(),
+            Some("I'm sorry, I can't help with that."),
+        );
+        // `null` round-trips, rather than becoming an empty string
+        assert_eq!(
+            serde_json::to_value(&message).unwrap()["content"],
+            serde_json::Value::Null,
+        );
+
+        // Without `content` at all
+        let message: ChatCompletionMessage = serde_json::from_str(
+            r#"{"role": "assistant", "refusal": "I'm sorry, I can't help with that."}"#,
+        )
+        .unwrap();
+
+        assert!(message.content.is_none());
+        assert!(message.refusal.is_some());
+        assert!(
+            serde_json::to_value(ChatCompletionMessage::assistant("Hi!"))
+                .unwrap()
+                .get("refusal")
+                .is_none()
+        );
+    }
+
+    #[test]
+    fn compare_backend() {
+        let completion = |system_fingerprint: Option<&str>| -> ChatCompletion {
+            serde_json::from_value(serde_json::json!({
+                "id": "chatcmpl-123",
+                "object": "chat.completion",
+                "created": 1679325191,
+                "model": "gpt-3.5-turbo",
+                "choices": [],
+                "system_fingerprint": system_fingerprint,
+            }))
+            .unwrap()
+        };
+        let first = completion(Some("fp_44709d6fcb"));
+
+        assert_eq!(
+            first.compare_backend(&completion(Some("fp_44709d6fcb"))),
+            Reproducibility::SameBackend,
+        );
+        assert_eq!(
+            first.compare_backend(&completion(Some("fp_7c0ebdc2a4"))),
+            Reproducibility::DifferentBackend,
+        );
+        assert_eq!(
+            first.compare_backend(&completion(None)),
+            Reproducibility::Unknown,
+        );
+    }
+
+    #[test]
+    fn service_tier() {
+        let request = ChatCompletion::builder(
+            ModelID::Gpt3_5Turbo,
+            [ChatCompletionMessage::user("Hello!")],
+        )
+        .service_tier(ServiceTier::Flex)
+        .build()
+        .unwrap();
+
+        assert_eq!(
+            serde_json::to_value(&request).unwrap()["service_tier"],
+            "flex"
+        );
+
+        let completion: ChatCompletion = serde_json::from_value(serde_json::json!({
+            "id": "chatcmpl-123",
+            "object": "chat.completion",
+            "created": 1679325191,
+            "model": "gpt-3.5-turbo",
+            "choices": [],
+            "service_tier": "default",
+        }))
+        .unwrap();
+
+        assert_eq!(completion.service_tier, Some(ServiceTier::Default));
+    }
+
+    #[test]
+    fn empty_choices() {
+        let chat_completion: ChatCompletion = serde_json::from_str(
+            r#"{
+                "id": "chatcmpl-6wBU7HGxEXqdShNC81ZlfkOLDM0MF",
+                "object": "chat.completion",
+                "created": 1679325191,
+                "model": "gpt-3.5-turbo",
+                "choices": []
+            }"#,
+        )
+        .unwrap();
+
+        assert!(chat_completion.first_choice().is_none());
+        assert!(chat_completion.content().is_none());
+    }
+
+    #[test]
+    fn round_trip() {
+        let body = serde_json::json!({
+            "id": "chatcmpl-6wBU7HGxEXqdShNC81ZlfkOLDM0MF",
+            "object": "chat.completion",
+            "created": 1679325191,
+            "model": "gpt-3.5-turbo",
+            "choices": [{
+                "index": 0,
+                "message": {"role": "assistant", "content": "\n\nHello there! How can I assist you today?"},
+                "finish_reason": "stop"
+            }],
+            "usage": {"prompt_tokens": 9, "completion_tokens": 12, "total_tokens": 21}
+        });
+        let chat_completion: ChatCompletion = serde_json::from_value(body.clone()).unwrap();
+
+        assert_eq!(serde_json::to_value(chat_completion).unwrap(), body);
+    }
+
+    #[test]
+    fn token_bytes_split_character() {
+        let events = [
+            r#"{"content": [{"token": "\\xf0\\x9f", "logprob": -0.1, "bytes": [240, 159], "top_logprobs": []}]}"#,
+            r#"{"content": [{"token": "\\xa6\\x80", "logprob": -0.2, "bytes": [166, 128], "top_logprobs": []}]}"#,
+            r#"{"content": [{"token": "!", "logprob": -0.3, "bytes": [33], "top_logprobs": []}]}"#,
+        ];
+        let mut decoder = TokenBytesDecoder::new();
+        let mut decoded = Vec::new();
+
+        for event in events {
+            let logprobs: ChatCompletionLogprobs = serde_json::from_str(event).unwrap();
+
+            for token in logprobs.content.unwrap() {
+                decoded.push(decoder.push(&token.bytes.unwrap()));
+            }
+        }
+
+        assert_eq!(decoded, ["", "🦀", "!"]);
+        assert_eq!(decoder.finish(), "");
+    }
+
+    #[test]
+    fn token_bytes_invalid() {
+        let mut decoder = TokenBytesDecoder::new();
+
+        assert_eq!(decoder.push(&[b'a', 0xff, b'b', 0xf0, 0x9f]), "a\u{fffd}b");
+        assert_eq!(decoder.finish(), "\u{fffd}");
     }
 
     #[test]
@@ -241,8 +4414,13 @@ mod tests {
                     delta: Delta::Role {
                         role: ChatCompletionMessageRole::Assistant
                     },
-                    finish_reason: None
-                }]
+                    finish_reason: None,
+                    logprobs: None,
+                    content_filter_results: None,
+                }],
+                usage: None,
+                system_fingerprint: None,
+                service_tier: None,
             }
         );
         assert_eq!(
@@ -257,8 +4435,13 @@ mod tests {
                     delta: Delta::Content {
                         content: "foobar".into()
                     },
-                    finish_reason: None
-                }]
+                    finish_reason: None,
+                    logprobs: None,
+                    content_filter_results: None,
+                }],
+                usage: None,
+                system_fingerprint: None,
+                service_tier: None,
             }
         );
         assert_eq!(
@@ -271,8 +4454,13 @@ mod tests {
                 choices: vec![ChatCompletionChoiceDelta {
                     index: 0,
                     delta: Delta::EndOfStream {},
-                    finish_reason: Some("stop".into())
-                }]
+                    finish_reason: Some("stop".into()),
+                    logprobs: None,
+                    content_filter_results: None,
+                }],
+                usage: None,
+                system_fingerprint: None,
+                service_tier: None,
             }
         );
     }
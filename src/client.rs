@@ -0,0 +1,1347 @@
+//! The client every request is sent through, and the configuration it carries.
+
+use super::{
+    layer::{self, ConcurrencyLimitLayer, Layer, Next, RetryLayer},
+    openai_get, openai_post, ApiResponseOrError, RequestError, Usage,
+};
+use futures::{
+    stream::{self, AbortHandle, Abortable},
+    Stream, StreamExt,
+};
+use openai_bootstrap::{authorization, OpenAiError, BASE_URL};
+#[cfg(any(feature = "native-tls", feature = "rustls-tls"))]
+use reqwest::{Certificate, Identity};
+use reqwest::{Method, Proxy, RequestBuilder, StatusCode};
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::HashMap,
+    env, fmt,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc, Mutex,
+    },
+    time::Duration,
+};
+
+/// The `User-Agent` sent when none is configured, e.g. `openai/1.0.0`.
+pub const DEFAULT_USER_AGENT: &str =
+    concat!(env!("CARGO_PKG_NAME"), "/", env!("CARGO_PKG_VERSION"));
+
+type RequestHook = Arc<dyn Fn(RequestBuilder) -> RequestBuilder + Send + Sync>;
+type MetricsHook = Arc<dyn Fn(&RequestMetrics) + Send + Sync>;
+type RetryPredicate = Arc<dyn Fn(Option<StatusCode>, u32) -> bool + Send + Sync>;
+
+/// Sends requests to the OpenAI API.
+///
+/// Cloning a client is cheap, the underlying connection pool is shared between clones.
+#[derive(Clone)]
+pub struct Client {
+    http: reqwest::Client,
+    base_url: String,
+    credentials: Option<Credentials>,
+    on_request: Option<RequestHook>,
+    on_metrics: Option<MetricsHook>,
+    /// Every request is sent through these, outermost first, see [`ClientBuilder::layer`].
+    layers: Arc<[Box<dyn Layer>]>,
+    streams: Arc<Streams>,
+}
+
+/// The streams created through a client and its clones that haven't ended yet,
+/// see [`Client::abort_all_streams`].
+#[derive(Default)]
+struct Streams {
+    next_id: AtomicU64,
+    handles: Mutex<HashMap<u64, AbortHandle>>,
+}
+
+/// Removes a stream from [`Streams`] once it's dropped.
+struct StreamRegistration {
+    streams: Arc<Streams>,
+    id: u64,
+}
+
+/// Configures a [`Client`] before it is built.
+#[derive(Default)]
+pub struct ClientBuilder {
+    http: reqwest::ClientBuilder,
+    http_client: Option<reqwest::Client>,
+    base_url: Option<String>,
+    credentials: Option<Credentials>,
+    user_agent: Option<String>,
+    app_name: Option<String>,
+    on_request: Option<RequestHook>,
+    on_metrics: Option<MetricsHook>,
+    retry_policy: Option<RetryPolicy>,
+    max_concurrent_requests: Option<usize>,
+    layers: Vec<Box<dyn Layer>>,
+}
+
+/// What requests are authorized with.
+///
+/// The API key is redacted from the [`Debug`](fmt::Debug) output, so credentials are safe to log.
+#[derive(Clone, PartialEq, Eq)]
+pub struct Credentials {
+    api_key: String,
+    organization: Option<String>,
+    project: Option<String>,
+}
+
+/// An error creating a client from the environment, see [`Client::from_env`].
+#[derive(Debug)]
+pub enum FromEnvError {
+    /// Neither `OPENAI_API_KEY` nor `OPENAI_KEY` is defined.
+    MissingApiKey,
+    /// The client couldn't be built.
+    Client(reqwest::Error),
+}
+
+/// Why [`Client::health_check`] failed.
+#[derive(Debug)]
+pub enum HealthCheckError {
+    /// The client has no [`Credentials`] and neither the `OPENAI_API_KEY` nor the `OPENAI_KEY`
+    /// environment variable is set,
+    /// so no request was sent.
+    MissingCredentials,
+    /// The API rejected the credentials, `401 Unauthorized`.
+    Unauthorized,
+    /// The API responded with another unsuccessful status, e.g. `503 Service Unavailable`.
+    Status(StatusCode),
+    /// The API couldn't be reached, or its response couldn't be received.
+    Request(RequestError),
+}
+
+/// Decides which failed requests are sent again, see [`ClientBuilder::retry_policy`].
+///
+/// A request is retried when it fails with a status, or without a response at all
+/// because connecting or waiting for the response timed out,
+/// until it has been retried `max_retries` times. Whether a failure is retried is decided by,
+/// in order:
+///
+/// 1. the rule for its status, see [`RetryPolicy::status`],
+/// 2. the predicate, see [`RetryPolicy::retry_if`],
+/// 3. by default, `429 Too Many Requests`, server errors and timeouts are retried.
+///
+/// Retries wait for the backoff, doubling it after every retry.
+#[derive(Clone)]
+pub struct RetryPolicy {
+    max_retries: u32,
+    backoff: Duration,
+    statuses: Vec<(StatusCode, u32)>,
+    predicate: Option<RetryPredicate>,
+}
+
+/// Measurements of a single request, see [`ClientBuilder::on_metrics`].
+#[derive(Debug, Clone, Copy)]
+pub struct RequestMetrics<'a> {
+    /// The route of the API that was requested, e.g. `chat/completions`.
+    pub route: &'a str,
+    /// The status the API responded with,
+    /// `None` if the request failed before a response was received.
+    pub status: Option<StatusCode>,
+    /// Time from sending the request to having received the whole response.
+    pub duration: Duration,
+    /// Tokens used by the request, if the response reports it in the same shape as completions do.
+    pub usage: Option<Usage>,
+    /// The `x-request-id` header of the response, which OpenAI's support asks for,
+    /// `None` if the request failed or the header was stripped, e.g. by a proxy.
+    pub request_id: Option<&'a str>,
+}
+
+impl Default for Client {
+    fn default() -> Self {
+        ClientBuilder::default()
+            .build()
+            .expect("default client should be buildable")
+    }
+}
+
+/// A client sending requests with the given HTTP client, see [`ClientBuilder::http_client`].
+impl From<reqwest::Client> for Client {
+    fn from(http: reqwest::Client) -> Self {
+        ClientBuilder::default()
+            .http_client(http)
+            .build()
+            .expect("a client with its own HTTP client should be buildable")
+    }
+}
+
+impl Client {
+    /// Creates a client with the default configuration.
+    ///
+    /// Proxies defined by the `HTTP_PROXY`, `HTTPS_PROXY` and `ALL_PROXY`
+    /// environment variables are respected.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Creates a client configured by environment variables, also loading them from a `.env` file:
+    ///
+    /// - `OPENAI_API_KEY` (or `OPENAI_KEY`), required
+    /// - `OPENAI_ORG_ID`, see [`Credentials::organization`]
+    /// - `OPENAI_PROJECT`, see [`Credentials::project`]
+    /// - `OPENAI_BASE_URL`, see [`ClientBuilder::base_url`]
+    pub fn from_env() -> Result<Self, FromEnvError> {
+        dotenvy::dotenv().ok();
+
+        ClientBuilder::from_vars(|name| env::var(name).ok())?
+            .build()
+            .map_err(FromEnvError::Client)
+    }
+
+    pub fn builder() -> ClientBuilder {
+        ClientBuilder::default()
+    }
+
+    /// Starts a request to the given route of the API.
+    pub(crate) fn request(&self, method: Method, route: &str) -> RequestBuilder {
+        self.http.request(method, self.base_url.clone() + route)
+    }
+
+    /// Authorizes a request and passes it through the `on_request` hook, right before it's sent.
+    ///
+    /// Without configured [`Credentials`], the API key is read from the `OPENAI_API_KEY`
+    /// (or `OPENAI_KEY`) environment variable.
+    pub(crate) fn finish(&self, request: RequestBuilder) -> RequestBuilder {
+        let request = match &self.credentials {
+            Some(credentials) => credentials.authorize(request),
+            None => authorization!(request),
+        };
+
+        match &self.on_request {
+            Some(on_request) => on_request(request),
+            None => request,
+        }
+    }
+
+    pub(crate) fn on_metrics(&self) -> Option<&(dyn Fn(&RequestMetrics) + Send + Sync)> {
+        self.on_metrics.as_deref()
+    }
+
+    /// Aborts every stream created through this client, or its clones, that hasn't ended yet,
+    /// e.g. when shutting down, returning how many there were.
+    ///
+    /// Aborted streams end as if the completion was done,
+    /// closing their connections as soon as they're next polled (tasks waiting for them are woken) or dropped.
+    pub fn abort_all_streams(&self) -> usize {
+        let handles: Vec<_> = self.streams.handles.lock().unwrap().drain().collect();
+
+        for (_, handle) in &handles {
+            handle.abort();
+        }
+
+        handles.len()
+    }
+
+    /// Sends a `GET` request to a route of the API this library doesn't cover yet, e.g. `batches`,
+    /// returning the response as untyped JSON to deserialize into your own types.
+    ///
+    /// The request is authorized, hooked, retried and metered like any other.
+    pub async fn raw_get(&self, route: &str) -> ApiResponseOrError<serde_json::Value> {
+        Ok(openai_get(self, route).await?.and_then(untyped))
+    }
+
+    /// Like [`Client::raw_get`], but sends a `POST` request with the given JSON body.
+    pub async fn raw_post<J>(&self, route: &str, body: &J) -> ApiResponseOrError<serde_json::Value>
+    where
+        J: Serialize + ?Sized,
+    {
+        Ok(openai_post(self, route, body).await?.and_then(untyped))
+    }
+
+    /// Checks that the API can be reached and accepts the client's credentials,
+    /// e.g. for a readiness probe, by listing the models, the cheapest authorized request.
+    ///
+    /// The request goes through the layers like any other, so it's retried according to the retry policy.
+    pub async fn health_check(&self) -> Result<(), HealthCheckError> {
+        dotenvy::dotenv().ok();
+        self.check_credentials(|name| env::var(name).ok())?;
+
+        let request = layer::Request {
+            route: "models".to_string(),
+            http: self
+                .finish(self.request(Method::GET, "models"))
+                .build()
+                .map_err(|error| HealthCheckError::Request(error.into()))?,
+        };
+        let response = self
+            .send(request)
+            .await
+            .map_err(HealthCheckError::Request)?;
+
+        match response.status {
+            status if status.is_success() => Ok(()),
+            StatusCode::UNAUTHORIZED => Err(HealthCheckError::Unauthorized),
+            status => Err(HealthCheckError::Status(status)),
+        }
+    }
+
+    /// Fails if requests can't be authorized, without [`Credentials`]
+    /// or the `OPENAI_API_KEY` and `OPENAI_KEY` variables [`Client::finish`] falls back to.
+    fn check_credentials(
+        &self,
+        var: impl Fn(&str) -> Option<String>,
+    ) -> Result<(), HealthCheckError> {
+        match (
+            &self.credentials,
+            var("OPENAI_API_KEY").or_else(|| var("OPENAI_KEY")),
+        ) {
+            (None, None) => Err(HealthCheckError::MissingCredentials),
+            _ => Ok(()),
+        }
+    }
+
+    /// Makes a stream abortable by [`Client::abort_all_streams`].
+    pub(crate) fn register_stream<S>(&self, stream: S) -> impl Stream<Item = S::Item> + Send + Unpin
+    where
+        S: Stream + Send + Unpin + 'static,
+        S::Item: Send,
+    {
+        let (handle, abort_registration) = AbortHandle::new_pair();
+        let id = self.streams.next_id.fetch_add(1, Ordering::Relaxed);
+
+        self.streams.handles.lock().unwrap().insert(id, handle);
+
+        let registration = StreamRegistration {
+            streams: self.streams.clone(),
+            id,
+        };
+
+        Box::pin(stream::unfold(
+            Some((Abortable::new(stream, abort_registration), registration)),
+            |state| async move {
+                let (mut stream, registration) = state?;
+                // Once the stream ends or is aborted, it's dropped along with its connection
+                let item = stream.next().await?;
+
+                Some((item, Some((stream, registration))))
+            },
+        ))
+    }
+
+    /// Sends a finished request through the layers, see [`ClientBuilder::layer`].
+    pub(crate) async fn send(
+        &self,
+        request: layer::Request,
+    ) -> Result<layer::Response, RequestError> {
+        Next::new(&self.http, &self.layers).run(request).await
+    }
+}
+
+/// Tells errors apart from the untyped JSON of a successful response,
+/// since any JSON, including an error, deserializes as untyped JSON.
+fn untyped(json: serde_json::Value) -> Result<serde_json::Value, OpenAiError> {
+    match json.get("error").map(OpenAiError::deserialize) {
+        Some(Ok(error)) => Err(error),
+        _ => Ok(json),
+    }
+}
+
+impl fmt::Debug for Client {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Client")
+            .field("http", &self.http)
+            .field("base_url", &self.base_url)
+            .field("credentials", &self.credentials)
+            .field("on_request", &self.on_request.is_some())
+            .field("on_metrics", &self.on_metrics.is_some())
+            .field("layers", &self.layers.len())
+            .finish()
+    }
+}
+
+impl Drop for StreamRegistration {
+    fn drop(&mut self) {
+        self.streams.handles.lock().unwrap().remove(&self.id);
+    }
+}
+
+impl RetryPolicy {
+    /// Retries a request at most `max_retries` times, waiting half a second before the first retry.
+    ///
+    /// With `max_retries` set to 0, requests are sent exactly like without a policy.
+    pub fn new(max_retries: u32) -> Self {
+        RetryPolicy {
+            max_retries,
+            backoff: Duration::from_millis(500),
+            statuses: Vec::new(),
+            predicate: None,
+        }
+    }
+
+    /// Waits for the given duration before the first retry, doubling it after every retry.
+    pub fn backoff(mut self, backoff: Duration) -> Self {
+        self.backoff = backoff;
+        self
+    }
+
+    /// Retries requests failing with the given status at most `max_retries` times,
+    /// 0 to never retry them.
+    ///
+    /// Retries for other statuses count towards the limit of the whole policy, but not towards this one.
+    pub fn status(mut self, status: StatusCode, max_retries: u32) -> Self {
+        self.statuses.retain(|(other, _)| *other != status);
+        self.statuses.push((status, max_retries));
+        self
+    }
+
+    /// Decides whether to retry failures without a rule for their status.
+    ///
+    /// The predicate is called with the failure's status, `None` if it timed out,
+    /// and how many times the request has been retried so far.
+    pub fn retry_if(
+        mut self,
+        predicate: impl Fn(Option<StatusCode>, u32) -> bool + Send + Sync + 'static,
+    ) -> Self {
+        self.predicate = Some(Arc::new(predicate));
+        self
+    }
+
+    /// How long to wait before retrying a request that has been retried `retries` times so far.
+    pub(crate) fn delay(&self, retries: u32) -> Duration {
+        self.backoff * 2u32.saturating_pow(retries)
+    }
+
+    pub(crate) fn should_retry(
+        &self,
+        status: Option<StatusCode>,
+        retried: &[Option<StatusCode>],
+    ) -> bool {
+        let retries = retried.len() as u32;
+
+        if retries >= self.max_retries {
+            return false;
+        }
+
+        if let Some(status) = status {
+            if let Some((_, max_retries)) = self.statuses.iter().find(|(other, _)| *other == status)
+            {
+                let retries = retried
+                    .iter()
+                    .filter(|other| **other == Some(status))
+                    .count();
+
+                return (retries as u32) < *max_retries;
+            }
+        }
+
+        match &self.predicate {
+            Some(predicate) => predicate(status, retries),
+            None => status.is_none_or(|status| {
+                status == StatusCode::TOO_MANY_REQUESTS || status.is_server_error()
+            }),
+        }
+    }
+}
+
+impl fmt::Debug for RetryPolicy {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("RetryPolicy")
+            .field("max_retries", &self.max_retries)
+            .field("backoff", &self.backoff)
+            .field("statuses", &self.statuses)
+            .field("predicate", &self.predicate.is_some())
+            .finish()
+    }
+}
+
+impl Credentials {
+    pub fn new(api_key: impl Into<String>) -> Self {
+        Credentials {
+            api_key: api_key.into(),
+            organization: None,
+            project: None,
+        }
+    }
+
+    /// Bills requests to the given organization, for users who belong to more than one.
+    pub fn organization(mut self, organization: impl Into<String>) -> Self {
+        self.organization = Some(organization.into());
+        self
+    }
+
+    /// Attributes requests to the given project.
+    pub fn project(mut self, project: impl Into<String>) -> Self {
+        self.project = Some(project.into());
+        self
+    }
+
+    fn authorize(&self, request: RequestBuilder) -> RequestBuilder {
+        let mut request = request.bearer_auth(&self.api_key);
+
+        if let Some(organization) = &self.organization {
+            request = request.header("OpenAI-Organization", organization);
+        }
+
+        if let Some(project) = &self.project {
+            request = request.header("OpenAI-Project", project);
+        }
+
+        request
+    }
+}
+
+impl fmt::Display for FromEnvError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            FromEnvError::MissingApiKey => {
+                write!(f, "environment variable `OPENAI_API_KEY` should be defined",)
+            }
+            FromEnvError::Client(error) => write!(f, "{error}"),
+        }
+    }
+}
+
+impl std::error::Error for FromEnvError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            FromEnvError::MissingApiKey => None,
+            FromEnvError::Client(error) => Some(error),
+        }
+    }
+}
+
+impl fmt::Display for HealthCheckError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            HealthCheckError::MissingCredentials => write!(
+                f,
+                "no credentials are configured and neither `OPENAI_API_KEY` nor `OPENAI_KEY` is set"
+            ),
+            HealthCheckError::Unauthorized => write!(f, "the API rejected the credentials"),
+            HealthCheckError::Status(status) => write!(f, "the API responded with {status}"),
+            HealthCheckError::Request(error) => write!(f, "{error}"),
+        }
+    }
+}
+
+impl std::error::Error for HealthCheckError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            HealthCheckError::MissingCredentials
+            | HealthCheckError::Unauthorized
+            | HealthCheckError::Status(_) => None,
+            HealthCheckError::Request(error) => Some(error),
+        }
+    }
+}
+
+impl fmt::Debug for Credentials {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        // Only the prefix identifying the kind of key, e.g. `sk-...****`
+        let api_key = match self.api_key.get(..3) {
+            Some(prefix) if self.api_key.len() > 3 => format!("{prefix}...****"),
+            _ => "****".to_string(),
+        };
+
+        f.debug_struct("Credentials")
+            .field("api_key", &api_key)
+            .field("organization", &self.organization)
+            .field("project", &self.project)
+            .finish()
+    }
+}
+
+impl ClientBuilder {
+    /// Reads the configuration [`Client::from_env`] uses, getting variables with `var`.
+    fn from_vars(var: impl Fn(&str) -> Option<String>) -> Result<Self, FromEnvError> {
+        let api_key = var("OPENAI_API_KEY")
+            .or_else(|| var("OPENAI_KEY"))
+            .ok_or(FromEnvError::MissingApiKey)?;
+        let mut credentials = Credentials::new(api_key);
+        let mut builder = ClientBuilder::default();
+
+        if let Some(organization) = var("OPENAI_ORG_ID") {
+            credentials = credentials.organization(organization);
+        }
+
+        if let Some(project) = var("OPENAI_PROJECT") {
+            credentials = credentials.project(project);
+        }
+
+        if let Some(base_url) = var("OPENAI_BASE_URL") {
+            builder = builder.base_url(base_url);
+        }
+
+        Ok(builder.credentials(credentials))
+    }
+
+    /// Sends requests to the given URL instead of `https://api.openai.com/v1/`,
+    /// for example to use a compatible API or a local mock server.
+    pub fn base_url(mut self, base_url: impl Into<String>) -> Self {
+        let mut base_url = base_url.into();
+
+        // Routes are appended to the base URL
+        if !base_url.ends_with('/') {
+            base_url.push('/');
+        }
+
+        self.base_url = Some(base_url);
+        self
+    }
+
+    /// Authorizes requests with the given credentials,
+    /// instead of the API key in the `OPENAI_API_KEY` (or `OPENAI_KEY`) environment variable.
+    pub fn credentials(mut self, credentials: Credentials) -> Self {
+        self.credentials = Some(credentials);
+        self
+    }
+
+    /// Sends requests with the given HTTP client exactly as it's configured,
+    /// e.g. with custom DNS resolution, connection pooling or TLS settings.
+    ///
+    /// Every connection-level setting should then be made on that client:
+    /// [`proxy`](Self::proxy), [`no_proxy`](Self::no_proxy), the certificates and the user agent
+    /// of this builder are ignored. Clients built from it share its connection pool.
+    pub fn http_client(mut self, http_client: reqwest::Client) -> Self {
+        self.http_client = Some(http_client);
+        self
+    }
+
+    /// Routes requests through the given proxy.
+    ///
+    /// Adding a proxy disables the proxies otherwise read from the environment.
+    /// Use [`Proxy::basic_auth`] if the proxy requires authentication.
+    pub fn proxy(mut self, proxy: Proxy) -> Self {
+        self.http = self.http.proxy(proxy);
+        self
+    }
+
+    /// Ignores any proxy, including ones defined by environment variables.
+    pub fn no_proxy(mut self) -> Self {
+        self.http = self.http.no_proxy();
+        self
+    }
+
+    /// Trusts an additional root certificate,
+    /// for example one used by a security appliance in front of the API.
+    #[cfg(any(feature = "native-tls", feature = "rustls-tls"))]
+    pub fn add_root_certificate(mut self, certificate: Certificate) -> Self {
+        self.http = self.http.add_root_certificate(certificate);
+        self
+    }
+
+    /// Presents a client certificate to gateways that require mutual TLS.
+    ///
+    /// How the [`Identity`] can be created depends on whether the `native-tls` or `rustls-tls` feature is enabled.
+    #[cfg(any(feature = "native-tls", feature = "rustls-tls"))]
+    pub fn identity(mut self, identity: Identity) -> Self {
+        self.http = self.http.identity(identity);
+        self
+    }
+
+    /// Replaces the [`DEFAULT_USER_AGENT`].
+    pub fn user_agent(mut self, user_agent: impl Into<String>) -> Self {
+        self.user_agent = Some(user_agent.into());
+        self
+    }
+
+    /// Appends the name (and ideally version) of your application to the `User-Agent`,
+    /// e.g. `my-app/1.2.3`, so its traffic can be told apart from others'.
+    pub fn app_name(mut self, app_name: impl Into<String>) -> Self {
+        self.app_name = Some(app_name.into());
+        self
+    }
+
+    /// Calls the given function with every request right before it is sent,
+    /// after its body and authorization have been set.
+    ///
+    /// The request it returns is the one that gets sent,
+    /// so this can be used to log, sign, or otherwise modify outgoing requests.
+    pub fn on_request(
+        mut self,
+        on_request: impl Fn(RequestBuilder) -> RequestBuilder + Send + Sync + 'static,
+    ) -> Self {
+        self.on_request = Some(Arc::new(on_request));
+        self
+    }
+
+    /// Calls the given function after every request with its route, status, duration and usage,
+    /// for example to report them to a monitoring system.
+    ///
+    /// Nothing extra is measured or parsed unless this is set.
+    /// Streamed completions aren't reported.
+    pub fn on_metrics(
+        mut self,
+        on_metrics: impl Fn(&RequestMetrics) + Send + Sync + 'static,
+    ) -> Self {
+        self.on_metrics = Some(Arc::new(on_metrics));
+        self
+    }
+
+    /// Retries failed requests according to the given policy, no requests are retried by default.
+    ///
+    /// The policy is applied by a [`RetryLayer`] below every other layer, see [`ClientBuilder::layer`].
+    /// Streamed completions aren't retried,
+    /// see [`create_restarting_stream`](crate::chat::ChatCompletionBuilder::create_restarting_stream) instead.
+    pub fn retry_policy(mut self, retry_policy: RetryPolicy) -> Self {
+        self.retry_policy = Some(retry_policy);
+        self
+    }
+
+    /// Limits how many requests the client, and its clones, have in flight at once,
+    /// making further requests wait for one of them to finish. Unlimited by default.
+    ///
+    /// A request is in flight from being sent until its whole response has been received,
+    /// including any retries and the backoff before them, see [`ClientBuilder::retry_policy`].
+    /// The limit is applied by a [`ConcurrencyLimitLayer`] above every other layer, see [`ClientBuilder::layer`].
+    /// Streamed completions don't count towards the limit.
+    ///
+    /// # Panics
+    ///
+    /// If `max_concurrent_requests` is 0, since no request could ever be sent.
+    pub fn max_concurrent_requests(mut self, max_concurrent_requests: usize) -> Self {
+        assert!(
+            max_concurrent_requests > 0,
+            "at least one request should be allowed in flight",
+        );
+        self.max_concurrent_requests = Some(max_concurrent_requests);
+        self
+    }
+
+    /// Sends every request through the given layer, e.g. a [`LoggingLayer`](layer::LoggingLayer)
+    /// or one of your own, see the [`layer`](crate::layer) module.
+    ///
+    /// Layers are called in the order they're added, each wrapping the ones added after it.
+    /// They're all wrapped by the limit of [`ClientBuilder::max_concurrent_requests`],
+    /// and all wrap the retries of [`ClientBuilder::retry_policy`], so a retried request passes through them once.
+    /// For another order, add a [`ConcurrencyLimitLayer`] or [`RetryLayer`] yourself instead.
+    pub fn layer(mut self, layer: impl Layer + 'static) -> Self {
+        self.layers.push(Box::new(layer));
+        self
+    }
+
+    pub fn build(self) -> Result<Client, reqwest::Error> {
+        let http = match self.http_client {
+            Some(http) => http,
+            None => {
+                let mut user_agent = self
+                    .user_agent
+                    .unwrap_or_else(|| DEFAULT_USER_AGENT.to_string());
+
+                if let Some(app_name) = self.app_name {
+                    user_agent = format!("{user_agent} {app_name}");
+                }
+
+                self.http.user_agent(user_agent).build()?
+            }
+        };
+        let mut layers: Vec<Box<dyn Layer>> = Vec::new();
+
+        if let Some(max_concurrent_requests) = self.max_concurrent_requests {
+            layers.push(Box::new(ConcurrencyLimitLayer::new(
+                max_concurrent_requests,
+            )));
+        }
+
+        layers.extend(self.layers);
+
+        // Without retries, requests are sent without cloning them first
+        if let Some(retry_policy) = self.retry_policy {
+            if retry_policy.max_retries > 0 {
+                layers.push(Box::new(RetryLayer::new(retry_policy)));
+            }
+        }
+
+        Ok(Client {
+            http,
+            base_url: self.base_url.unwrap_or_else(|| BASE_URL.to_string()),
+            credentials: self.credentials,
+            on_request: self.on_request,
+            on_metrics: self.on_metrics,
+            layers: layers.into(),
+            streams: Arc::default(),
+        })
+    }
+}
+
+impl fmt::Debug for ClientBuilder {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("ClientBuilder")
+            .field("http", &self.http)
+            .field("http_client", &self.http_client)
+            .field("base_url", &self.base_url)
+            .field("credentials", &self.credentials)
+            .field("user_agent", &self.user_agent)
+            .field("app_name", &self.app_name)
+            .field("on_request", &self.on_request.is_some())
+            .field("on_metrics", &self.on_metrics.is_some())
+            .field("retry_policy", &self.retry_policy)
+            .field("max_concurrent_requests", &self.max_concurrent_requests)
+            .field("layers", &self.layers.len())
+            .finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        models::{Model, ModelID},
+        tests::stub_server,
+    };
+    use reqwest::header::AUTHORIZATION;
+    use std::{
+        collections::HashMap,
+        sync::{
+            atomic::{AtomicUsize, Ordering},
+            Mutex,
+        },
+    };
+    use tokio::{io::AsyncWriteExt, net::TcpListener, sync::oneshot};
+
+    #[cfg(any(feature = "native-tls", feature = "rustls-tls"))]
+    const ROOT_CERTIFICATE: &[u8] = b"-----BEGIN CERTIFICATE-----
+MIIBiDCCAS+gAwIBAgIUfLfkHnr/gFXWKveamJICVjBAnqgwCgYIKoZIzj0EAwIw
+GTEXMBUGA1UEAwwOb3BlbmFpIHRlc3QgQ0EwIBcNMjYxMDE2MDgyNDE5WhgPMjEy
+NjA5MjIwODI0MTlaMBkxFzAVBgNVBAMMDm9wZW5haSB0ZXN0IENBMFkwEwYHKoZI
+zj0CAQYIKoZIzj0DAQcDQgAEAtEDWq6GNzrMD0ZuvzA87bLMFzc4LgJsCG5jg/0O
+EFFxKe8LUfsCloKra5J9SfGNWhcziM75ed3e/9JXEGjKBaNTMFEwHQYDVR0OBBYE
+FKxm04zAe2ZKeGZjKlJmCVieHLy6MB8GA1UdIwQYMBaAFKxm04zAe2ZKeGZjKlJm
+CVieHLy6MA8GA1UdEwEB/wQFMAMBAf8wCgYIKoZIzj0EAwIDRwAwRAIgFz/hV5Dl
+jSRCIWcux3hH7Y1iUKhZf11DlzUFgfPd2EkCIE9fBKZ8OGUluI0e+2D6QWC2KFw8
+8NjfjcUDvF7dItzD
+-----END CERTIFICATE-----
+";
+
+    /// Answers the first connection with an error,
+    /// returning the head of the request it received.
+    async fn proxy_stub() -> (Proxy, oneshot::Receiver<String>) {
+        let (sender, received) = oneshot::channel();
+        let sender = Mutex::new(Some(sender));
+        let address = stub_server(move |head, mut stream| {
+            if let Some(sender) = sender.lock().unwrap().take() {
+                sender.send(head).unwrap();
+            }
+
+            async move {
+                stream
+                    .write_all(b"HTTP/1.1 403 Forbidden\r\n\r\n")
+                    .await
+                    .unwrap();
+            }
+        })
+        .await;
+        let proxy = Proxy::all(format!("http://{address}")).unwrap();
+
+        (proxy, received)
+    }
+
+    /// Answers every request with the next of the given statuses, repeating the last one,
+    /// returning a client sending requests to it and how many requests it received.
+    async fn status_stub(
+        statuses: &[u16],
+        retry_policy: RetryPolicy,
+    ) -> (Client, Arc<AtomicUsize>) {
+        let (builder, requests) = status_server(statuses).await;
+        let client = builder
+            .retry_policy(retry_policy.backoff(Duration::ZERO))
+            .build()
+            .unwrap();
+
+        (client, requests)
+    }
+
+    /// Like [`status_stub`], but returns a builder for the client to configure it further.
+    async fn status_server(statuses: &[u16]) -> (ClientBuilder, Arc<AtomicUsize>) {
+        let statuses = statuses.to_vec();
+        let requests = Arc::new(AtomicUsize::new(0));
+        let address = stub_server({
+            let requests = requests.clone();
+
+            move |_, mut stream| {
+                let request = requests.fetch_add(1, Ordering::SeqCst);
+                let status = statuses[request.min(statuses.len() - 1)];
+
+                async move {
+                    let body = if status == 200 {
+                        r#"{"object":"list","data":[]}"#
+                    } else {
+                        r#"{"error":{"message":"stub","type":"stub"}}"#
+                    };
+                    let response = format!(
+                        "HTTP/1.1 {status} Stub\r\nContent-Type: application/json\r\n\
+                        x-request-id: req_{request}\r\n\
+                        Content-Length: {}\r\nConnection: close\r\n\r\n{body}",
+                        body.len(),
+                    );
+
+                    stream.write_all(response.as_bytes()).await.unwrap();
+                }
+            }
+        })
+        .await;
+        let builder = Client::builder()
+            .base_url(format!("http://{address}/v1"))
+            .credentials(Credentials::new("sk-test"));
+
+        (builder, requests)
+    }
+
+    /// How many requests are sent to a server answering with the given statuses.
+    async fn requests_sent(statuses: &[u16], retry_policy: RetryPolicy) -> usize {
+        let (client, requests) = status_stub(statuses, retry_policy).await;
+        let _ = crate::openai_get::<serde_json::Value>(&client, "models").await;
+
+        requests.load(Ordering::SeqCst)
+    }
+
+    #[tokio::test]
+    async fn raw_get() {
+        let (client, requests) = status_stub(&[503, 200], RetryPolicy::new(1)).await;
+
+        assert_eq!(
+            client.raw_get("models").await.unwrap().unwrap(),
+            serde_json::json!({"object": "list", "data": []}),
+        );
+        assert_eq!(requests.load(Ordering::SeqCst), 2);
+
+        let (client, _) = status_stub(&[404], RetryPolicy::new(0)).await;
+
+        assert_eq!(
+            client.raw_get("models").await.unwrap().unwrap_err().message,
+            "stub",
+        );
+    }
+
+    #[tokio::test]
+    async fn retry_policy() {
+        let policy = RetryPolicy::new(10)
+            .status(StatusCode::TOO_MANY_REQUESTS, 5)
+            .status(StatusCode::SERVICE_UNAVAILABLE, 2)
+            .status(StatusCode::BAD_REQUEST, 0);
+
+        assert_eq!(requests_sent(&[429], policy.clone()).await, 6);
+        assert_eq!(requests_sent(&[503], policy.clone()).await, 3);
+        assert_eq!(requests_sent(&[400], policy.clone()).await, 1);
+        assert_eq!(requests_sent(&[503, 503, 200], policy.clone()).await, 3);
+        // Both statuses are retried within their own limits
+        assert_eq!(
+            requests_sent(&[503, 429, 429, 503, 503], policy.clone()).await,
+            5
+        );
+        // Statuses without a rule are retried by default if they're server errors
+        assert_eq!(requests_sent(&[500, 500, 404], policy.clone()).await, 3);
+        assert_eq!(requests_sent(&[500], policy.clone()).await, 11);
+        // The policy's limit applies to statuses with a rule too
+        assert_eq!(
+            requests_sent(
+                &[429],
+                RetryPolicy::new(3).status(StatusCode::TOO_MANY_REQUESTS, 5)
+            )
+            .await,
+            4
+        );
+
+        let predicate = policy
+            .retry_if(|status, retries| status == Some(StatusCode::IM_A_TEAPOT) && retries < 1);
+
+        assert_eq!(requests_sent(&[418], predicate.clone()).await, 2);
+        assert_eq!(requests_sent(&[500], predicate.clone()).await, 1);
+        assert_eq!(requests_sent(&[429], predicate).await, 6);
+    }
+
+    #[tokio::test]
+    async fn no_retries() {
+        let (client, _) = status_stub(&[503], RetryPolicy::new(0)).await;
+
+        assert!(client.layers.is_empty());
+        assert_eq!(requests_sent(&[503], RetryPolicy::new(0)).await, 1);
+        assert_eq!(requests_sent(&[503], RetryPolicy::new(1)).await, 2);
+    }
+
+    #[tokio::test]
+    async fn health_check() {
+        let check = |statuses: &'static [u16]| async move {
+            let (client, _) = status_stub(statuses, RetryPolicy::new(1)).await;
+
+            client.health_check().await
+        };
+
+        assert!(check(&[200]).await.is_ok());
+        assert!(check(&[503, 200]).await.is_ok());
+        assert!(matches!(
+            check(&[401]).await,
+            Err(HealthCheckError::Unauthorized),
+        ));
+        assert!(matches!(
+            check(&[503]).await,
+            Err(HealthCheckError::Status(StatusCode::SERVICE_UNAVAILABLE)),
+        ));
+
+        // Nothing listens on the port once the listener is dropped
+        let address = TcpListener::bind("127.0.0.1:0")
+            .await
+            .unwrap()
+            .local_addr()
+            .unwrap();
+        let client = Client::builder()
+            .base_url(format!("http://{address}/v1"))
+            .credentials(Credentials::new("sk-test"))
+            .build()
+            .unwrap();
+
+        assert!(matches!(
+            client.health_check().await,
+            Err(HealthCheckError::Request(RequestError::Http(_))),
+        ));
+    }
+
+    #[tokio::test]
+    async fn health_check_without_credentials() {
+        let client = Client::builder().build().unwrap();
+
+        assert!(matches!(
+            client.check_credentials(|_| None),
+            Err(HealthCheckError::MissingCredentials),
+        ));
+        assert!(client
+            .check_credentials(|name| (name == "OPENAI_KEY").then(|| "sk-test".to_string()))
+            .is_ok());
+        assert!(client
+            .check_credentials(|name| (name == "OPENAI_API_KEY").then(|| "sk-test".to_string()))
+            .is_ok());
+
+        let client = Client::builder()
+            .credentials(Credentials::new("sk-test"))
+            .build()
+            .unwrap();
+
+        assert!(client.check_credentials(|_| None).is_ok());
+
+        // Only checked against the real environment where nothing would authorize the request
+        if env::var_os("OPENAI_API_KEY").is_none()
+            && env::var_os("OPENAI_KEY").is_none()
+            && dotenvy::dotenv().is_err()
+        {
+            assert!(matches!(
+                Client::builder().build().unwrap().health_check().await,
+                Err(HealthCheckError::MissingCredentials),
+            ));
+        }
+    }
+
+    #[tokio::test]
+    async fn request_id() {
+        let (client, _) = status_stub(&[400, 400], RetryPolicy::new(0)).await;
+        let error = crate::openai_get::<crate::Page<serde_json::Value>>(&client, "models")
+            .await
+            .unwrap()
+            .unwrap_err();
+
+        assert_eq!(error.request_id.as_deref(), Some("req_0"));
+        assert_eq!(error.to_string(), "stub (request ID req_0)");
+
+        let (builder, _) = status_server(&[200]).await;
+        let seen = Arc::new(Mutex::new(Vec::new()));
+        let client = builder
+            .retry_policy(RetryPolicy::new(0))
+            .on_metrics({
+                let seen = seen.clone();
+
+                move |metrics| {
+                    seen.lock()
+                        .unwrap()
+                        .push(metrics.request_id.map(String::from));
+                }
+            })
+            .build()
+            .unwrap();
+
+        assert!(crate::openai_get::<serde_json::Value>(&client, "models")
+            .await
+            .unwrap()
+            .is_ok());
+        assert_eq!(*seen.lock().unwrap(), [Some("req_0".to_string())]);
+    }
+
+    #[tokio::test]
+    async fn layers() {
+        /// Records the routes it sees, answering requests for `cached` itself.
+        struct CacheLayer {
+            seen: Arc<Mutex<Vec<String>>>,
+        }
+
+        #[async_trait::async_trait]
+        impl Layer for CacheLayer {
+            async fn call(
+                &self,
+                request: layer::Request,
+                next: Next<'_>,
+            ) -> Result<layer::Response, RequestError> {
+                self.seen.lock().unwrap().push(request.route.clone());
+
+                if request.route != "cached" {
+                    return next.run(request).await;
+                }
+
+                Ok(layer::Response {
+                    status: StatusCode::OK,
+                    headers: Default::default(),
+                    body: br#"{"cached": true}"#.to_vec(),
+                })
+            }
+        }
+
+        let (builder, requests) = status_server(&[503, 200]).await;
+        let log = Arc::new(Mutex::new(Vec::new()));
+        let seen = Arc::new(Mutex::new(Vec::new()));
+        let client = builder
+            .retry_policy(RetryPolicy::new(1).backoff(Duration::ZERO))
+            .layer(layer::LoggingLayer::new({
+                let log = log.clone();
+
+                move |line| log.lock().unwrap().push(line.to_string())
+            }))
+            .layer(CacheLayer { seen: seen.clone() })
+            .build()
+            .unwrap();
+
+        client.raw_get("models").await.unwrap().unwrap();
+        assert_eq!(
+            client.raw_get("cached").await.unwrap().unwrap(),
+            serde_json::json!({"cached": true}),
+        );
+
+        // The retry happened below the other layers
+        assert_eq!(requests.load(Ordering::SeqCst), 2);
+        assert_eq!(*seen.lock().unwrap(), ["models", "cached"]);
+
+        let log = log.lock().unwrap();
+
+        assert_eq!(log.len(), 2);
+        assert!(log[0].starts_with("GET models: 200 OK in "), "{}", log[0]);
+        assert!(log[1].starts_with("GET cached: 200 OK in "), "{}", log[1]);
+    }
+
+    #[tokio::test]
+    async fn max_concurrent_requests() {
+        let in_flight = Arc::new(AtomicUsize::new(0));
+        let most_in_flight = Arc::new(AtomicUsize::new(0));
+        let address = stub_server({
+            let in_flight = in_flight.clone();
+            let most_in_flight = most_in_flight.clone();
+
+            move |_, mut stream| {
+                let in_flight = in_flight.clone();
+                let most_in_flight = most_in_flight.clone();
+
+                async move {
+                    let now_in_flight = in_flight.fetch_add(1, Ordering::SeqCst) + 1;
+
+                    most_in_flight.fetch_max(now_in_flight, Ordering::SeqCst);
+                    tokio::time::sleep(Duration::from_millis(50)).await;
+                    in_flight.fetch_sub(1, Ordering::SeqCst);
+
+                    let body = r#"{"object":"list","data":[]}"#;
+                    let response = format!(
+                        "HTTP/1.1 200 OK\r\nContent-Type: application/json\r\n\
+                        Content-Length: {}\r\nConnection: close\r\n\r\n{body}",
+                        body.len(),
+                    );
+
+                    stream.write_all(response.as_bytes()).await.unwrap();
+                }
+            }
+        })
+        .await;
+        let client = Client::builder()
+            .base_url(format!("http://{address}/v1"))
+            .credentials(Credentials::new("sk-test"))
+            .max_concurrent_requests(2)
+            .build()
+            .unwrap();
+
+        let requests = (0..6).map(|_| crate::openai_get::<serde_json::Value>(&client, "models"));
+
+        for response in futures::future::join_all(requests).await {
+            response.unwrap().unwrap();
+        }
+
+        assert_eq!(most_in_flight.load(Ordering::SeqCst), 2);
+    }
+
+    #[cfg(any(feature = "native-tls", feature = "rustls-tls"))]
+    #[tokio::test]
+    async fn proxy() {
+        let (proxy, received) = proxy_stub().await;
+        let client = Client::builder()
+            .credentials(Credentials::new("sk-test"))
+            .proxy(proxy.basic_auth("username", "password"))
+            .build()
+            .unwrap();
+
+        assert!(Model::from(&client, ModelID::TextDavinci003).await.is_err());
+
+        let received = received.await.unwrap();
+
+        assert!(received.starts_with("CONNECT api.openai.com:443"));
+        assert!(received
+            .to_lowercase()
+            .contains("proxy-authorization: basic "));
+        // `username:password`, base64 encoded
+        assert!(received.contains("dXNlcm5hbWU6cGFzc3dvcmQ="));
+    }
+
+    #[cfg(any(feature = "native-tls", feature = "rustls-tls"))]
+    #[tokio::test]
+    async fn http_client() {
+        let (proxy, received) = proxy_stub().await;
+        let http = reqwest::Client::builder()
+            .proxy(proxy)
+            .user_agent("my-transport/1.0")
+            .build()
+            .unwrap();
+        let client = Client::builder()
+            .credentials(Credentials::new("sk-test"))
+            .http_client(http)
+            .build()
+            .unwrap();
+
+        assert!(Model::from(&client, ModelID::TextDavinci003).await.is_err());
+        assert!(received
+            .await
+            .unwrap()
+            .to_lowercase()
+            .contains("user-agent: my-transport/1.0\r\n"));
+    }
+
+    #[cfg(any(feature = "native-tls", feature = "rustls-tls"))]
+    #[tokio::test]
+    async fn user_agent() {
+        let (proxy, received) = proxy_stub().await;
+        let client = Client::builder()
+            .credentials(Credentials::new("sk-test"))
+            .proxy(proxy)
+            .app_name("my-app/1.2.3")
+            .build()
+            .unwrap();
+
+        assert!(Model::from(&client, ModelID::TextDavinci003).await.is_err());
+        assert!(received.await.unwrap().to_lowercase().contains(&format!(
+            "user-agent: {DEFAULT_USER_AGENT} my-app/1.2.3\r\n"
+        )));
+    }
+
+    #[cfg(any(feature = "native-tls", feature = "rustls-tls"))]
+    #[test]
+    fn root_certificate() {
+        let certificate = Certificate::from_pem(ROOT_CERTIFICATE).unwrap();
+
+        assert!(Client::builder()
+            .add_root_certificate(certificate)
+            .build()
+            .is_ok());
+    }
+
+    #[tokio::test]
+    async fn on_request() {
+        let (proxy, _) = proxy_stub().await;
+        let seen = Arc::new(Mutex::new(Vec::new()));
+        let client = Client::builder()
+            .credentials(Credentials::new("sk-test"))
+            .proxy(proxy)
+            .on_request({
+                let seen = seen.clone();
+
+                move |request| {
+                    let built = request.try_clone().unwrap().build().unwrap();
+
+                    seen.lock().unwrap().push((
+                        built.url().to_string(),
+                        built.headers().contains_key(AUTHORIZATION),
+                    ));
+
+                    request.header("OpenAI-Beta", "assistants=v1")
+                }
+            })
+            .build()
+            .unwrap();
+
+        assert!(Model::from(&client, ModelID::TextDavinci003).await.is_err());
+        assert_eq!(
+            *seen.lock().unwrap(),
+            [(BASE_URL.to_owned() + "models/text-davinci-003", true)],
+        );
+    }
+
+    #[test]
+    fn credentials() {
+        let credentials = Credentials::new("sk-0123456789abcdef")
+            .organization("org-123")
+            .project("proj_456");
+        let client = Client::builder()
+            .credentials(credentials.clone())
+            .build()
+            .unwrap();
+        let request = client
+            .finish(client.request(Method::GET, "models"))
+            .build()
+            .unwrap();
+
+        assert_eq!(
+            request.headers()[AUTHORIZATION],
+            "Bearer sk-0123456789abcdef",
+        );
+        assert_eq!(request.headers()["OpenAI-Organization"], "org-123");
+        assert_eq!(request.headers()["OpenAI-Project"], "proj_456");
+
+        for debug in [format!("{credentials:?}"), format!("{client:?}")] {
+            assert!(!debug.contains("0123456789abcdef"));
+            assert!(debug.contains("sk-...****"));
+        }
+    }
+
+    #[test]
+    fn from_vars() {
+        let vars = HashMap::from([
+            ("OPENAI_KEY", "sk-old"),
+            ("OPENAI_API_KEY", "sk-new"),
+            ("OPENAI_ORG_ID", "org-123"),
+            ("OPENAI_BASE_URL", "http://localhost:8080/v1"),
+        ]);
+        let client = ClientBuilder::from_vars(|name| vars.get(name).map(|var| var.to_string()))
+            .unwrap()
+            .build()
+            .unwrap();
+        let request = client
+            .finish(client.request(Method::GET, "models"))
+            .build()
+            .unwrap();
+
+        assert_eq!(request.url().as_str(), "http://localhost:8080/v1/models");
+        assert_eq!(request.headers()[AUTHORIZATION], "Bearer sk-new");
+        assert_eq!(request.headers()["OpenAI-Organization"], "org-123");
+        assert!(!request.headers().contains_key("OpenAI-Project"));
+        assert!(matches!(
+            ClientBuilder::from_vars(|_| None),
+            Err(FromEnvError::MissingApiKey),
+        ));
+    }
+
+    #[cfg(any(feature = "native-tls", feature = "rustls-tls"))]
+    #[tokio::test]
+    async fn on_metrics() {
+        let (proxy, _) = proxy_stub().await;
+        let seen = Arc::new(Mutex::new(Vec::new()));
+        let client = Client::builder()
+            .credentials(Credentials::new("sk-test"))
+            .proxy(proxy)
+            .on_metrics({
+                let seen = seen.clone();
+
+                move |metrics| {
+                    seen.lock()
+                        .unwrap()
+                        .push((metrics.route.to_string(), metrics.status));
+                }
+            })
+            .build()
+            .unwrap();
+
+        assert!(Model::from(&client, ModelID::TextDavinci003).await.is_err());
+        assert_eq!(
+            *seen.lock().unwrap(),
+            [("models/text-davinci-003".to_string(), None)],
+        );
+    }
+}
@@ -1,33 +1,60 @@
 //! Given a prompt, the model will return one or more predicted completions,
 //! and can also return the probabilities of alternative tokens at each position.
 
-use super::{models::ModelID, openai_post, ApiResponseOrError, Usage};
+use super::{
+    chat::{ChatCompletion, ChatCompletionMessage},
+    invalid_request,
+    models::ModelID,
+    openai_dry_run, openai_post, ApiResponseOrError, Client, DryRun, EndUserId, OpenAiError, Usage,
+};
 use derive_builder::Builder;
-use reqwest::Client;
 use serde::{Deserialize, Serialize};
 use std::collections::HashMap;
 
-#[derive(Deserialize, Clone)]
+#[derive(Deserialize, Serialize, Clone)]
 pub struct Completion {
     pub id: String,
     pub created: u32,
     pub model: ModelID,
+    /// The generated choices, which can be empty, for example if the response was filtered.
+    /// [`Completion::first_choice`] and [`Completion::text`] account for that.
     pub choices: Vec<CompletionChoice>,
     pub usage: Usage,
+    /// Identifies the backend configuration the model ran with, like [`ChatCompletion::system_fingerprint`].
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub system_fingerprint: Option<String>,
 }
 
-#[derive(Deserialize, Clone)]
+#[derive(Deserialize, Serialize, Clone)]
 pub struct CompletionChoice {
     pub text: String,
     pub index: u16,
-    pub logprobs: Option<u16>,
+    /// The log probabilities of the tokens, if `logprobs` was requested.
+    ///
+    /// With `echo`, the tokens of the prompt are included as well.
+    /// With `best_of`, they're the log probabilities of this choice, not of the discarded candidates.
+    pub logprobs: Option<CompletionLogprobs>,
     pub finish_reason: String,
 }
 
+#[derive(Deserialize, Serialize, Clone, Debug, PartialEq)]
+pub struct CompletionLogprobs {
+    pub tokens: Vec<String>,
+    /// The log probability of each token,
+    /// `None` for the first token of an echoed prompt, which nothing precedes.
+    pub token_logprobs: Vec<Option<f64>>,
+    /// The most likely tokens at each position with their log probabilities,
+    /// as many as `logprobs` requested.
+    pub top_logprobs: Vec<Option<HashMap<String, f64>>>,
+    /// The character offset of each token in the text.
+    pub text_offset: Vec<u32>,
+}
+
 #[derive(Serialize, Builder, Debug, Clone)]
 #[builder(pattern = "owned")]
 #[builder(name = "CompletionBuilder")]
 #[builder(setter(strip_option, into))]
+#[builder(build_fn(validate = "Self::validate"))]
 pub struct CompletionRequest {
     /// ID of the model to use.
     /// You can use the [List models](https://beta.openai.com/docs/api-reference/models/list)
@@ -87,8 +114,10 @@ pub struct CompletionRequest {
     /// For example, if logprobs is 5, the API will return a list of the 5 most likely tokens.
     /// The API will always return the `logprob` of the sampled token, so there may be up to `logprobs+1` elements in the response.
     ///
-    /// The maximum value for `logprobs` is 5.
+    /// The maximum value for `logprobs` is 5, building the request fails if it's higher.
     /// If you need more than this, please contact us through our Help center and describe your use case.
+    ///
+    /// Unlike the boolean `logprobs` of chat completions, this is a count, like their `top_logprobs`.
     #[serde(skip_serializing_if = "Option::is_none")]
     #[builder(default)]
     pub logprobs: Option<u8>,
@@ -122,6 +151,8 @@ pub struct CompletionRequest {
     ///
     /// When used with `n`, `best_of` controls the number of candidate completions and `n` specifies how many to return –
     /// `best_of` must be greater than `n`.
+    /// Only the `n` best candidates (1 by default) are returned, indexed from 0 in the response,
+    /// each with its own `logprobs` if they were requested. The other candidates are discarded, but billed anyway.
     ///
     /// **Note:** Because this parameter generates many completions, it can quickly consume your token quota.
     /// Use carefully and ensure that you have reasonable settings for `max_tokens` and `stop`.
@@ -140,33 +171,195 @@ pub struct CompletionRequest {
     #[serde(skip_serializing_if = "HashMap::is_empty")]
     #[builder(default)]
     pub logit_bias: HashMap<String, i16>,
-    /// A unique identifier representing your end-user, which can help OpenAI to monitor and detect abuse.
-    /// [Learn more](https://beta.openai.com/docs/guides/safety-best-practices/end-user-ids).
-    #[serde(skip_serializing_if = "Option::is_none")]
+    /// A unique identifier representing your end-user, see [`EndUserId`].
+    #[serde(skip_serializing_if = "EndUserId::is_empty")]
     #[builder(default)]
-    pub user: Option<String>,
+    pub user: EndUserId,
+    /// Whether the request is sent as a chat completion instead, see [`CompletionBuilder::via_chat`].
+    /// Not sent to the API.
+    #[serde(skip)]
+    #[builder(setter(custom), default)]
+    pub via_chat: bool,
 }
 
 impl Completion {
     /// Creates a completion for the provided prompt and parameters
     async fn create(client: &Client, request: &CompletionRequest) -> ApiResponseOrError<Self> {
+        if request.via_chat {
+            return Self::create_via_chat(client, request).await;
+        }
+
         openai_post(client, "completions", request).await
     }
 
+    /// Sends the request as a chat completion with the prompt as its only message, from the user,
+    /// and maps the chat completion back, see [`CompletionBuilder::via_chat`].
+    async fn create_via_chat(
+        client: &Client,
+        request: &CompletionRequest,
+    ) -> ApiResponseOrError<Self> {
+        let unsupported = [
+            ("suffix", request.suffix.is_some()),
+            ("echo", request.echo.is_some()),
+            ("logprobs", request.logprobs.is_some()),
+            ("best_of", request.best_of.is_some()),
+        ];
+
+        // Rejected like the API rejects invalid parameters
+        if let Some((param, _)) = unsupported.iter().find(|(_, set)| *set) {
+            return Ok(Err(OpenAiError::new(
+                format!("`{param}` isn't supported when sending completions via chat"),
+                "invalid_request_error",
+            )
+            .param(*param)));
+        }
+
+        let mut chat = ChatCompletion::builder(
+            request.model.clone(),
+            [ChatCompletionMessage::user(
+                request.prompt.clone().unwrap_or_default(),
+            )],
+        )
+        .stop(request.stop.clone())
+        .user(request.user.clone());
+
+        if let Some(max_tokens) = request.max_tokens {
+            chat = chat.max_completion_tokens(max_tokens);
+        }
+        if let Some(temperature) = request.temperature {
+            chat = chat.temperature(temperature);
+        }
+        if let Some(top_p) = request.top_p {
+            chat = chat.top_p(top_p);
+        }
+        if let Some(n) = request.n {
+            chat = chat.n(u8::try_from(n).unwrap_or(u8::MAX));
+        }
+        if let Some(presence_penalty) = request.presence_penalty {
+            chat = chat.presence_penalty(f32::from(presence_penalty));
+        }
+        if let Some(frequency_penalty) = request.frequency_penalty {
+            chat = chat.frequency_penalty(f32::from(frequency_penalty));
+        }
+        if !request.logit_bias.is_empty() {
+            chat = chat.logit_bias(
+                request
+                    .logit_bias
+                    .iter()
+                    .map(|(token, &bias)| (token.clone(), f32::from(bias)))
+                    .collect::<HashMap<_, _>>(),
+            );
+        }
+
+        let chat = match chat.build() {
+            Ok(chat) => chat,
+            Err(error) => return Ok(Err(invalid_request(error))),
+        };
+
+        Ok(ChatCompletion::create(client, &chat)
+            .await?
+            .map(|chat| Completion {
+                id: chat.id,
+                created: chat.created as u32,
+                model: chat.model,
+                choices: chat
+                    .choices
+                    .into_iter()
+                    .map(|choice| CompletionChoice {
+                        text: choice.message.content.unwrap_or_default(),
+                        index: choice.index as u16,
+                        logprobs: None,
+                        finish_reason: choice.finish_reason,
+                    })
+                    .collect(),
+                usage: chat.usage.unwrap_or(Usage {
+                    prompt_tokens: 0,
+                    completion_tokens: 0,
+                    total_tokens: 0,
+                    prompt_tokens_details: None,
+                    completion_tokens_details: None,
+                }),
+                system_fingerprint: chat.system_fingerprint,
+            }))
+    }
+
     pub fn builder(model: ModelID) -> CompletionBuilder {
         CompletionBuilder::create_empty().model(model)
     }
+
+    /// The first of the generated choices, `None` if there are none.
+    pub fn first_choice(&self) -> Option<&CompletionChoice> {
+        self.choices.first()
+    }
+
+    /// The text of the first choice, `None` if there are no choices.
+    pub fn text(&self) -> Option<&str> {
+        self.first_choice().map(|choice| choice.text.as_str())
+    }
+}
+
+impl CompletionLogprobs {
+    /// Sums the log probabilities of all tokens, giving the log probability of the whole text.
+    ///
+    /// Request with `echo`, `logprobs` set to 0 and `max_tokens` set to 0
+    /// to score how likely a given prompt is under the model, e.g. to rank candidate texts.
+    /// Tokens without a log probability are skipped.
+    pub fn total_logprob(&self) -> f64 {
+        self.token_logprobs.iter().flatten().sum()
+    }
 }
 
 impl CompletionBuilder {
+    /// The most `logprobs` the API returns.
+    const MAX_LOGPROBS: u8 = 5;
+
+    /// Requests that fail to build, e.g. with `logprobs` out of range,
+    /// fail with an `invalid_request_error` without being sent.
     pub async fn create(self, client: &Client) -> ApiResponseOrError<Completion> {
-        Completion::create(client, &self.build().unwrap()).await
+        match self.build() {
+            Ok(request) => Completion::create(client, &request).await,
+            Err(error) => Ok(Err(invalid_request(error))),
+        }
+    }
+
+    /// Returns the request [`create`](Self::create) would send, without sending it.
+    ///
+    /// Requests sent [`via_chat`](Self::via_chat) are shown as they'd be sent to the completions endpoint.
+    pub fn dry_run(self, client: &Client) -> ApiResponseOrError<DryRun> {
+        match self.build() {
+            Ok(request) => Ok(Ok(openai_dry_run(client, "completions", &request)?)),
+            Err(error) => Ok(Err(invalid_request(error))),
+        }
+    }
+
+    /// Sends the request to the chat completions endpoint instead of the deprecated completions endpoint,
+    /// so code written for completions keeps working with current models, which only support chat.
+    ///
+    /// The prompt is sent as the only message, from the user, and the reply of each choice becomes its `text`.
+    /// The parameters without an equivalent in chat, `suffix`, `echo`, `logprobs` and `best_of`,
+    /// make the request fail with an `invalid_request_error`, so no log probabilities are returned.
+    /// `max_tokens` is sent as `max_completion_tokens`. Chat models tend to answer the prompt
+    /// rather than continue it, so replies can differ from those of the completion models.
+    pub fn via_chat(mut self) -> Self {
+        self.via_chat = Some(true);
+        self
+    }
+
+    fn validate(&self) -> Result<(), String> {
+        match self.logprobs {
+            Some(Some(logprobs)) if logprobs > Self::MAX_LOGPROBS => Err(format!(
+                "`logprobs` should be between 0 and {}, but is {logprobs}",
+                Self::MAX_LOGPROBS,
+            )),
+            _ => Ok(()),
+        }
     }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::tests::StubLayer;
     use dotenvy::dotenv;
 
     #[tokio::test]
@@ -177,14 +370,236 @@ mod tests {
             .prompt("Say this is a test")
             .max_tokens(7)
             .temperature(0.0)
-            .create(&Client::new())
+            .create(&crate::tests::live_client("completion"))
+            .await
+            .unwrap()
+            .unwrap();
+
+        assert_eq!(completion.text().unwrap(), "\n\nThis is indeed a test");
+    }
+
+    #[test]
+    fn logprobs_range() {
+        let request = Completion::builder(ModelID::TextDavinci003)
+            .prompt("Say this is a test")
+            .logprobs(5)
+            .build()
+            .unwrap();
+
+        assert_eq!(serde_json::to_value(&request).unwrap()["logprobs"], 5);
+        assert_eq!(
+            Completion::builder(ModelID::TextDavinci003)
+                .logprobs(6)
+                .build()
+                .unwrap_err()
+                .to_string(),
+            "`logprobs` should be between 0 and 5, but is 6",
+        );
+    }
+
+    #[tokio::test]
+    async fn invalid_parameters() {
+        let stub = StubLayer::chat("Paris.");
+        let client = stub.client();
+        let builder = || {
+            Completion::builder(ModelID::TextDavinci003)
+                .prompt("Say this is a test")
+                .logprobs(6)
+        };
+        let Err(error) = builder().create(&client).await.unwrap() else {
+            panic!("expected `logprobs` to be rejected");
+        };
+
+        assert_eq!(error.error_type, "invalid_request_error");
+        assert_eq!(
+            error.message,
+            "`logprobs` should be between 0 and 5, but is 6"
+        );
+        assert_eq!(
+            builder().dry_run(&client).unwrap().unwrap_err().message,
+            error.message,
+        );
+        assert!(stub.requests().is_empty());
+    }
+
+    #[tokio::test]
+    async fn via_chat() {
+        let stub = StubLayer::chat("Paris.");
+        let client = stub.client();
+        let completion = Completion::builder(ModelID::Gpt3_5Turbo)
+            .prompt("The capital of France is")
+            .max_tokens(16)
+            .temperature(0.0)
+            .via_chat()
+            .create(&client)
             .await
             .unwrap()
             .unwrap();
 
+        assert_eq!(completion.text(), Some("Paris."));
+        assert_eq!(completion.first_choice().unwrap().finish_reason, "stop");
+        assert_eq!(completion.usage.total_tokens, 15);
+
+        let (route, body) = stub.requests().remove(0);
+
+        assert_eq!(route, "chat/completions");
+        assert_eq!(
+            body,
+            serde_json::json!({
+                "model": "gpt-3.5-turbo",
+                "messages": [{"role": "user", "content": "The capital of France is"}],
+                "max_completion_tokens": 16,
+                "temperature": 0.0
+            }),
+        );
+
+        let error = Completion::builder(ModelID::Gpt3_5Turbo)
+            .prompt("The capital of France is")
+            .echo(true)
+            .via_chat()
+            .create(&client)
+            .await
+            .unwrap();
+        let Err(error) = error else {
+            panic!("expected the echo parameter to be rejected");
+        };
+
+        assert_eq!(error.param.as_deref(), Some("echo"));
+        assert_eq!(stub.requests().len(), 1);
+    }
+
+    #[test]
+    fn empty_choices() {
+        let completion: Completion = serde_json::from_str(
+            r#"{
+                "id": "cmpl-uqkvlQyYK7bGYrRHQ0eXlWi7",
+                "created": 1589478378,
+                "model": "text-davinci-003",
+                "choices": [],
+                "usage": {"prompt_tokens": 5, "completion_tokens": 0, "total_tokens": 5}
+            }"#,
+        )
+        .unwrap();
+
+        assert!(completion.first_choice().is_none());
+        assert!(completion.text().is_none());
+    }
+
+    #[test]
+    fn echoed_logprobs() {
+        let choice: CompletionChoice = serde_json::from_str(
+            r#"{
+                "text": "Say this is a test",
+                "index": 0,
+                "logprobs": {
+                    "tokens": ["Say", " this", " is", " a", " test"],
+                    "token_logprobs": [null, -4.5, -1.25, -0.5, -0.75],
+                    "top_logprobs": [null, {" this": -4.5}, {" is": -1.25}, {" a": -0.5}, {" test": -0.75}],
+                    "text_offset": [0, 3, 8, 11, 13]
+                },
+                "finish_reason": "length"
+            }"#,
+        )
+        .unwrap();
+        let logprobs = choice.logprobs.unwrap();
+
+        assert_eq!(logprobs.token_logprobs[0], None);
+        assert_eq!(logprobs.top_logprobs[1].as_ref().unwrap()[" this"], -4.5);
+        assert_eq!(logprobs.total_logprob(), -7.0);
+    }
+
+    #[test]
+    fn best_of_logprobs() {
+        // The response to `best_of: 3, n: 2, logprobs: 1`
+        let completion: Completion = serde_json::from_str(
+            r#"{
+                "id": "cmpl-uqkvlQyYK7bGYrRHQ0eXlWi7",
+                "object": "text_completion",
+                "created": 1589478378,
+                "model": "gpt-3.5-turbo-instruct",
+                "choices": [
+                    {
+                        "text": " yes",
+                        "index": 0,
+                        "logprobs": {
+                            "tokens": [" yes"],
+                            "token_logprobs": [-0.25],
+                            "top_logprobs": [{" yes": -0.25}],
+                            "text_offset": [12]
+                        },
+                        "finish_reason": "length"
+                    },
+                    {
+                        "text": " no",
+                        "index": 1,
+                        "logprobs": {
+                            "tokens": [" no"],
+                            "token_logprobs": [-1.5],
+                            "top_logprobs": [{" yes": -0.25}],
+                            "text_offset": [12]
+                        },
+                        "finish_reason": "length"
+                    }
+                ],
+                "usage": {"prompt_tokens": 4, "completion_tokens": 3, "total_tokens": 7}
+            }"#,
+        )
+        .unwrap();
+        let choices: Vec<_> = completion
+            .choices
+            .iter()
+            .map(|choice| {
+                (
+                    choice.index,
+                    choice.logprobs.as_ref().unwrap().total_logprob(),
+                )
+            })
+            .collect();
+
+        assert_eq!(choices, [(0, -0.25), (1, -1.5)]);
+    }
+
+    #[test]
+    fn fully_modeled() {
+        assert_eq!(
+            crate::tests::unmodeled_fields::<Completion>(include_str!(
+                "../fixtures/completion.json"
+            )),
+            ["object"],
+        );
+    }
+
+    #[test]
+    fn field_names() {
+        assert_eq!(
+            crate::tests::misnamed_fields::<Completion>(include_str!(
+                "../fixtures/completion.json"
+            )),
+            Vec::<String>::new(),
+        );
+
+        let request = Completion::builder(ModelID::TextDavinci003)
+            .prompt("Say this is a test")
+            .max_tokens(7)
+            .temperature(0.5)
+            .top_p(1.0)
+            .n(1u16)
+            .logprobs(2)
+            .stop(vec!["\n".to_string()])
+            .presence_penalty(1)
+            .frequency_penalty(-1)
+            .best_of(2u16)
+            .logit_bias(HashMap::from([("50256".to_string(), -100)]))
+            .user("user-1234")
+            .build()
+            .unwrap();
+
         assert_eq!(
-            completion.choices.first().unwrap().text,
-            "\n\nThis is indeed a test"
+            serde_json::to_value(&request).unwrap(),
+            serde_json::from_str::<serde_json::Value>(include_str!(
+                "../fixtures/completion_request.json"
+            ))
+            .unwrap(),
         );
     }
 }
@@ -1,21 +1,23 @@
 //! Given a prompt and an instruction, the model will return an edited version of the prompt.
 
-use super::{models::ModelID, openai_post, ApiResponseOrError, OpenAiError, Usage};
+use super::{
+    invalid_request, models::ModelID, openai_dry_run, openai_post, ApiResponseOrError, Client,
+    DryRun, OpenAiError, Usage,
+};
 use derive_builder::Builder;
-use reqwest::Client;
 use serde::{Deserialize, Serialize};
 
-#[derive(Deserialize, Clone)]
+#[derive(Deserialize, Serialize, Clone)]
 pub struct Edit {
     pub created: u32,
-    #[serde(skip_deserializing)]
+    #[serde(skip)]
     pub choices: Vec<String>,
     pub usage: Usage,
     #[serde(rename = "choices")]
     choices_bad: Vec<EditChoice>,
 }
 
-#[derive(Deserialize, Clone)]
+#[derive(Deserialize, Serialize, Clone)]
 struct EditChoice {
     text: String,
 }
@@ -80,16 +82,55 @@ impl Edit {
 }
 
 impl EditBuilder {
-    pub async fn create(self) -> ApiResponseOrError<Edit> {
-        Edit::create(&Client::new(), &self.build().unwrap()).await
+    pub async fn create(self, client: &Client) -> ApiResponseOrError<Edit> {
+        match self.build() {
+            Ok(request) => Edit::create(client, &request).await,
+            Err(error) => Ok(Err(invalid_request(error))),
+        }
+    }
+
+    /// Returns the request [`create`](Self::create) would send, without sending it.
+    pub fn dry_run(self, client: &Client) -> ApiResponseOrError<DryRun> {
+        match self.build() {
+            Ok(request) => Ok(Ok(openai_dry_run(client, "edits", &request)?)),
+            Err(error) => Ok(Err(invalid_request(error))),
+        }
     }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::Credentials;
     use dotenvy::dotenv;
 
+    #[tokio::test]
+    async fn missing_fields() {
+        let client = Client::builder()
+            .credentials(Credentials::new("sk-test"))
+            .build()
+            .unwrap();
+        let Err(error) = EditBuilder::default()
+            .instruction("Fix the spelling mistakes")
+            .create(&client)
+            .await
+            .unwrap()
+        else {
+            panic!("the edit without a model should be rejected");
+        };
+
+        assert_eq!(error.error_type, "invalid_request_error");
+        assert!(error.message.contains("model"), "{}", error.message);
+        assert_eq!(
+            EditBuilder::default()
+                .dry_run(&client)
+                .unwrap()
+                .unwrap_err()
+                .error_type,
+            "invalid_request_error",
+        );
+    }
+
     #[tokio::test]
     async fn edit() {
         dotenv().ok();
@@ -97,7 +138,7 @@ mod tests {
         let edit = Edit::builder(ModelID::TextDavinciEdit001, "Fix the spelling mistakes")
             .input("What day of the wek is it?")
             .temperature(0.0)
-            .create()
+            .create(&crate::tests::live_client("edit"))
             .await
             .unwrap()
             .unwrap();
@@ -2,35 +2,240 @@
 //!
 //! Related guide: [Embeddings](https://beta.openai.com/docs/guides/embeddings)
 
-use super::{models::ModelID, openai_post, ApiResponseOrError};
-use reqwest::Client;
-use serde::{Deserialize, Serialize};
+use super::{
+    models::ModelID, openai_post, ApiResponseOrError, Client, EndUserId, OpenAiError, RequestError,
+};
+use base64::{engine::general_purpose::STANDARD, Engine};
+use futures::future;
+use serde::{de, ser::SerializeStruct, Deserialize, Deserializer, Serialize, Serializer};
+use std::{fmt, ops::Range};
 
 #[derive(Serialize, Clone)]
 struct CreateEmbeddingsRequestBody<'a> {
     model: ModelID,
     input: Vec<&'a str>,
-    #[serde(skip_serializing_if = "str::is_empty")]
-    user: &'a str,
+    #[serde(skip_serializing_if = "EndUserId::is_empty")]
+    user: EndUserId,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    encoding_format: Option<EncodingFormat>,
 }
 
-#[derive(Deserialize, Clone)]
+/// The format the API returns embeddings in.
+#[derive(Deserialize, Serialize, Clone, Copy, Debug, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+#[non_exhaustive]
+pub enum EncodingFormat {
+    /// An array of floats, the default.
+    Float,
+    /// The little-endian bytes of 32-bit floats, encoded in base64.
+    /// Considerably smaller than the array of floats.
+    Base64,
+}
+
+#[derive(Deserialize, Serialize, Clone)]
 pub struct Embeddings {
+    /// The embeddings in the same order as the inputs they were created for.
+    #[serde(deserialize_with = "sorted_by_index")]
     pub data: Vec<Embedding>,
     pub model: ModelID,
     pub usage: EmbeddingsUsage,
+    #[serde(skip)]
+    input_tokens: Option<Vec<u32>>,
 }
 
-#[derive(Deserialize, Clone, Copy)]
+#[derive(Deserialize, Serialize, Clone, Copy)]
 pub struct EmbeddingsUsage {
     pub prompt_tokens: u32,
     pub total_tokens: u32,
 }
 
 #[derive(Deserialize, Clone)]
+#[serde(from = "RawEmbedding")]
 pub struct Embedding {
-    #[serde(rename = "embedding")]
+    /// The position of the input this embedding was created for.
+    pub index: u32,
+    /// The embedding, empty if it was returned in base64, see [`Embedding::vec_f32`].
     pub vec: Vec<f64>,
+    /// The 32-bit floats the API encoded, if it was returned in base64.
+    vec_f32: Option<Vec<f32>>,
+}
+
+/// The embeddings of [`Embeddings::create_batched`],
+/// keeping those of the batches that succeeded when others failed.
+pub struct BatchedEmbeddings {
+    /// An embedding for each input, in the same order, `None` for the inputs of failed batches.
+    pub embeddings: Vec<Option<Embedding>>,
+    /// The usage of the batches that succeeded.
+    pub usage: EmbeddingsUsage,
+    /// The batches that failed, which can be sent again with [`BatchedEmbeddings::retry_failed`].
+    pub failures: Vec<BatchFailure>,
+}
+
+/// A batch of [`Embeddings::create_batched`] that failed.
+#[derive(Debug)]
+pub struct BatchFailure {
+    /// The positions of the inputs in the batch.
+    pub inputs: Range<usize>,
+    pub error: BatchError,
+}
+
+/// Why a batch of [`Embeddings::create_batched`] failed.
+#[derive(Debug)]
+pub enum BatchError {
+    /// The request couldn't be sent, or its response couldn't be received or decoded.
+    Request(RequestError),
+    /// The API rejected the batch.
+    Api(OpenAiError),
+}
+
+/// The most tokens an input can be, the API rejects requests with longer inputs.
+pub const MAX_INPUT_TOKENS: usize = 8192;
+
+/// Inputs longer than [`MAX_INPUT_TOKENS`], found by [`Embeddings::check_input`] before sending them.
+#[cfg(feature = "tokenizer")]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct InputTooLongError {
+    /// The positions of the inputs that are too long, each with how many tokens it is.
+    pub inputs: Vec<(usize, usize)>,
+}
+
+/// An embedding as returned by the API, in either encoding format.
+#[derive(Deserialize)]
+struct RawEmbedding {
+    index: u32,
+    embedding: RawEmbeddingVector,
+}
+
+enum RawEmbeddingVector {
+    Float(Vec<f64>),
+    /// Decoded as soon as it's read, without copying the string.
+    Base64(Vec<f32>),
+}
+
+// Rather than `#[serde(untagged)]`, which buffers the whole vector before trying each variant
+impl<'de> Deserialize<'de> for RawEmbeddingVector {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        struct RawEmbeddingVectorVisitor;
+
+        impl<'de> de::Visitor<'de> for RawEmbeddingVectorVisitor {
+            type Value = RawEmbeddingVector;
+
+            fn expecting(&self, formatter: &mut std::fmt::Formatter) -> std::fmt::Result {
+                formatter.write_str("an array of floats or a base64 string")
+            }
+
+            fn visit_seq<A>(self, mut seq: A) -> Result<Self::Value, A::Error>
+            where
+                A: de::SeqAccess<'de>,
+            {
+                let mut vec = Vec::with_capacity(seq.size_hint().unwrap_or(1536));
+
+                while let Some(float) = seq.next_element()? {
+                    vec.push(float);
+                }
+
+                Ok(RawEmbeddingVector::Float(vec))
+            }
+
+            fn visit_str<E>(self, base64: &str) -> Result<Self::Value, E>
+            where
+                E: de::Error,
+            {
+                decode_base64(base64)
+                    .map(RawEmbeddingVector::Base64)
+                    .map_err(E::custom)
+            }
+        }
+
+        deserializer.deserialize_any(RawEmbeddingVectorVisitor)
+    }
+}
+
+/// Orders embeddings by their index, in case the API doesn't return them in input order.
+fn sorted_by_index<'de, D>(deserializer: D) -> Result<Vec<Embedding>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    let mut data = Vec::<Embedding>::deserialize(deserializer)?;
+
+    data.sort_by_key(|embedding| embedding.index);
+
+    Ok(data)
+}
+
+/// Decodes a base64 embedding straight into its floats, without an intermediate buffer of bytes.
+// `is_multiple_of` needs Rust 1.87
+#[allow(clippy::manual_is_multiple_of)]
+fn decode_base64(base64: &str) -> Result<Vec<f32>, String> {
+    let padding = base64
+        .bytes()
+        .rev()
+        .take_while(|&byte| byte == b'=')
+        .count();
+    let len = (base64.len() / 4 * 3).saturating_sub(padding);
+
+    if base64.len() % 4 != 0 || len % 4 != 0 {
+        return Err(format!(
+            "base64 embedding should be made of 4-byte floats, but has {} characters",
+            base64.len(),
+        ));
+    }
+
+    let mut vec = vec![0f32; len / 4];
+
+    let decoded = STANDARD
+        .decode_slice(base64, bytemuck::cast_slice_mut(&mut vec))
+        .map_err(|error| format!("invalid base64 embedding: {error}"))?;
+
+    if decoded != len {
+        return Err("invalid base64 embedding: unexpected padding".to_string());
+    }
+
+    // The floats are little-endian, which is a no-op on most targets
+    for float in &mut vec {
+        *float = f32::from_bits(u32::from_le(float.to_bits()));
+    }
+
+    Ok(vec)
+}
+
+impl From<RawEmbedding> for Embedding {
+    fn from(raw: RawEmbedding) -> Self {
+        match raw.embedding {
+            RawEmbeddingVector::Float(vec) => Embedding {
+                index: raw.index,
+                vec,
+                vec_f32: None,
+            },
+            RawEmbeddingVector::Base64(vec_f32) => Embedding {
+                index: raw.index,
+                vec: Vec::new(),
+                vec_f32: Some(vec_f32),
+            },
+        }
+    }
+}
+
+// Serializes the floats of either representation as `embedding`
+impl Serialize for Embedding {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        let mut embedding = serializer.serialize_struct("Embedding", 2)?;
+
+        embedding.serialize_field("index", &self.index)?;
+
+        match &self.vec_f32 {
+            Some(vec_f32) => embedding.serialize_field("embedding", vec_f32)?,
+            None => embedding.serialize_field("embedding", &self.vec)?,
+        }
+
+        embedding.end()
+    }
 }
 
 impl Embeddings {
@@ -45,22 +250,166 @@ impl Embeddings {
     /// * `input` - Input text to get embeddings for, encoded as a string or array of tokens.
     ///   To get embeddings for multiple inputs in a single request, pass an array of strings or array of token arrays.
     ///   Each input must not exceed 8192 tokens in length.
-    /// * `user` - A unique identifier representing your end-user, which can help OpenAI to monitor and detect abuse.
-    ///   [Learn more](https://beta.openai.com/docs/guides/safety-best-practices/end-user-ids).
+    /// * `user` - A unique identifier representing your end-user, see [`EndUserId`].
+    ///   Pass an empty string to not identify one.
     pub async fn create(
         client: &Client,
         model: ModelID,
         input: Vec<&str>,
-        user: &str,
+        user: impl Into<EndUserId>,
+    ) -> ApiResponseOrError<Self> {
+        Self::create_request(client, model, input, user.into(), None).await
+    }
+
+    /// Like [`Embeddings::create`], but requests the embeddings in the given format.
+    ///
+    /// Embeddings requested in [`EncodingFormat::Base64`] are decoded into their 32-bit floats,
+    /// see [`Embedding::vec_f32`], leaving [`Embedding::vec`] empty.
+    pub async fn create_with_format(
+        client: &Client,
+        model: ModelID,
+        input: Vec<&str>,
+        user: impl Into<EndUserId>,
+        encoding_format: EncodingFormat,
+    ) -> ApiResponseOrError<Self> {
+        Self::create_request(client, model, input, user.into(), Some(encoding_format)).await
+    }
+
+    /// Like [`Embeddings::create`], but also counts the tokens of each input,
+    /// see [`Embeddings::input_tokens`].
+    ///
+    /// Inputs longer than [`MAX_INPUT_TOKENS`] aren't sent, but fail with an `invalid_request_error`
+    /// naming them, see [`Embeddings::check_input`] and [`Embeddings::create_truncating`].
+    #[cfg(feature = "tokenizer")]
+    pub async fn create_counting_tokens(
+        client: &Client,
+        model: ModelID,
+        input: Vec<&str>,
+        user: impl Into<EndUserId>,
+    ) -> ApiResponseOrError<Self> {
+        let input_tokens = match Self::check_input(&model, &input) {
+            Ok(input_tokens) => input_tokens,
+            Err(error) => return Ok(Err(error.into())),
+        };
+        let mut response = Self::create_request(client, model, input, user.into(), None).await?;
+
+        if let Ok(embeddings) = &mut response {
+            embeddings.input_tokens = Some(input_tokens);
+        }
+
+        Ok(response)
+    }
+
+    /// Like [`Embeddings::create_counting_tokens`], but cuts inputs longer than [`MAX_INPUT_TOKENS`] short to fit,
+    /// see [`tokenizer::truncate`](crate::tokenizer::truncate).
+    ///
+    /// The embedding of a truncated input only represents its beginning.
+    #[cfg(feature = "tokenizer")]
+    pub async fn create_truncating(
+        client: &Client,
+        model: ModelID,
+        input: Vec<&str>,
+        user: impl Into<EndUserId>,
+    ) -> ApiResponseOrError<Self> {
+        let input = input
+            .into_iter()
+            .map(|input| crate::tokenizer::truncate(&model, input, MAX_INPUT_TOKENS))
+            .collect();
+
+        Self::create_counting_tokens(client, model, input, user).await
+    }
+
+    /// Counts the tokens of each input, failing with those longer than [`MAX_INPUT_TOKENS`],
+    /// which the API would reject without saying which ones they are.
+    #[cfg(feature = "tokenizer")]
+    pub fn check_input(model: &ModelID, input: &[&str]) -> Result<Vec<u32>, InputTooLongError> {
+        let input_tokens: Vec<usize> = input
+            .iter()
+            .map(|input| crate::tokenizer::count_tokens(model, input))
+            .collect();
+        let too_long: Vec<(usize, usize)> = input_tokens
+            .iter()
+            .copied()
+            .enumerate()
+            .filter(|&(_, tokens)| tokens > MAX_INPUT_TOKENS)
+            .collect();
+
+        if !too_long.is_empty() {
+            return Err(InputTooLongError { inputs: too_long });
+        }
+
+        Ok(input_tokens
+            .into_iter()
+            .map(|tokens| tokens as u32)
+            .collect())
+    }
+
+    /// How many tokens each input was, in the same order as `data`,
+    /// if they were counted by [`Embeddings::create_counting_tokens`].
+    ///
+    /// The API only reports the tokens of the whole batch in `usage`,
+    /// so these are counted locally and might not add up to it exactly.
+    pub fn input_tokens(&self) -> Option<&[u32]> {
+        self.input_tokens.as_deref()
+    }
+
+    async fn create_request(
+        client: &Client,
+        model: ModelID,
+        input: Vec<&str>,
+        user: EndUserId,
+        encoding_format: Option<EncodingFormat>,
     ) -> ApiResponseOrError<Self> {
         openai_post(
             client,
             "embeddings",
-            &CreateEmbeddingsRequestBody { model, input, user },
+            &CreateEmbeddingsRequestBody {
+                model,
+                input,
+                user,
+                encoding_format,
+            },
         )
         .await
     }
 
+    /// Creates embeddings for any number of inputs, sending them in batches of at most `batch_size`, all at once
+    /// (as far as [`ClientBuilder::max_concurrent_requests`](crate::ClientBuilder::max_concurrent_requests) allows).
+    ///
+    /// A failed batch doesn't fail the others, its inputs are left without embeddings and the failure is recorded,
+    /// see [`BatchedEmbeddings::retry_failed`].
+    ///
+    /// # Panics
+    ///
+    /// If `batch_size` is 0.
+    pub async fn create_batched(
+        client: &Client,
+        model: ModelID,
+        input: &[&str],
+        batch_size: usize,
+        user: impl Into<EndUserId>,
+    ) -> BatchedEmbeddings {
+        assert!(batch_size > 0, "batches should have at least one input");
+
+        let mut batched = BatchedEmbeddings {
+            embeddings: vec![None; input.len()],
+            usage: EmbeddingsUsage {
+                prompt_tokens: 0,
+                total_tokens: 0,
+            },
+            failures: Vec::new(),
+        };
+        let batches = (0..input.len())
+            .step_by(batch_size)
+            .map(|start| start..input.len().min(start + batch_size))
+            .collect();
+
+        batched
+            .create(client, model, input, batches, user.into())
+            .await;
+        batched
+    }
+
     pub fn distances(&self) -> Vec<f64> {
         let mut distances = Vec::new();
         let mut last_embedding: Option<&Embedding> = None;
@@ -77,8 +426,123 @@ impl Embeddings {
     }
 }
 
+impl BatchedEmbeddings {
+    /// Whether every batch succeeded, so every input has an embedding.
+    pub fn is_complete(&self) -> bool {
+        self.failures.is_empty()
+    }
+
+    /// Sends the failed batches again, e.g. after transient failures like rate limits,
+    /// with the same arguments [`Embeddings::create_batched`] was called with.
+    ///
+    /// Batches that fail again are recorded in `failures` again.
+    pub async fn retry_failed(
+        &mut self,
+        client: &Client,
+        model: ModelID,
+        input: &[&str],
+        user: impl Into<EndUserId>,
+    ) {
+        let batches = self
+            .failures
+            .drain(..)
+            .map(|failure| failure.inputs)
+            .collect();
+
+        self.create(client, model, input, batches, user.into())
+            .await;
+    }
+
+    async fn create(
+        &mut self,
+        client: &Client,
+        model: ModelID,
+        input: &[&str],
+        batches: Vec<Range<usize>>,
+        user: EndUserId,
+    ) {
+        let responses = future::join_all(batches.iter().map(|batch| {
+            Embeddings::create_request(
+                client,
+                model.clone(),
+                input[batch.clone()].to_vec(),
+                user.clone(),
+                None,
+            )
+        }))
+        .await;
+
+        for (batch, response) in batches.into_iter().zip(responses) {
+            let error = match response {
+                Ok(Ok(embeddings)) => {
+                    self.usage.prompt_tokens += embeddings.usage.prompt_tokens;
+                    self.usage.total_tokens += embeddings.usage.total_tokens;
+
+                    for (index, mut embedding) in batch.zip(embeddings.data) {
+                        embedding.index = index as u32;
+                        self.embeddings[index] = Some(embedding);
+                    }
+
+                    continue;
+                }
+                Ok(Err(error)) => BatchError::Api(error),
+                Err(error) => BatchError::Request(error),
+            };
+
+            self.failures.push(BatchFailure {
+                inputs: batch,
+                error,
+            });
+        }
+    }
+}
+
+impl fmt::Display for BatchError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            BatchError::Request(error) => write!(f, "{error}"),
+            BatchError::Api(error) => write!(f, "{error}"),
+        }
+    }
+}
+
+impl std::error::Error for BatchError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            BatchError::Request(error) => Some(error),
+            BatchError::Api(error) => Some(error),
+        }
+    }
+}
+
+#[cfg(feature = "tokenizer")]
+impl fmt::Display for InputTooLongError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for (index, tokens) in &self.inputs {
+            write!(f, "input {index} is {tokens} tokens, ")?;
+        }
+
+        write!(f, "over the limit of {MAX_INPUT_TOKENS}")
+    }
+}
+
+#[cfg(feature = "tokenizer")]
+impl std::error::Error for InputTooLongError {}
+
+#[cfg(feature = "tokenizer")]
+impl From<InputTooLongError> for OpenAiError {
+    fn from(error: InputTooLongError) -> Self {
+        OpenAiError::new(error.to_string(), "invalid_request_error").param("input")
+    }
+}
+
 impl Embedding {
-    pub async fn create(client: &Client, model: ModelID, input: &str, user: &str) -> ApiResponseOrError<Self> {
+    pub async fn create(
+        client: &Client,
+        model: ModelID,
+        input: &str,
+        user: impl Into<EndUserId>,
+    ) -> ApiResponseOrError<Self> {
         let response = Embeddings::create(client, model, vec![input], user).await?;
 
         match response {
@@ -87,30 +551,83 @@ impl Embedding {
         }
     }
 
+    /// The embedding as the 32-bit floats the API encoded,
+    /// if it was requested in [`EncodingFormat::Base64`].
+    ///
+    /// Takes half the memory of the same floats in [`Embedding::vec`], without losing any precision.
+    pub fn vec_f32(&self) -> Option<&[f32]> {
+        self.vec_f32.as_deref()
+    }
+
+    /// Like [`Embedding::vec_f32`], but copies the floats.
+    pub fn to_f32(&self) -> Option<Vec<f32>> {
+        self.vec_f32.clone()
+    }
+
     pub fn distance(&self, other: &Self) -> f64 {
-        let dot_product: f64 = self
-            .vec
-            .iter()
-            .zip(other.vec.iter())
-            .map(|(x, y)| x * y)
-            .sum();
-        let product_of_lengths = (self.vec.len() * other.vec.len()) as f64;
+        let dot_product: f64 = self.floats().zip(other.floats()).map(|(x, y)| x * y).sum();
+        let product_of_lengths = (self.floats().count() * other.floats().count()) as f64;
 
         dot_product / product_of_lengths
     }
+
+    /// The cosine of the angle between the embeddings, from -1 for opposite meanings to 1 for the same meaning.
+    ///
+    /// 0 if either embedding is all zeros.
+    pub fn cosine_similarity(&self, other: &Self) -> f64 {
+        let dot_product: f64 = self.floats().zip(other.floats()).map(|(x, y)| x * y).sum();
+        let norms = norm(self.floats()) * norm(other.floats());
+
+        if norms == 0.0 {
+            0.0
+        } else {
+            dot_product / norms
+        }
+    }
+
+    /// The `k` entries of `corpus` most similar to this embedding by [cosine similarity](Embedding::cosine_similarity),
+    /// most similar first, with their similarity.
+    ///
+    /// Compares against every entry, which is plenty fast for a few thousand embeddings kept in memory.
+    pub fn nearest<'a>(&self, corpus: &[(&'a str, Embedding)], k: usize) -> Vec<(&'a str, f64)> {
+        let mut similarities: Vec<_> = corpus
+            .iter()
+            .map(|(text, embedding)| (*text, self.cosine_similarity(embedding)))
+            .collect();
+
+        similarities.sort_by(|(_, a), (_, b)| b.total_cmp(a));
+        similarities.truncate(k);
+
+        similarities
+    }
+
+    /// The floats of the embedding, whichever representation it was returned in.
+    fn floats(&self) -> impl Iterator<Item = f64> + '_ {
+        // Only one of them is filled
+        let vec_f32 = self.vec_f32.iter().flatten().copied().map(f64::from);
+
+        self.vec.iter().copied().chain(vec_f32)
+    }
+}
+
+fn norm(vec: impl Iterator<Item = f64>) -> f64 {
+    vec.map(|x| x * x).sum::<f64>().sqrt()
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::tests::StubLayer;
     use dotenvy::dotenv;
+    use reqwest::StatusCode;
+    use std::sync::atomic::{AtomicUsize, Ordering};
 
     #[tokio::test]
     async fn embeddings() {
         dotenv().ok();
 
         let embeddings = Embeddings::create(
-            &Client::new(),
+            &crate::tests::live_client("embeddings"),
             ModelID::TextEmbeddingAda002,
             vec!["The food was delicious and the waiter..."],
             "",
@@ -127,7 +644,7 @@ mod tests {
         dotenv().ok();
 
         let embedding = Embedding::create(
-            &Client::new(),
+            &crate::tests::live_client("embedding"),
             ModelID::TextEmbeddingAda002,
             "The food was delicious and the waiter...",
             "",
@@ -144,10 +661,14 @@ mod tests {
         let embeddings = Embeddings {
             data: vec![
                 Embedding {
+                    index: 0,
                     vec: vec![1.0, 0.0, 0.0],
+                    vec_f32: None,
                 },
                 Embedding {
+                    index: 1,
                     vec: vec![0.0, 1.0, 0.0],
+                    vec_f32: None,
                 },
             ],
             model: ModelID::TextEmbeddingAda002,
@@ -155,6 +676,7 @@ mod tests {
                 prompt_tokens: 0,
                 total_tokens: 0,
             },
+            input_tokens: None,
         };
 
         assert_eq!(embeddings.distances()[0], 0.0);
@@ -165,10 +687,14 @@ mod tests {
         let embeddings = Embeddings {
             data: vec![
                 Embedding {
+                    index: 0,
                     vec: vec![1.0, 1.0, 0.0],
+                    vec_f32: None,
                 },
                 Embedding {
+                    index: 1,
                     vec: vec![0.0, 1.0, 0.0],
+                    vec_f32: None,
                 },
             ],
             model: ModelID::TextEmbeddingAda002,
@@ -176,8 +702,285 @@ mod tests {
                 prompt_tokens: 0,
                 total_tokens: 0,
             },
+            input_tokens: None,
         };
 
         assert_ne!(embeddings.distances()[0], 0.0);
     }
+
+    #[test]
+    fn nearest() {
+        let embedding = |vec: Vec<f64>| Embedding {
+            index: 0,
+            vec,
+            vec_f32: None,
+        };
+        let corpus = [
+            ("east", embedding(vec![1.0, 0.0])),
+            ("north", embedding(vec![0.0, 2.0])),
+            ("west", embedding(vec![-1.0, 0.0])),
+            ("northeast", embedding(vec![3.0, 3.0])),
+            ("nowhere", embedding(vec![0.0, 0.0])),
+        ];
+        let query = embedding(vec![2.0, 1.0]);
+        let nearest: Vec<_> = query
+            .nearest(&corpus, 3)
+            .into_iter()
+            .map(|(text, _)| text)
+            .collect();
+
+        assert_eq!(nearest, ["northeast", "east", "north"]);
+        assert!((query.cosine_similarity(&corpus[0].1) - 2.0 / 5f64.sqrt()).abs() < 1e-12);
+        assert_eq!(query.cosine_similarity(&corpus[4].1), 0.0);
+        assert_eq!(query.nearest(&corpus, 10).len(), corpus.len());
+    }
+
+    #[test]
+    fn fully_modeled() {
+        assert_eq!(
+            crate::tests::unmodeled_fields::<Embeddings>(include_str!(
+                "../fixtures/embeddings.json"
+            )),
+            ["data[0].object", "object"],
+        );
+    }
+
+    #[test]
+    fn field_names() {
+        assert_eq!(
+            crate::tests::misnamed_fields::<Embeddings>(include_str!(
+                "../fixtures/embeddings.json"
+            )),
+            Vec::<String>::new(),
+        );
+
+        let request = CreateEmbeddingsRequestBody {
+            model: ModelID::TextEmbeddingAda002,
+            input: vec!["The food was delicious and the waiter..."],
+            user: EndUserId::from("user-1234"),
+            encoding_format: Some(EncodingFormat::Float),
+        };
+
+        assert_eq!(
+            serde_json::to_value(&request).unwrap(),
+            serde_json::from_str::<serde_json::Value>(include_str!(
+                "../fixtures/embeddings_request.json"
+            ))
+            .unwrap(),
+        );
+    }
+
+    #[test]
+    fn unknown_model() {
+        let embeddings: Embeddings = serde_json::from_str(
+            r#"{
+                "object": "list",
+                "data": [{"object": "embedding", "index": 0, "embedding": [1.0, 0.0]}],
+                "model": "text-embedding-99-enormous",
+                "usage": {"prompt_tokens": 2, "total_tokens": 2}
+            }"#,
+        )
+        .unwrap();
+
+        assert_eq!(
+            embeddings.model,
+            ModelID::Custom("text-embedding-99-enormous".to_string()),
+        );
+        assert_eq!(embeddings.data[0].vec, [1.0, 0.0]);
+    }
+
+    #[test]
+    fn base64_embedding() {
+        // 1.0, -0.5 and 0.25 as little-endian 32-bit floats
+        let base64 = STANDARD.encode(
+            [1.0f32, -0.5, 0.25]
+                .iter()
+                .flat_map(|float| float.to_le_bytes())
+                .collect::<Vec<u8>>(),
+        );
+        let embedding: Embedding = serde_json::from_value(serde_json::json!({
+            "object": "embedding",
+            "index": 0,
+            "embedding": base64,
+        }))
+        .unwrap();
+        let float_embedding: Embedding = serde_json::from_str(
+            r#"{"object": "embedding", "index": 0, "embedding": [1.0, -0.5, 0.25]}"#,
+        )
+        .unwrap();
+
+        assert!(embedding.vec.is_empty());
+        assert_eq!(embedding.vec_f32().unwrap(), [1.0, -0.5, 0.25]);
+        assert_eq!(embedding.to_f32().unwrap(), [1.0, -0.5, 0.25]);
+        assert_eq!(float_embedding.vec, [1.0, -0.5, 0.25]);
+        assert!((embedding.cosine_similarity(&float_embedding) - 1.0).abs() < 1e-12);
+        assert_eq!(
+            serde_json::to_value(&embedding).unwrap(),
+            serde_json::to_value(&float_embedding).unwrap(),
+        );
+        assert_eq!(float_embedding.vec_f32(), None);
+        assert!(serde_json::from_str::<Embedding>(
+            r#"{"object": "embedding", "index": 0, "embedding": "AAAAAAA="}"#,
+        )
+        .is_err());
+    }
+
+    #[cfg(feature = "tokenizer")]
+    #[tokio::test]
+    async fn too_long_input() {
+        let client = length_stub(0).client();
+        let long = "hello ".repeat(MAX_INPUT_TOKENS);
+        let input = vec!["a", long.as_str()];
+        let error = Embeddings::check_input(&ModelID::TextEmbeddingAda002, &input).unwrap_err();
+
+        assert_eq!(error.inputs, [(1, MAX_INPUT_TOKENS + 1)]);
+        assert_eq!(
+            error.to_string(),
+            "input 1 is 8193 tokens, over the limit of 8192"
+        );
+
+        let Err(error) = Embeddings::create_counting_tokens(
+            &client,
+            ModelID::TextEmbeddingAda002,
+            input.clone(),
+            "",
+        )
+        .await
+        .unwrap() else {
+            panic!("the long input should be rejected");
+        };
+
+        assert_eq!(error.param.as_deref(), Some("input"));
+
+        let embeddings =
+            Embeddings::create_truncating(&client, ModelID::TextEmbeddingAda002, input, "")
+                .await
+                .unwrap()
+                .unwrap();
+
+        assert_eq!(embeddings.input_tokens(), Some([1, 8192].as_slice()));
+        // The embeddings of the stub are the lengths of the inputs
+        assert_eq!(embeddings.data[0].vec, [1.0]);
+        assert_eq!(embeddings.data[1].vec, [long.trim_end().len() as f64]);
+    }
+
+    /// Embeds every input as its length, failing batches with an input of `"fail"` the first `failures` times.
+    fn length_stub(failures: usize) -> StubLayer {
+        let failures = AtomicUsize::new(failures);
+
+        StubLayer::new(move |_, body| {
+            let input: Vec<&str> = body["input"]
+                .as_array()
+                .unwrap()
+                .iter()
+                .map(|input| input.as_str().unwrap())
+                .collect();
+            let fails = input.contains(&"fail")
+                && failures
+                    .fetch_update(Ordering::SeqCst, Ordering::SeqCst, |failures| {
+                        failures.checked_sub(1)
+                    })
+                    .is_ok();
+
+            if fails {
+                (
+                    StatusCode::SERVICE_UNAVAILABLE,
+                    serde_json::json!({"error": {"message": "overloaded", "type": "server_error"}}),
+                )
+            } else {
+                (
+                    StatusCode::OK,
+                    serde_json::json!({
+                        "object": "list",
+                        "data": input
+                            .iter()
+                            .enumerate()
+                            .map(|(index, input)| serde_json::json!({
+                                "object": "embedding",
+                                "index": index,
+                                "embedding": [input.len()]
+                            }))
+                            .collect::<Vec<_>>(),
+                        "model": "text-embedding-ada-002",
+                        "usage": {"prompt_tokens": input.len(), "total_tokens": input.len()}
+                    }),
+                )
+            }
+        })
+    }
+
+    #[tokio::test]
+    async fn create_batched() {
+        let client = length_stub(1).client();
+        let input = ["a", "bb", "fail", "dddd", "eeeee"];
+        let lengths = |batched: &BatchedEmbeddings| -> Vec<Option<(u32, f64)>> {
+            batched
+                .embeddings
+                .iter()
+                .map(|embedding| {
+                    embedding
+                        .as_ref()
+                        .map(|embedding| (embedding.index, embedding.vec[0]))
+                })
+                .collect()
+        };
+        let mut batched =
+            Embeddings::create_batched(&client, ModelID::TextEmbeddingAda002, &input, 2, "").await;
+
+        assert!(!batched.is_complete());
+        assert_eq!(
+            lengths(&batched),
+            [Some((0, 1.0)), Some((1, 2.0)), None, None, Some((4, 5.0))],
+        );
+        assert_eq!(batched.usage.total_tokens, 3);
+        assert_eq!(batched.failures.len(), 1);
+        assert_eq!(batched.failures[0].inputs, 2..4);
+        assert!(matches!(
+            &batched.failures[0].error,
+            BatchError::Api(error) if error.message == "overloaded",
+        ));
+
+        batched
+            .retry_failed(&client, ModelID::TextEmbeddingAda002, &input, "")
+            .await;
+
+        assert!(batched.is_complete());
+        assert_eq!(
+            lengths(&batched),
+            [
+                Some((0, 1.0)),
+                Some((1, 2.0)),
+                Some((2, 4.0)),
+                Some((3, 4.0)),
+                Some((4, 5.0))
+            ],
+        );
+        assert_eq!(batched.usage.total_tokens, 5);
+    }
+
+    #[test]
+    fn shuffled_response() {
+        let embeddings: Embeddings = serde_json::from_str(
+            r#"{
+                "object": "list",
+                "data": [
+                    {"object": "embedding", "index": 2, "embedding": [0.0, 0.0, 1.0]},
+                    {"object": "embedding", "index": 0, "embedding": [1.0, 0.0, 0.0]},
+                    {"object": "embedding", "index": 1, "embedding": [0.0, 1.0, 0.0]}
+                ],
+                "model": "text-embedding-ada-002",
+                "usage": {"prompt_tokens": 3, "total_tokens": 3}
+            }"#,
+        )
+        .unwrap();
+        let indices: Vec<u32> = embeddings
+            .data
+            .iter()
+            .map(|embedding| embedding.index)
+            .collect();
+
+        assert_eq!(indices, [0, 1, 2]);
+        assert_eq!(embeddings.data[0].vec, [1.0, 0.0, 0.0]);
+        assert_eq!(embeddings.data[2].vec, [0.0, 0.0, 1.0]);
+    }
 }
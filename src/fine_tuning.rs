@@ -0,0 +1,166 @@
+//! Tailor a model to your application by training it on your own examples.
+//!
+//! Related guide: [Fine-tuning](https://platform.openai.com/docs/guides/fine-tuning)
+
+use super::{models::ModelID, openai_request, paginate, ApiResponseOrError, Client, Page};
+use futures::Stream;
+use reqwest::Method;
+use serde::{Deserialize, Serialize};
+
+/// A snapshot of a model taken during a fine-tuning job,
+/// which can be used like the fine-tuned model, e.g. if it performs better.
+#[derive(Deserialize, Serialize, Clone, Debug, PartialEq)]
+pub struct Checkpoint {
+    pub id: String,
+    pub object: String,
+    /// When the checkpoint was created, as a Unix timestamp in seconds.
+    pub created_at: u64,
+    /// The ID of the model to request completions from the checkpoint with.
+    pub fine_tuned_model_checkpoint: ModelID,
+    pub fine_tuning_job_id: String,
+    /// The step of the job at which the checkpoint was created.
+    pub step_number: u64,
+    pub metrics: CheckpointMetrics,
+}
+
+/// How the model performed at the step of a [`Checkpoint`].
+///
+/// Validation metrics are only reported if the job has a validation file.
+#[derive(Deserialize, Serialize, Clone, Copy, Debug, Default, PartialEq)]
+pub struct CheckpointMetrics {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub step: Option<f64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub train_loss: Option<f64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub train_mean_token_accuracy: Option<f64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub valid_loss: Option<f64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub valid_mean_token_accuracy: Option<f64>,
+    /// The loss on the whole validation file, rather than a batch of it.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub full_valid_loss: Option<f64>,
+    /// The token accuracy on the whole validation file, rather than a batch of it.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub full_valid_mean_token_accuracy: Option<f64>,
+}
+
+/// Pagination for [`Checkpoint::list`].
+#[derive(Clone, Debug, Default)]
+pub struct CheckpointListParams {
+    /// The ID of the last checkpoint from the previous page, see [`Page::last_id`].
+    pub after: Option<String>,
+    /// Number of checkpoints to retrieve, defaults to 10.
+    pub limit: Option<u32>,
+}
+
+impl Checkpoint {
+    /// Lists a page of the checkpoints of a fine-tuning job.
+    pub async fn list(
+        client: &Client,
+        fine_tuning_job_id: &str,
+        params: &CheckpointListParams,
+    ) -> ApiResponseOrError<Page<Self>> {
+        openai_request(
+            client,
+            Method::GET,
+            &format!("fine_tuning/jobs/{fine_tuning_job_id}/checkpoints"),
+            |request| request.query(&params.query()),
+        )
+        .await
+    }
+
+    /// Lists every checkpoint of a fine-tuning job,
+    /// fetching further pages as the stream is consumed.
+    ///
+    /// `params.after` is only used for the first page.
+    pub fn list_all(
+        client: &Client,
+        fine_tuning_job_id: &str,
+        params: CheckpointListParams,
+    ) -> impl Stream<Item = ApiResponseOrError<Self>> + Send {
+        let client = client.clone();
+        let fine_tuning_job_id = fine_tuning_job_id.to_string();
+
+        paginate(move |after| {
+            let client = client.clone();
+            let fine_tuning_job_id = fine_tuning_job_id.clone();
+            let params = CheckpointListParams {
+                after: after.or_else(|| params.after.clone()),
+                ..params.clone()
+            };
+
+            async move { Self::list(&client, &fine_tuning_job_id, &params).await }
+        })
+    }
+}
+
+impl CheckpointListParams {
+    fn query(&self) -> Vec<(&'static str, String)> {
+        let mut query = Vec::new();
+
+        if let Some(after) = &self.after {
+            query.push(("after", after.clone()));
+        }
+
+        if let Some(limit) = self.limit {
+            query.push(("limit", limit.to_string()));
+        }
+
+        query
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn checkpoint() {
+        let page: Page<Checkpoint> = serde_json::from_str(
+            r#"{
+                "object": "list",
+                "data": [
+                    {
+                        "object": "fine_tuning.job.checkpoint",
+                        "id": "ftckpt_zc4Q7MP6XxulcVzj4MZdwsAB",
+                        "created_at": 1721764867,
+                        "fine_tuned_model_checkpoint": "ft:gpt-4o-mini-2024-07-18:my-org:custom-suffix:96olL566:ckpt-step-2000",
+                        "metrics": {
+                            "full_valid_loss": 0.134,
+                            "full_valid_mean_token_accuracy": 0.874
+                        },
+                        "fine_tuning_job_id": "ftjob-abc123",
+                        "step_number": 2000
+                    }
+                ],
+                "first_id": "ftckpt_zc4Q7MP6XxulcVzj4MZdwsAB",
+                "last_id": "ftckpt_zc4Q7MP6XxulcVzj4MZdwsAB",
+                "has_more": true
+            }"#,
+        )
+        .unwrap();
+        let checkpoint = &page.data[0];
+
+        assert_eq!(
+            checkpoint.fine_tuned_model_checkpoint.to_string(),
+            "ft:gpt-4o-mini-2024-07-18:my-org:custom-suffix:96olL566:ckpt-step-2000",
+        );
+        assert_eq!(checkpoint.step_number, 2000);
+        assert_eq!(checkpoint.metrics.full_valid_loss, Some(0.134));
+        assert_eq!(checkpoint.metrics.train_loss, None);
+        assert!(page.has_more);
+        assert_eq!(
+            CheckpointListParams {
+                after: page.last_id,
+                limit: Some(5),
+            }
+            .query(),
+            [
+                ("after", "ftckpt_zc4Q7MP6XxulcVzj4MZdwsAB".to_string()),
+                ("limit", "5".to_string()),
+            ],
+        );
+    }
+}
@@ -0,0 +1,411 @@
+//! Given a prompt, the model will generate new images.
+//!
+//! Related guide: [Image generation](https://platform.openai.com/docs/guides/images)
+
+use super::{
+    invalid_request, models::ModelID, openai_dry_run, openai_post, ApiResponseOrError, Client,
+    DryRun, EndUserId,
+};
+use base64::{engine::general_purpose::STANDARD, Engine};
+use derive_builder::Builder;
+use serde::{Deserialize, Serialize};
+
+#[derive(Deserialize, Serialize, Clone, Debug)]
+pub struct Images {
+    pub created: u64,
+    /// The generated images, as many as `n` requested.
+    pub data: Vec<Image>,
+}
+
+/// A generated image, either as a URL or encoded in base64, depending on the requested `response_format`.
+#[derive(Deserialize, Serialize, Clone, Debug, PartialEq, Eq)]
+pub struct Image {
+    /// Where the image can be downloaded from, for an hour after it was generated.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub url: Option<String>,
+    /// The image encoded in base64, see [`Image::bytes`].
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub b64_json: Option<String>,
+    /// The prompt the image was actually generated from, as `dall-e-3` rewrites prompts to add detail.
+    /// `None` for models that use the prompt as it is, like `dall-e-2`.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub revised_prompt: Option<String>,
+}
+
+/// The format [`Images`] are returned in.
+#[derive(Deserialize, Serialize, Clone, Copy, Debug, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+#[non_exhaustive]
+pub enum ImageResponseFormat {
+    /// A URL to download each image from, the default.
+    Url,
+    /// Each image encoded in base64.
+    B64Json,
+}
+
+/// The size of the generated images, each model only generates some of them.
+#[derive(Deserialize, Serialize, Clone, Copy, Debug, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum ImageSize {
+    /// 256x256 pixels, `dall-e-2` only.
+    #[serde(rename = "256x256")]
+    Square256,
+    /// 512x512 pixels, `dall-e-2` only.
+    #[serde(rename = "512x512")]
+    Square512,
+    /// 1024x1024 pixels, the default.
+    #[serde(rename = "1024x1024")]
+    Square1024,
+    /// 1792x1024 pixels, `dall-e-3` only.
+    #[serde(rename = "1792x1024")]
+    Landscape1792,
+    /// 1024x1792 pixels, `dall-e-3` only.
+    #[serde(rename = "1024x1792")]
+    Portrait1792,
+}
+
+/// The quality of the generated images, `dall-e-3` only.
+#[derive(Deserialize, Serialize, Clone, Copy, Debug, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+#[non_exhaustive]
+pub enum ImageQuality {
+    /// The default.
+    Standard,
+    /// Finer details and greater consistency across the image, at a higher price.
+    Hd,
+}
+
+/// The style of the generated images, `dall-e-3` only.
+#[derive(Deserialize, Serialize, Clone, Copy, Debug, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+#[non_exhaustive]
+pub enum ImageStyle {
+    /// Hyper-real and dramatic images, the default.
+    Vivid,
+    /// More natural, less hyper-real looking images.
+    Natural,
+}
+
+#[derive(Serialize, Builder, Debug, Clone)]
+#[builder(pattern = "owned")]
+#[builder(name = "ImageBuilder")]
+#[builder(setter(strip_option, into))]
+#[builder(build_fn(validate = "Self::validate"))]
+pub struct ImageRequest {
+    /// A text description of the desired images.
+    pub prompt: String,
+    /// ID of the model to use, `dall-e-2` if unset.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    #[builder(default)]
+    pub model: Option<ModelID>,
+    /// How many images to generate, between 1 and 10.
+    /// `dall-e-3` only generates one image per request.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    #[builder(setter(into = false), default)]
+    pub n: Option<u8>,
+    /// The size of the images, 1024x1024 if unset.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    #[builder(default)]
+    pub size: Option<ImageSize>,
+    /// The quality of the images, `dall-e-3` only.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    #[builder(default)]
+    pub quality: Option<ImageQuality>,
+    /// The style of the images, `dall-e-3` only.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    #[builder(default)]
+    pub style: Option<ImageStyle>,
+    /// The format the images are returned in, URLs if unset.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    #[builder(default)]
+    pub response_format: Option<ImageResponseFormat>,
+    #[serde(skip_serializing_if = "EndUserId::is_empty")]
+    #[builder(default)]
+    pub user: EndUserId,
+}
+
+impl Images {
+    async fn create(client: &Client, request: &ImageRequest) -> ApiResponseOrError<Self> {
+        openai_post(client, "images/generations", request).await
+    }
+
+    pub fn builder(prompt: impl Into<String>) -> ImageBuilder {
+        ImageBuilder::create_empty().prompt(prompt)
+    }
+
+    /// The URLs of the images, skipping those returned in base64.
+    pub fn urls(&self) -> impl Iterator<Item = &str> {
+        self.data.iter().filter_map(|image| image.url.as_deref())
+    }
+}
+
+impl Image {
+    /// Decodes the image if it was returned in base64, `None` if it was returned as a URL.
+    pub fn bytes(&self) -> Option<Result<Vec<u8>, base64::DecodeError>> {
+        self.b64_json.as_ref().map(|base64| STANDARD.decode(base64))
+    }
+}
+
+impl ImageSize {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            ImageSize::Square256 => "256x256",
+            ImageSize::Square512 => "512x512",
+            ImageSize::Square1024 => "1024x1024",
+            ImageSize::Landscape1792 => "1792x1024",
+            ImageSize::Portrait1792 => "1024x1792",
+        }
+    }
+}
+
+impl ImageBuilder {
+    /// The model used when none is set.
+    const DEFAULT_MODEL: &'static str = "dall-e-2";
+    /// The most images a request can generate.
+    const MAX_N: u8 = 10;
+    /// The sizes each model generates, the sizes of other models aren't checked.
+    const SIZES: &'static [(&'static str, &'static [ImageSize])] = &[
+        (
+            "dall-e-2",
+            &[
+                ImageSize::Square256,
+                ImageSize::Square512,
+                ImageSize::Square1024,
+            ],
+        ),
+        (
+            "dall-e-3",
+            &[
+                ImageSize::Square1024,
+                ImageSize::Landscape1792,
+                ImageSize::Portrait1792,
+            ],
+        ),
+    ];
+
+    /// Requests that fail to build, e.g. asking `dall-e-3` for several images,
+    /// fail with an `invalid_request_error` without being sent.
+    pub async fn create(self, client: &Client) -> ApiResponseOrError<Images> {
+        match self.build() {
+            Ok(request) => Images::create(client, &request).await,
+            Err(error) => Ok(Err(invalid_request(error))),
+        }
+    }
+
+    /// Returns the request [`create`](Self::create) would send, without sending it.
+    pub fn dry_run(self, client: &Client) -> ApiResponseOrError<DryRun> {
+        match self.build() {
+            Ok(request) => Ok(Ok(openai_dry_run(client, "images/generations", &request)?)),
+            Err(error) => Ok(Err(invalid_request(error))),
+        }
+    }
+
+    fn validate(&self) -> Result<(), String> {
+        let model = match &self.model {
+            Some(Some(model)) => model.to_string(),
+            _ => Self::DEFAULT_MODEL.to_string(),
+        };
+        let max_n = match model.as_str() {
+            "dall-e-3" => 1,
+            _ => Self::MAX_N,
+        };
+
+        match self.n {
+            Some(Some(n)) if n != 1 && max_n == 1 => {
+                return Err(format!(
+                    "`{model}` only generates one image per request, but `n` is {n}"
+                ))
+            }
+            Some(Some(n)) if !(1..=max_n).contains(&n) => {
+                return Err(format!(
+                    "`n` should be between 1 and {max_n} for `{model}`, but is {n}"
+                ))
+            }
+            _ => {}
+        }
+
+        if model == "dall-e-2" {
+            if let Some(Some(_)) = self.quality {
+                return Err(format!("`quality` isn't supported by `{model}`"));
+            }
+
+            if let Some(Some(_)) = self.style {
+                return Err(format!("`style` isn't supported by `{model}`"));
+            }
+        }
+
+        let sizes = Self::SIZES
+            .iter()
+            .find(|(sizes_of, _)| *sizes_of == model)
+            .map(|(_, sizes)| *sizes);
+
+        match (self.size, sizes) {
+            (Some(Some(size)), Some(sizes)) if !sizes.contains(&size) => Err(format!(
+                "`{model}` generates images of {}, but not {}",
+                sizes
+                    .iter()
+                    .map(ImageSize::as_str)
+                    .collect::<Vec<_>>()
+                    .join(", "),
+                size.as_str(),
+            )),
+            _ => Ok(()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn n_range() {
+        let n = |model: Option<&str>, n: u8| {
+            let builder = Images::builder("A cute baby sea otter").n(n);
+            let builder = match model {
+                Some(model) => builder.model(ModelID::Custom(model.to_string())),
+                None => builder,
+            };
+
+            builder.build().map_err(|error| error.to_string())
+        };
+
+        assert_eq!(serde_json::to_value(n(None, 10).unwrap()).unwrap()["n"], 10);
+        assert!(n(Some("dall-e-2"), 2).is_ok());
+        assert!(n(Some("dall-e-3"), 1).is_ok());
+        assert_eq!(
+            n(Some("dall-e-3"), 2).unwrap_err(),
+            "`dall-e-3` only generates one image per request, but `n` is 2",
+        );
+        assert_eq!(
+            n(None, 11).unwrap_err(),
+            "`n` should be between 1 and 10 for `dall-e-2`, but is 11",
+        );
+        assert_eq!(
+            n(Some("dall-e-2"), 0).unwrap_err(),
+            "`n` should be between 1 and 10 for `dall-e-2`, but is 0",
+        );
+    }
+
+    #[tokio::test]
+    async fn invalid_parameters() {
+        let client = Client::builder()
+            .credentials(crate::Credentials::new("sk-test"))
+            .build()
+            .unwrap();
+        let builder = || {
+            Images::builder("A cute baby sea otter")
+                .model(ModelID::Custom("dall-e-3".to_string()))
+                .n(2)
+        };
+        let error = builder().create(&client).await.unwrap().unwrap_err();
+
+        assert_eq!(error.error_type, "invalid_request_error");
+        assert_eq!(
+            error.message,
+            "`dall-e-3` only generates one image per request, but `n` is 2",
+        );
+        assert_eq!(
+            builder().dry_run(&client).unwrap().unwrap_err().message,
+            error.message,
+        );
+    }
+
+    #[test]
+    fn dall_e_3_parameters() {
+        let request = Images::builder("A cute baby sea otter")
+            .model(ModelID::Custom("dall-e-3".to_string()))
+            .size(ImageSize::Landscape1792)
+            .quality(ImageQuality::Hd)
+            .style(ImageStyle::Natural)
+            .build()
+            .unwrap();
+
+        assert_eq!(
+            serde_json::to_value(request).unwrap(),
+            serde_json::json!({
+                "prompt": "A cute baby sea otter",
+                "model": "dall-e-3",
+                "size": "1792x1024",
+                "quality": "hd",
+                "style": "natural"
+            }),
+        );
+        assert_eq!(
+            serde_json::to_value(Images::builder("A cute baby sea otter").build().unwrap())
+                .unwrap(),
+            serde_json::json!({"prompt": "A cute baby sea otter"}),
+        );
+
+        let error = |builder: ImageBuilder| builder.build().unwrap_err().to_string();
+
+        assert_eq!(
+            error(Images::builder("A cute baby sea otter").quality(ImageQuality::Standard)),
+            "`quality` isn't supported by `dall-e-2`",
+        );
+        assert_eq!(
+            error(
+                Images::builder("A cute baby sea otter")
+                    .model(ModelID::Custom("dall-e-2".to_string()))
+                    .style(ImageStyle::Vivid)
+            ),
+            "`style` isn't supported by `dall-e-2`",
+        );
+        assert_eq!(
+            error(Images::builder("A cute baby sea otter").size(ImageSize::Portrait1792)),
+            "`dall-e-2` generates images of 256x256, 512x512, 1024x1024, but not 1024x1792",
+        );
+        assert_eq!(
+            error(
+                Images::builder("A cute baby sea otter")
+                    .model(ModelID::Custom("dall-e-3".to_string()))
+                    .size(ImageSize::Square256)
+            ),
+            "`dall-e-3` generates images of 1024x1024, 1792x1024, 1024x1792, but not 256x256",
+        );
+    }
+
+    #[test]
+    fn multiple_images() {
+        let images: Images = serde_json::from_value(serde_json::json!({
+            "created": 1589478378,
+            "data": [
+                {"url": "https://example.com/otter-1.png"},
+                {"b64_json": STANDARD.encode(b"\x89PNG")},
+                {"url": "https://example.com/otter-3.png"}
+            ]
+        }))
+        .unwrap();
+
+        assert_eq!(images.data.len(), 3);
+        assert_eq!(
+            images.urls().collect::<Vec<_>>(),
+            [
+                "https://example.com/otter-1.png",
+                "https://example.com/otter-3.png"
+            ],
+        );
+        assert!(images.data[0].bytes().is_none());
+        assert_eq!(images.data[1].bytes().unwrap().unwrap(), b"\x89PNG");
+    }
+
+    #[test]
+    fn revised_prompt() {
+        let images: Images = serde_json::from_value(serde_json::json!({
+            "created": 1589478378,
+            "data": [{
+                "url": "https://example.com/otter.png",
+                "revised_prompt": "A fluffy baby sea otter floating on its back in calm blue water."
+            }]
+        }))
+        .unwrap();
+        let dall_e_2: Image =
+            serde_json::from_str(r#"{"url": "https://example.com/otter.png"}"#).unwrap();
+
+        assert_eq!(
+            images.data[0].revised_prompt.as_deref(),
+            Some("A fluffy baby sea otter floating on its back in calm blue water."),
+        );
+        assert_eq!(dall_e_2.revised_prompt, None);
+    }
+}
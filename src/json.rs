@@ -0,0 +1,78 @@
+//! Decoding of response bodies, with `serde_json`,
+//! or with `simd-json` if the feature of the same name is enabled.
+//!
+//! Public for the benchmarks only, it isn't part of the API.
+
+use super::DeserializationError;
+use serde::de::DeserializeOwned;
+
+/// Decodes a JSON body, keeping it in the error if it doesn't have the expected shape.
+pub fn decode<T: DeserializeOwned>(body: Vec<u8>) -> Result<T, DeserializationError> {
+    decode_as::<T, T>(body)
+}
+
+/// Decodes a JSON body as `D`, which is `T` or wraps it, reporting errors as decoding `T`,
+/// which says more than the error decoding an untagged wrapper.
+#[cfg(not(feature = "simd-json"))]
+pub(crate) fn decode_as<T, D>(body: Vec<u8>) -> Result<D, DeserializationError>
+where
+    T: DeserializeOwned,
+    D: DeserializeOwned,
+{
+    serde_json::from_slice(&body).map_err(|error| {
+        let error = serde_json::from_slice::<T>(&body).err().unwrap_or(error);
+
+        DeserializationError::new::<T>(&body, error)
+    })
+}
+
+/// Decodes a JSON body as `D`, which is `T` or wraps it, reporting errors as decoding `T`.
+///
+/// `simd-json` decodes the body in place, without copying it, so it can't be decoded again as `T`
+/// and its errors don't have a line and column. The body kept in the error
+/// may have had the escape sequences in its strings decoded already.
+#[cfg(feature = "simd-json")]
+pub(crate) fn decode_as<T, D>(mut body: Vec<u8>) -> Result<D, DeserializationError>
+where
+    T: DeserializeOwned,
+    D: DeserializeOwned,
+{
+    simd_json::serde::from_slice(&mut body)
+        .map_err(|error| DeserializationError::new::<T>(&body, serde::de::Error::custom(error)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::embeddings::Embeddings;
+
+    #[test]
+    fn embeddings() {
+        let embeddings: Embeddings = decode(
+            br#"{
+                "object": "list",
+                "data": [{"object": "embedding", "index": 0, "embedding": [0.5, -0.25]}],
+                "model": "text-embedding-ada-002",
+                "usage": {"prompt_tokens": 8, "total_tokens": 8}
+            }"#
+            .to_vec(),
+        )
+        .unwrap();
+
+        assert_eq!(embeddings.data[0].vec, [0.5, -0.25]);
+    }
+
+    #[test]
+    fn invalid() {
+        let error = match decode::<Embeddings>(br#"{"object": "list""#.to_vec()) {
+            Err(error) => error,
+            Ok(_) => panic!("the body should fail to decode"),
+        };
+
+        assert_eq!(error.type_name, "openai::embeddings::Embeddings");
+        assert_eq!(error.body, r#"{"object": "list""#);
+        // `simd-json`'s errors aren't classified
+        #[cfg(not(feature = "simd-json"))]
+        assert!(error.error.is_eof());
+    }
+}
@@ -0,0 +1,428 @@
+//! Layers wrap every request a client sends, for concerns that cut across all routes,
+//! like retries, logging or limiting concurrency, see [`ClientBuilder::layer`](crate::ClientBuilder::layer).
+//!
+//! A layer gets each request along with the rest of the stack, [`Next`],
+//! and decides whether, how often and in what shape it's passed on, and what's done with the response.
+//! The bottom of the stack sends the request and reads the whole response.
+//!
+//! Streamed completions don't go through the layers.
+
+use super::{client::RetryPolicy, models::ModelID, RequestError, Usage};
+use async_trait::async_trait;
+use futures_timer::Delay;
+use reqwest::{
+    header::{HeaderMap, HeaderName},
+    StatusCode,
+};
+use serde::Deserialize;
+use std::{
+    fmt,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc,
+    },
+    time::{Duration, Instant},
+};
+use tokio::sync::Semaphore;
+
+/// The header OpenAI identifies responses with, which its support asks for.
+pub(crate) const REQUEST_ID: HeaderName = HeaderName::from_static("x-request-id");
+
+/// Wraps the requests a client sends, see the [module documentation](self).
+///
+/// The trait is object safe, layers are stored as `Box<dyn Layer>`.
+#[async_trait]
+pub trait Layer: Send + Sync {
+    /// Handles a request, usually passing it on to `next` and returning its response.
+    async fn call(&self, request: Request, next: Next<'_>) -> Result<Response, RequestError>;
+}
+
+/// The layers below the current one, and the HTTP client at the bottom of the stack.
+///
+/// Can be run any number of times, e.g. to retry a request.
+#[derive(Clone, Copy)]
+pub struct Next<'a> {
+    http: &'a reqwest::Client,
+    layers: &'a [Box<dyn Layer>],
+}
+
+/// A request on its way through the layers.
+#[derive(Debug)]
+pub struct Request {
+    /// The route of the API that is requested, e.g. `chat/completions`.
+    pub route: String,
+    /// The authorized request, as it will be sent.
+    pub http: reqwest::Request,
+}
+
+/// A response on its way back through the layers, with its whole body.
+#[derive(Debug, Clone)]
+pub struct Response {
+    pub status: StatusCode,
+    pub headers: HeaderMap,
+    pub body: Vec<u8>,
+}
+
+/// Retries failed requests, see [`RetryPolicy`] and [`ClientBuilder::retry_policy`](crate::ClientBuilder::retry_policy).
+///
+/// Requests whose body can't be cloned, like streamed uploads, are only sent once.
+#[derive(Debug, Clone)]
+pub struct RetryLayer {
+    policy: RetryPolicy,
+}
+
+/// Limits how many requests are in flight at once,
+/// see [`ClientBuilder::max_concurrent_requests`](crate::ClientBuilder::max_concurrent_requests).
+#[derive(Debug)]
+pub struct ConcurrencyLimitLayer {
+    permits: Semaphore,
+}
+
+/// Refuses to send requests with [`RequestError::BudgetExceeded`] once the requests before them
+/// have used up a number of tokens or cost an amount of money, so a runaway loop can't spend without bound.
+///
+/// Clones share what has been spent, so keep a clone to display it, see [`BudgetLayer::spent_tokens`].
+/// The budget is checked before every request, so requests in flight at once can together overshoot it.
+/// Only usage reported in the same shape as completions do is counted, and only usage of models with known pricing
+/// counts towards the cost, see [`Usage::estimated_cost`]. Streamed completions don't go through the layers.
+#[derive(Debug, Clone)]
+pub struct BudgetLayer {
+    budget: Arc<Budget>,
+}
+
+#[derive(Debug)]
+struct Budget {
+    max_tokens: Option<u64>,
+    /// In US dollars.
+    max_cost: Option<f64>,
+    tokens: AtomicU64,
+    /// In billionths of a US dollar, to be counted atomically.
+    nano_dollars: AtomicU64,
+}
+
+/// Reports every request with a line like `POST chat/completions: 200 OK in 1.2s (request ID req_123)`.
+#[derive(Clone)]
+pub struct LoggingLayer {
+    log: Arc<dyn Fn(&str) + Send + Sync>,
+}
+
+impl<'a> Next<'a> {
+    pub(crate) fn new(http: &'a reqwest::Client, layers: &'a [Box<dyn Layer>]) -> Self {
+        Next { http, layers }
+    }
+
+    /// Passes the request on to the next layer, or sends it if there are none left.
+    pub async fn run(self, request: Request) -> Result<Response, RequestError> {
+        match self.layers.split_first() {
+            Some((layer, layers)) => {
+                layer
+                    .call(
+                        request,
+                        Next {
+                            http: self.http,
+                            layers,
+                        },
+                    )
+                    .await
+            }
+            None => {
+                let response = self.http.execute(request.http).await?;
+                let response = Response {
+                    status: response.status(),
+                    headers: response.headers().clone(),
+                    body: response.bytes().await?.to_vec(),
+                };
+
+                #[cfg(feature = "tracing")]
+                tracing::debug!(
+                    route = %request.route,
+                    status = %response.status,
+                    request_id = response.request_id(),
+                    "received a response from the OpenAI API",
+                );
+
+                Ok(response)
+            }
+        }
+    }
+}
+
+impl Request {
+    /// Copies the request, `None` if its body can't be copied, like a streamed upload.
+    pub fn try_clone(&self) -> Option<Self> {
+        Some(Request {
+            route: self.route.clone(),
+            http: self.http.try_clone()?,
+        })
+    }
+}
+
+impl Response {
+    /// The `x-request-id` header, which OpenAI's support asks for,
+    /// `None` if the response doesn't have one, e.g. because a proxy stripped it.
+    pub fn request_id(&self) -> Option<&str> {
+        self.headers.get(REQUEST_ID)?.to_str().ok()
+    }
+
+    /// The tokens used by the request, if the response reports them in the same shape as completions do.
+    pub fn usage(&self) -> Option<Usage> {
+        #[derive(Deserialize)]
+        struct Metered {
+            usage: Usage,
+        }
+
+        serde_json::from_slice::<Metered>(&self.body)
+            .ok()
+            .map(|metered| metered.usage)
+    }
+}
+
+impl RetryLayer {
+    pub fn new(policy: RetryPolicy) -> Self {
+        RetryLayer { policy }
+    }
+}
+
+#[async_trait]
+impl Layer for RetryLayer {
+    async fn call(&self, request: Request, next: Next<'_>) -> Result<Response, RequestError> {
+        // The statuses of the failures retried so far, `None` for timeouts
+        let mut retried = Vec::new();
+
+        loop {
+            let attempt = match request.try_clone() {
+                Some(attempt) => attempt,
+                None => return next.run(request).await,
+            };
+            let response = next.run(attempt).await;
+            let status = match &response {
+                Ok(response) => Some(response.status),
+                Err(RequestError::Http(error)) if error.is_connect() || error.is_timeout() => None,
+                Err(_) => return response,
+            };
+
+            if status.is_some_and(|status| status.is_success())
+                || !self.policy.should_retry(status, &retried)
+            {
+                return response;
+            }
+
+            Delay::new(self.policy.delay(retried.len() as u32)).await;
+            retried.push(status);
+        }
+    }
+}
+
+impl ConcurrencyLimitLayer {
+    /// Allows at most `max_concurrent_requests` requests in flight at once.
+    ///
+    /// # Panics
+    ///
+    /// If `max_concurrent_requests` is 0, since no request could ever be sent.
+    pub fn new(max_concurrent_requests: usize) -> Self {
+        assert!(
+            max_concurrent_requests > 0,
+            "at least one request should be allowed in flight",
+        );
+
+        ConcurrencyLimitLayer {
+            permits: Semaphore::new(max_concurrent_requests),
+        }
+    }
+}
+
+#[async_trait]
+impl Layer for ConcurrencyLimitLayer {
+    async fn call(&self, request: Request, next: Next<'_>) -> Result<Response, RequestError> {
+        let _permit = self
+            .permits
+            .acquire()
+            .await
+            .expect("request semaphore is never closed");
+
+        next.run(request).await
+    }
+}
+
+impl BudgetLayer {
+    /// Stops sending requests once they've used `max_tokens` tokens in total.
+    pub fn max_tokens(max_tokens: u64) -> Self {
+        Self::new(Some(max_tokens), None)
+    }
+
+    /// Stops sending requests once they've cost an estimated `max_cost` US dollars in total.
+    pub fn max_cost(max_cost: f64) -> Self {
+        Self::new(None, Some(max_cost))
+    }
+
+    fn new(max_tokens: Option<u64>, max_cost: Option<f64>) -> Self {
+        BudgetLayer {
+            budget: Arc::new(Budget {
+                max_tokens,
+                max_cost,
+                tokens: AtomicU64::new(0),
+                nano_dollars: AtomicU64::new(0),
+            }),
+        }
+    }
+
+    /// The tokens used by the requests so far.
+    pub fn spent_tokens(&self) -> u64 {
+        self.budget.tokens.load(Ordering::Relaxed)
+    }
+
+    /// The estimated cost of the requests so far, in US dollars.
+    pub fn spent_cost(&self) -> f64 {
+        self.budget.nano_dollars.load(Ordering::Relaxed) as f64 / 1e9
+    }
+
+    /// Whether the budget has been spent, so further requests are refused.
+    pub fn is_exceeded(&self) -> bool {
+        self.budget
+            .max_tokens
+            .is_some_and(|max_tokens| self.spent_tokens() >= max_tokens)
+            || self
+                .budget
+                .max_cost
+                .is_some_and(|max_cost| self.spent_cost() >= max_cost)
+    }
+
+    fn spend(&self, response: &Response) {
+        #[derive(Deserialize)]
+        struct Billed {
+            model: ModelID,
+            usage: Usage,
+        }
+
+        let Ok(billed) = serde_json::from_slice::<Billed>(&response.body) else {
+            return;
+        };
+
+        self.budget
+            .tokens
+            .fetch_add(billed.usage.total_tokens.into(), Ordering::Relaxed);
+
+        if let Some(cost) = billed.usage.estimated_cost(&billed.model) {
+            self.budget
+                .nano_dollars
+                .fetch_add((cost * 1e9).round() as u64, Ordering::Relaxed);
+        }
+    }
+}
+
+#[async_trait]
+impl Layer for BudgetLayer {
+    async fn call(&self, request: Request, next: Next<'_>) -> Result<Response, RequestError> {
+        if self.is_exceeded() {
+            return Err(RequestError::BudgetExceeded);
+        }
+
+        let response = next.run(request).await?;
+
+        self.spend(&response);
+
+        Ok(response)
+    }
+}
+
+impl LoggingLayer {
+    /// Logs with the given function, e.g. `|line| eprintln!("{line}")`.
+    pub fn new(log: impl Fn(&str) + Send + Sync + 'static) -> Self {
+        LoggingLayer { log: Arc::new(log) }
+    }
+}
+
+#[async_trait]
+impl Layer for LoggingLayer {
+    async fn call(&self, request: Request, next: Next<'_>) -> Result<Response, RequestError> {
+        let method = request.http.method().clone();
+        let route = request.route.clone();
+        let started = Instant::now();
+        let response = next.run(request).await;
+        let duration = Duration::from_millis(started.elapsed().as_millis() as u64);
+
+        match &response {
+            Ok(response) => (self.log)(&format!(
+                "{method} {route}: {} in {duration:?}{}",
+                response.status,
+                match response.request_id() {
+                    Some(request_id) => format!(" (request ID {request_id})"),
+                    None => String::new(),
+                },
+            )),
+            Err(error) => (self.log)(&format!("{method} {route}: {error} after {duration:?}")),
+        }
+
+        response
+    }
+}
+
+impl fmt::Debug for LoggingLayer {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("LoggingLayer").finish_non_exhaustive()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use reqwest::Method;
+
+    /// Answers every request with a completion of `gpt-3.5-turbo` using 1000 tokens.
+    struct CompletionStub;
+
+    #[async_trait]
+    impl Layer for CompletionStub {
+        async fn call(&self, _request: Request, _next: Next<'_>) -> Result<Response, RequestError> {
+            Ok(Response {
+                status: StatusCode::OK,
+                headers: HeaderMap::new(),
+                body: br#"{
+                    "model": "gpt-3.5-turbo",
+                    "usage": {"prompt_tokens": 600, "completion_tokens": 400, "total_tokens": 1000}
+                }"#
+                .to_vec(),
+            })
+        }
+    }
+
+    async fn send_until_exceeded(budget: &BudgetLayer) -> usize {
+        let http = reqwest::Client::new();
+        let layers: Vec<Box<dyn Layer>> = vec![Box::new(budget.clone()), Box::new(CompletionStub)];
+        let mut sent = 0;
+
+        loop {
+            let request = Request {
+                route: "chat/completions".to_string(),
+                http: reqwest::Request::new(
+                    Method::POST,
+                    "https://api.openai.com/v1/chat/completions"
+                        .parse()
+                        .unwrap(),
+                ),
+            };
+
+            match Next::new(&http, &layers).run(request).await {
+                Ok(_) => sent += 1,
+                Err(RequestError::BudgetExceeded) => return sent,
+                Err(error) => panic!("{error}"),
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn budget() {
+        let tokens = BudgetLayer::max_tokens(2500);
+
+        assert_eq!(send_until_exceeded(&tokens).await, 3);
+        assert_eq!(tokens.spent_tokens(), 3000);
+        assert!(tokens.is_exceeded());
+
+        // 0.0003 + 0.0006 = 0.0009 dollars per request
+        let cost = BudgetLayer::max_cost(0.0018);
+
+        assert_eq!(send_until_exceeded(&cost).await, 2);
+        assert!((cost.spent_cost() - 0.0018).abs() < 1e-12);
+        assert_eq!(cost.spent_tokens(), 2000);
+    }
+}
@@ -1,23 +1,549 @@
+pub use client::{
+    Client, ClientBuilder, Credentials, FromEnvError, HealthCheckError, RequestMetrics,
+    RetryPolicy, DEFAULT_USER_AGENT,
+};
+use futures::{stream, Future, Stream, StreamExt};
+use openai_bootstrap::ApiResponse;
 pub use openai_bootstrap::OpenAiError;
-use openai_bootstrap::{authorization, ApiResponse, BASE_URL};
-pub use reqwest::Client;
-use reqwest::{Method, RequestBuilder};
-use serde::{de::DeserializeOwned, Deserialize, Serialize};
+pub use reqwest::Proxy;
+use reqwest::{
+    header::{HeaderMap, HeaderValue, AUTHORIZATION},
+    Method, RequestBuilder, Url,
+};
+#[cfg(any(feature = "native-tls", feature = "rustls-tls"))]
+pub use reqwest::{Certificate, Identity};
+use serde::{
+    de::{self, DeserializeOwned},
+    Deserialize, Deserializer, Serialize,
+};
+use std::time::Instant;
 
+#[cfg(feature = "cassettes")]
+pub mod cassette;
 pub mod chat;
+pub mod client;
 pub mod completions;
 pub mod edits;
 pub mod embeddings;
+pub mod fine_tuning;
+pub mod images;
+#[doc(hidden)]
+pub mod json;
+pub mod layer;
 pub mod models;
+pub mod moderations;
+pub mod provider;
+#[cfg(feature = "tokenizer")]
+pub mod tokenizer;
 
-#[derive(Deserialize, Clone, Copy, Debug)]
+#[derive(Deserialize, Serialize, Clone, Copy, Debug, PartialEq)]
 pub struct Usage {
     pub prompt_tokens: u32,
     pub completion_tokens: u32,
     pub total_tokens: u32,
+    /// Breakdown of the prompt tokens, omitted by older models.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub prompt_tokens_details: Option<PromptTokensDetails>,
+    /// Breakdown of the completion tokens, omitted by older models.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub completion_tokens_details: Option<CompletionTokensDetails>,
 }
 
-type ApiResponseOrError<T> = Result<Result<T, OpenAiError>, reqwest::Error>;
+#[derive(Deserialize, Serialize, Clone, Copy, Debug, Default, PartialEq)]
+pub struct PromptTokensDetails {
+    /// Prompt tokens that were read from the prompt cache.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub cached_tokens: Option<u32>,
+    /// Audio input tokens present in the prompt.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub audio_tokens: Option<u32>,
+}
+
+#[derive(Deserialize, Serialize, Clone, Copy, Debug, Default, PartialEq)]
+pub struct CompletionTokensDetails {
+    /// Tokens generated by the model for reasoning, which aren't part of the visible output.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub reasoning_tokens: Option<u32>,
+    /// Audio output tokens generated by the model.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub audio_tokens: Option<u32>,
+    /// Tokens of the [`Prediction`](chat::Prediction) that appeared in the completion.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub accepted_prediction_tokens: Option<u32>,
+    /// Tokens of the [`Prediction`](chat::Prediction) that didn't appear in the completion.
+    /// Like reasoning tokens, they're billed as completion tokens.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub rejected_prediction_tokens: Option<u32>,
+}
+
+impl Usage {
+    /// The share of prompt tokens that were read from the prompt cache, between 0 and 1.
+    ///
+    /// `None` if the model doesn't report cached tokens, or there were no prompt tokens.
+    pub fn cached_token_ratio(&self) -> Option<f32> {
+        let cached_tokens = self.prompt_tokens_details?.cached_tokens?;
+
+        if self.prompt_tokens == 0 {
+            return None;
+        }
+
+        Some(cached_tokens as f32 / self.prompt_tokens as f32)
+    }
+
+    /// Estimates what the usage cost in US dollars, `None` if the model's pricing isn't known.
+    ///
+    /// See [`ModelID::pricing`](models::ModelID::pricing) for the caveats.
+    pub fn estimated_cost(&self, model: &models::ModelID) -> Option<f64> {
+        let pricing = model.pricing()?;
+
+        Some(
+            (self.prompt_tokens as f64 * pricing.input
+                + self.completion_tokens as f64 * pricing.output)
+                / 1_000_000.0,
+        )
+    }
+}
+
+impl std::ops::AddAssign for Usage {
+    /// Adds up the usage of several requests, e.g. ones that make up a single result.
+    fn add_assign(&mut self, other: Usage) {
+        fn add(tokens: Option<u32>, other: Option<u32>) -> Option<u32> {
+            match (tokens, other) {
+                (Some(tokens), Some(other)) => Some(tokens + other),
+                (tokens, other) => tokens.or(other),
+            }
+        }
+
+        self.prompt_tokens += other.prompt_tokens;
+        self.completion_tokens += other.completion_tokens;
+        self.total_tokens += other.total_tokens;
+        self.prompt_tokens_details = match (self.prompt_tokens_details, other.prompt_tokens_details)
+        {
+            (Some(details), Some(other)) => Some(PromptTokensDetails {
+                cached_tokens: add(details.cached_tokens, other.cached_tokens),
+                audio_tokens: add(details.audio_tokens, other.audio_tokens),
+            }),
+            (details, other) => details.or(other),
+        };
+        self.completion_tokens_details = match (
+            self.completion_tokens_details,
+            other.completion_tokens_details,
+        ) {
+            (Some(details), Some(other)) => Some(CompletionTokensDetails {
+                reasoning_tokens: add(details.reasoning_tokens, other.reasoning_tokens),
+                audio_tokens: add(details.audio_tokens, other.audio_tokens),
+                accepted_prediction_tokens: add(
+                    details.accepted_prediction_tokens,
+                    other.accepted_prediction_tokens,
+                ),
+                rejected_prediction_tokens: add(
+                    details.rejected_prediction_tokens,
+                    other.rejected_prediction_tokens,
+                ),
+            }),
+            (details, other) => details.or(other),
+        };
+    }
+}
+
+/// One page of a cursor-paginated list.
+#[derive(Deserialize, Serialize, Clone, Debug)]
+pub struct Page<T> {
+    pub data: Vec<T>,
+    /// The ID of the first object in `data`.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub first_id: Option<String>,
+    /// The ID of the last object in `data`, used as the cursor to fetch the next page.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub last_id: Option<String>,
+    /// Whether there are more objects after this page.
+    #[serde(default)]
+    pub has_more: bool,
+}
+
+/// The order objects of a list are returned in, by the time they were created.
+#[derive(Deserialize, Serialize, Clone, Copy, Debug, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum Order {
+    Asc,
+    Desc,
+}
+
+/// Confirmation that an object was deleted.
+#[derive(Deserialize, Serialize, Clone, Debug, PartialEq, Eq)]
+pub struct DeletedObject {
+    pub id: String,
+    pub object: String,
+    pub deleted: bool,
+}
+
+/// A unique identifier representing your end-user, which can help OpenAI to monitor and detect abuse.
+/// [Learn more](https://platform.openai.com/docs/guides/safety-best-practices/end-user-ids).
+///
+/// Surrounding whitespace is trimmed, and an empty ID isn't sent at all.
+/// Created from a string with [`From`], e.g. `EndUserId::from("user-123")`.
+#[derive(Serialize, Deserialize, Clone, Debug, Default, PartialEq, Eq, Hash)]
+#[serde(from = "String")]
+pub struct EndUserId(String);
+
+/// A parameter outside of the range the API accepts, see [`Temperature`], [`TopP`] and [`Penalty`].
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct OutOfRangeError {
+    /// The name of the parameter, e.g. `temperature`.
+    pub parameter: &'static str,
+    pub value: f32,
+    pub min: f32,
+    pub max: f32,
+}
+
+/// Defines a float parameter that can only hold values within the given range.
+macro_rules! ranged_parameter {
+    ($(#[$attribute:meta])* $name:ident, $parameter:literal, $min:literal..=$max:literal) => {
+        $(#[$attribute])*
+        ///
+        #[doc = concat!("Created from an `f32` between ", $min, " and ", $max, " with [`TryFrom`].")]
+        #[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, PartialOrd)]
+        #[serde(try_from = "f32")]
+        pub struct $name(f32);
+
+        impl $name {
+            pub const MIN: f32 = $min;
+            pub const MAX: f32 = $max;
+
+            pub fn get(self) -> f32 {
+                self.0
+            }
+        }
+
+        impl TryFrom<f32> for $name {
+            type Error = OutOfRangeError;
+
+            fn try_from(value: f32) -> Result<Self, Self::Error> {
+                if (Self::MIN..=Self::MAX).contains(&value) {
+                    Ok($name(value))
+                } else {
+                    Err(OutOfRangeError {
+                        parameter: $parameter,
+                        value,
+                        min: Self::MIN,
+                        max: Self::MAX,
+                    })
+                }
+            }
+        }
+
+        impl TryFrom<f64> for $name {
+            type Error = OutOfRangeError;
+
+            fn try_from(value: f64) -> Result<Self, Self::Error> {
+                Self::try_from(value as f32)
+            }
+        }
+
+        impl From<$name> for f32 {
+            fn from(value: $name) -> Self {
+                value.0
+            }
+        }
+    };
+}
+
+ranged_parameter!(
+    /// What sampling temperature to use. Higher values like 0.8 will make the output more random,
+    /// while lower values like 0.2 will make it more focused and deterministic.
+    Temperature,
+    "temperature",
+    0.0..=2.0
+);
+
+ranged_parameter!(
+    /// The probability mass of the tokens considered for nucleus sampling,
+    /// so 0.1 means only the tokens comprising the top 10% probability mass are considered.
+    TopP,
+    "top_p",
+    0.0..=1.0
+);
+
+ranged_parameter!(
+    /// A presence or frequency penalty. Positive values penalize new tokens
+    /// based on whether or how often they appear in the text so far.
+    Penalty,
+    "penalty",
+    -2.0..=2.0
+);
+
+/// A request as it would have been sent, returned by the `dry_run` methods of the builders.
+#[derive(Clone, Debug)]
+pub struct DryRun {
+    pub method: Method,
+    pub url: Url,
+    /// The headers of the request, with the API key redacted.
+    ///
+    /// Headers added by the client when sending, like `User-Agent`, aren't included.
+    pub headers: HeaderMap,
+    /// The serialized JSON body.
+    pub body: Option<String>,
+}
+
+/// An error sending a request, or receiving its response.
+///
+/// Errors reported by the API itself are [`OpenAiError`]s instead.
+#[derive(Debug)]
+pub enum RequestError {
+    /// The request couldn't be sent, or its response couldn't be received.
+    Http(reqwest::Error),
+    /// The response didn't have the expected shape.
+    Deserialization(DeserializationError),
+    /// The request wasn't sent because the client's budget has been spent,
+    /// see [`BudgetLayer`](layer::BudgetLayer).
+    BudgetExceeded,
+}
+
+/// A response, or an event of a stream, that didn't have the expected shape.
+#[derive(Debug)]
+pub struct DeserializationError {
+    /// The name of the type the JSON was decoded as, e.g. `openai::chat::ChatCompletion`.
+    pub type_name: &'static str,
+    /// The offending JSON, truncated to around a kilobyte surrounding where decoding failed.
+    pub body: String,
+    pub error: serde_json::Error,
+}
+
+/// An error that ends a stream of events.
+#[derive(Debug)]
+pub enum StreamError {
+    /// The connection failed, or the API responded with an error status.
+    EventSource(reqwest_eventsource::Error),
+    /// The API sent an error in place of an event, e.g. when hitting a rate limit mid-stream.
+    Api(OpenAiError),
+    /// An event didn't have the expected shape.
+    Deserialization(DeserializationError),
+    /// No content arrived within the configured time,
+    /// see [`first_token_timeout`](chat::ChatCompletionBuilder::first_token_timeout).
+    FirstTokenTimeout,
+    /// Writing the content failed, see [`create_stream_to_writer`](chat::ChatCompletionBuilder::create_stream_to_writer).
+    Write(std::io::Error),
+}
+
+impl StreamError {
+    /// Whether the connection failed or ended early, as opposed to the API rejecting the request.
+    fn is_disconnect(&self) -> bool {
+        matches!(
+            self,
+            StreamError::EventSource(
+                reqwest_eventsource::Error::Transport(_) | reqwest_eventsource::Error::StreamEnded
+            )
+        )
+    }
+}
+
+impl DeserializationError {
+    /// How much of the body is kept.
+    const BODY_LEN: usize = 1024;
+
+    pub(crate) fn new<T>(body: &[u8], error: serde_json::Error) -> Self {
+        let body = String::from_utf8_lossy(body);
+        let body = if body.len() <= Self::BODY_LEN {
+            body.into_owned()
+        } else {
+            // Where decoding failed, from the line and column, which start at 1
+            let position = body
+                .split_inclusive('\n')
+                .take(error.line().saturating_sub(1))
+                .map(str::len)
+                .sum::<usize>()
+                + error.column();
+            let mut start = position
+                .saturating_sub(Self::BODY_LEN / 2)
+                .min(body.len() - Self::BODY_LEN);
+
+            while !body.is_char_boundary(start) {
+                start -= 1;
+            }
+
+            let mut end = start + Self::BODY_LEN;
+
+            while !body.is_char_boundary(end) {
+                end += 1;
+            }
+
+            format!(
+                "{}{}{}",
+                if start > 0 { "…" } else { "" },
+                &body[start..end],
+                if end < body.len() { "…" } else { "" },
+            )
+        };
+
+        DeserializationError {
+            type_name: std::any::type_name::<T>(),
+            body,
+            error,
+        }
+    }
+}
+
+impl std::fmt::Display for RequestError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            RequestError::Http(error) => write!(f, "{error}"),
+            RequestError::Deserialization(error) => write!(f, "{error}"),
+            RequestError::BudgetExceeded => write!(f, "the budget for requests has been spent"),
+        }
+    }
+}
+
+impl std::error::Error for RequestError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            RequestError::Http(error) => Some(error),
+            RequestError::Deserialization(error) => Some(error),
+            RequestError::BudgetExceeded => None,
+        }
+    }
+}
+
+impl From<reqwest::Error> for RequestError {
+    fn from(error: reqwest::Error) -> Self {
+        RequestError::Http(error)
+    }
+}
+
+impl From<DeserializationError> for RequestError {
+    fn from(error: DeserializationError) -> Self {
+        RequestError::Deserialization(error)
+    }
+}
+
+impl std::fmt::Display for DeserializationError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "couldn't decode `{}`: {}, in: {}",
+            self.type_name, self.error, self.body,
+        )
+    }
+}
+
+impl std::error::Error for DeserializationError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        Some(&self.error)
+    }
+}
+
+impl std::fmt::Display for StreamError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            StreamError::EventSource(error) => write!(f, "{error}"),
+            StreamError::Api(error) => write!(f, "{error}"),
+            StreamError::Deserialization(error) => write!(f, "{error}"),
+            StreamError::FirstTokenTimeout => write!(f, "timed out waiting for the first token"),
+            StreamError::Write(error) => write!(f, "failed to write the stream: {error}"),
+        }
+    }
+}
+
+impl std::error::Error for StreamError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            StreamError::EventSource(error) => Some(error),
+            StreamError::Api(error) => Some(error),
+            StreamError::Deserialization(error) => Some(error),
+            StreamError::FirstTokenTimeout => None,
+            StreamError::Write(error) => Some(error),
+        }
+    }
+}
+
+impl EndUserId {
+    pub fn as_str(&self) -> &str {
+        &self.0
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+}
+
+impl From<String> for EndUserId {
+    fn from(id: String) -> Self {
+        match id.trim() {
+            trimmed if trimmed.len() == id.len() => EndUserId(id),
+            trimmed => EndUserId(trimmed.to_string()),
+        }
+    }
+}
+
+impl From<&str> for EndUserId {
+    fn from(id: &str) -> Self {
+        EndUserId(id.trim().to_string())
+    }
+}
+
+impl std::fmt::Display for OutOfRangeError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "`{}` should be between {} and {}, but is {}",
+            self.parameter, self.min, self.max, self.value,
+        )
+    }
+}
+
+impl std::error::Error for OutOfRangeError {}
+
+impl From<std::convert::Infallible> for OutOfRangeError {
+    fn from(infallible: std::convert::Infallible) -> Self {
+        match infallible {}
+    }
+}
+
+impl From<reqwest_eventsource::Error> for StreamError {
+    fn from(error: reqwest_eventsource::Error) -> Self {
+        StreamError::EventSource(error)
+    }
+}
+
+impl From<DeserializationError> for StreamError {
+    fn from(error: DeserializationError) -> Self {
+        StreamError::Deserialization(error)
+    }
+}
+
+type ApiResponseOrError<T> = Result<Result<T, OpenAiError>, RequestError>;
+
+/// A response along with the usage it reports, only parsed when metrics are collected.
+struct Metered<T> {
+    value: T,
+    usage: Option<Usage>,
+}
+
+impl<'de, T: DeserializeOwned> Deserialize<'de> for Metered<T> {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        let value = serde_json::Value::deserialize(deserializer)?;
+        let usage = value
+            .get("usage")
+            .and_then(|usage| Usage::deserialize(usage).ok());
+
+        T::deserialize(value)
+            .map(|value| Metered { value, usage })
+            .map_err(de::Error::custom)
+    }
+}
+
+/// Decodes a response body as `D`, which is `T` or wraps it, or as an error from the API.
+///
+/// Decoding errors are reported as decoding `T`, which says more than the error decoding an untagged [`ApiResponse`].
+fn decode_response<T, D>(body: Vec<u8>) -> Result<ApiResponse<D>, RequestError>
+where
+    T: DeserializeOwned,
+    D: DeserializeOwned,
+{
+    Ok(json::decode_as::<T, _>(body)?)
+}
 
 async fn openai_request<F, T>(
     client: &Client,
@@ -29,16 +555,125 @@ where
     F: FnOnce(RequestBuilder) -> RequestBuilder,
     T: DeserializeOwned,
 {
-    let mut request = client.request(method, BASE_URL.to_owned() + route);
+    let request = layer::Request {
+        route: route.to_string(),
+        http: client
+            .finish(builder(client.request(method, route)))
+            .build()?,
+    };
+    let on_metrics = match client.on_metrics() {
+        Some(on_metrics) => on_metrics,
+        None => {
+            let response = client.send(request).await?;
+            let request_id = response.request_id().map(String::from);
+
+            return match decode_response::<T, T>(response.body)? {
+                ApiResponse::Ok(t) => Ok(Ok(t)),
+                ApiResponse::Err { mut error } => {
+                    error.request_id = request_id;
+
+                    Ok(Err(error))
+                }
+            };
+        }
+    };
+    let started = Instant::now();
+    let response = client.send(request).await;
+    let (status, request_id) = match &response {
+        Ok(response) => (
+            Some(response.status),
+            response.request_id().map(String::from),
+        ),
+        Err(_) => (None, None),
+    };
+    let api_response =
+        response.and_then(|response| decode_response::<T, Metered<T>>(response.body));
+
+    on_metrics(&RequestMetrics {
+        route,
+        status,
+        duration: started.elapsed(),
+        usage: match &api_response {
+            Ok(ApiResponse::Ok(metered)) => metered.usage,
+            _ => None,
+        },
+        request_id: request_id.as_deref(),
+    });
+
+    match api_response? {
+        ApiResponse::Ok(metered) => Ok(Ok(metered.value)),
+        ApiResponse::Err { mut error } => {
+            error.request_id = request_id;
+
+            Ok(Err(error))
+        }
+    }
+}
+
+/// Lazily fetches every page of a cursor-paginated list, yielding its objects one by one.
+///
+/// `fetch_page` is called with the cursor to fetch the page after, `None` for the first page.
+/// The stream ends after the last page, or after yielding an error.
+fn paginate<T, F, Fut>(fetch_page: F) -> impl Stream<Item = ApiResponseOrError<T>>
+where
+    F: FnMut(Option<String>) -> Fut,
+    Fut: Future<Output = ApiResponseOrError<Page<T>>>,
+{
+    // `None` once there are no pages left, `Some(None)` before the first page
+    let cursor: Option<Option<String>> = Some(None);
 
-    request = builder(request);
+    stream::unfold(
+        (fetch_page, cursor),
+        |(mut fetch_page, cursor)| async move {
+            let page = match fetch_page(cursor?).await {
+                Ok(Ok(page)) => page,
+                Ok(Err(error)) => return Some((vec![Ok(Err(error))], (fetch_page, None))),
+                Err(error) => return Some((vec![Err(error)], (fetch_page, None))),
+            };
+            let cursor = if page.has_more {
+                page.last_id.map(Some)
+            } else {
+                None
+            };
 
-    let api_response: ApiResponse<T> = authorization!(request).send().await?.json().await?;
+            Some((
+                page.data.into_iter().map(|t| Ok(Ok(t))).collect(),
+                (fetch_page, cursor),
+            ))
+        },
+    )
+    .flat_map(stream::iter)
+}
 
-    match api_response {
-        ApiResponse::Ok(t) => Ok(Ok(t)),
-        ApiResponse::Err { error } => Ok(Err(error)),
+/// The error a request that fails to build is rejected with instead of being sent,
+/// an `invalid_request_error` like the API would respond with.
+fn invalid_request(error: impl std::fmt::Display) -> OpenAiError {
+    OpenAiError::new(error.to_string(), "invalid_request_error")
+}
+
+/// Builds a request exactly like [`openai_post`] would, without sending it.
+fn openai_dry_run<J>(client: &Client, route: &str, json: &J) -> Result<DryRun, reqwest::Error>
+where
+    J: Serialize + ?Sized,
+{
+    let request = client
+        .finish(client.request(Method::POST, route).json(json))
+        .build()?;
+    let mut headers = request.headers().clone();
+
+    if headers.contains_key(AUTHORIZATION) {
+        headers.insert(AUTHORIZATION, HeaderValue::from_static("Bearer [REDACTED]"));
     }
+
+    Ok(DryRun {
+        method: request.method().clone(),
+        url: request.url().clone(),
+        headers,
+        body: request
+            .body()
+            .and_then(|body| body.as_bytes())
+            .map(|body| String::from_utf8_lossy(body).into_owned()),
+    })
 }
 
 async fn openai_get<T>(client: &Client, route: &str) -> ApiResponseOrError<T>
@@ -48,6 +683,14 @@ where
     openai_request(client, Method::GET, route, |request| request).await
 }
 
+/// Sends a `DELETE` without a body, so also without a `Content-Type`, and decodes the response like any other.
+async fn openai_delete<T>(client: &Client, route: &str) -> ApiResponseOrError<T>
+where
+    T: DeserializeOwned,
+{
+    openai_request(client, Method::DELETE, route, |request| request).await
+}
+
 async fn openai_post<J, T>(client: &Client, route: &str, json: &J) -> ApiResponseOrError<T>
 where
     J: Serialize + ?Sized,
@@ -55,3 +698,615 @@ where
 {
     openai_request(client, Method::POST, route, |request| request.json(json)).await
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use async_trait::async_trait;
+    use futures::future;
+    use layer::{Layer, Next};
+    use reqwest::{header::CONTENT_TYPE, StatusCode};
+    use std::{
+        future::Future,
+        net::SocketAddr,
+        sync::{Arc, Mutex},
+    };
+    use tokio::{
+        io::AsyncReadExt,
+        net::{TcpListener, TcpStream},
+    };
+
+    /// The client of the tests that send requests to the API.
+    ///
+    /// With the `cassettes` feature, it replays `fixtures/cassettes/{cassette}.json` if it was recorded,
+    /// without needing an API key, or records it, see [`CassetteLayer::from_env`](cassette::CassetteLayer::from_env).
+    pub(crate) fn live_client(cassette: &str) -> Client {
+        #[cfg(feature = "cassettes")]
+        if let Some(layer) = cassette::CassetteLayer::from_env(format!(
+            "{}/fixtures/cassettes/{cassette}.json",
+            env!("CARGO_MANIFEST_DIR"),
+        ))
+        .unwrap()
+        {
+            let builder = match layer.mode() {
+                // Nothing is sent, so any key will do
+                cassette::CassetteMode::Replay => {
+                    Client::builder().credentials(Credentials::new("sk-test"))
+                }
+                cassette::CassetteMode::Record => Client::builder(),
+            };
+
+            return builder.layer(layer).build().unwrap();
+        }
+
+        #[cfg(not(feature = "cassettes"))]
+        let _ = cassette;
+
+        Client::new()
+    }
+
+    /// Serves HTTP on a free local port for the tests that need a real connection, returning its address.
+    ///
+    /// Each connection is handled in a task of its own: its request is read, then `handle` gets its head,
+    /// the request line and headers, along with the connection to write the response to,
+    /// which is closed once `handle` is done with it.
+    pub(crate) async fn stub_server<F, R>(handle: F) -> SocketAddr
+    where
+        F: Fn(String, TcpStream) -> R + Send + Sync + 'static,
+        R: Future<Output = ()> + Send,
+    {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let address = listener.local_addr().unwrap();
+        let handle = Arc::new(handle);
+
+        tokio::spawn(async move {
+            loop {
+                let (mut stream, _) = listener.accept().await.unwrap();
+                let handle = handle.clone();
+
+                tokio::spawn(async move {
+                    let head = read_request(&mut stream).await;
+
+                    handle(head, stream).await;
+                });
+            }
+        });
+
+        address
+    }
+
+    /// Reads the head of a request, which it returns, then skips as much of its body as its `content-length` says.
+    async fn read_request(stream: &mut TcpStream) -> String {
+        let mut received = Vec::new();
+        let mut buffer = [0; 1024];
+        let head_length = loop {
+            if let Some(position) = received.windows(4).position(|window| window == b"\r\n\r\n") {
+                break position + 4;
+            }
+
+            let read = stream.read(&mut buffer).await.unwrap();
+
+            assert!(
+                read > 0,
+                "the connection closed before the request was complete"
+            );
+            received.extend_from_slice(&buffer[..read]);
+        };
+        let head = String::from_utf8_lossy(&received[..head_length]).into_owned();
+        let content_length = head
+            .lines()
+            .find_map(|line| {
+                line.to_lowercase()
+                    .strip_prefix("content-length:")
+                    .map(|length| length.trim().parse::<usize>().unwrap())
+            })
+            .unwrap_or(0);
+
+        while received.len() < head_length + content_length {
+            let read = stream.read(&mut buffer).await.unwrap();
+
+            assert!(
+                read > 0,
+                "the connection closed before the request was complete"
+            );
+            received.extend_from_slice(&buffer[..read]);
+        }
+
+        head
+    }
+
+    /// A chat completion of `gpt-3.5-turbo` with a single choice, as the API returns it.
+    pub(crate) fn chat_completion_json(content: &str, finish_reason: &str) -> serde_json::Value {
+        serde_json::json!({
+            "id": "chatcmpl-123",
+            "object": "chat.completion",
+            "created": 1679325191,
+            "model": "gpt-3.5-turbo",
+            "choices": [{
+                "index": 0,
+                "message": {"role": "assistant", "content": content},
+                "finish_reason": finish_reason
+            }],
+            "usage": {"prompt_tokens": 10, "completion_tokens": 5, "total_tokens": 15}
+        })
+    }
+
+    type Respond =
+        dyn Fn(&str, &serde_json::Value) -> (StatusCode, serde_json::Value) + Send + Sync;
+
+    /// Answers every request instead of sending it, for the tests that don't need a real connection,
+    /// recording the route with its query string, if any, and the JSON body (null if there's none) of each request.
+    #[derive(Clone)]
+    pub(crate) struct StubLayer {
+        respond: Arc<Respond>,
+        requests: Arc<Mutex<Vec<(String, serde_json::Value)>>>,
+    }
+
+    impl StubLayer {
+        /// Answers with the status and JSON body `respond` returns for the route and body of the request.
+        pub(crate) fn new(
+            respond: impl Fn(&str, &serde_json::Value) -> (StatusCode, serde_json::Value)
+                + Send
+                + Sync
+                + 'static,
+        ) -> Self {
+            StubLayer {
+                respond: Arc::new(respond),
+                requests: Arc::default(),
+            }
+        }
+
+        /// Answers with each of `responses` in turn, as `200 OK`.
+        pub(crate) fn responses(responses: Vec<serde_json::Value>) -> Self {
+            let responses = Mutex::new(responses.into_iter());
+
+            Self::new(move |_, _| (StatusCode::OK, responses.lock().unwrap().next().unwrap()))
+        }
+
+        /// Answers every request with the [`chat_completion_json`] of `content`.
+        pub(crate) fn chat(content: &str) -> Self {
+            let completion = chat_completion_json(content, "stop");
+
+            Self::new(move |_, _| (StatusCode::OK, completion.clone()))
+        }
+
+        /// A client that sends its requests to the stub.
+        pub(crate) fn client(&self) -> Client {
+            Client::builder()
+                .credentials(Credentials::new("sk-test"))
+                .layer(self.clone())
+                .build()
+                .unwrap()
+        }
+
+        /// The routes, with their query strings, and bodies of the requests so far, in the order they were received.
+        pub(crate) fn requests(&self) -> Vec<(String, serde_json::Value)> {
+            self.requests.lock().unwrap().clone()
+        }
+    }
+
+    #[async_trait]
+    impl Layer for StubLayer {
+        async fn call(
+            &self,
+            request: layer::Request,
+            _next: Next<'_>,
+        ) -> Result<layer::Response, RequestError> {
+            let body = match request.http.body().and_then(|body| body.as_bytes()) {
+                Some(body) => serde_json::from_slice(body).unwrap(),
+                None => serde_json::Value::Null,
+            };
+            let (status, response) = (self.respond)(&request.route, &body);
+            let route = match request.http.url().query() {
+                Some(query) => format!("{}?{query}", request.route),
+                None => request.route,
+            };
+            let mut headers = HeaderMap::new();
+
+            headers.insert(layer::REQUEST_ID, HeaderValue::from_static("req_123"));
+            self.requests.lock().unwrap().push((route, body));
+
+            Ok(layer::Response {
+                status,
+                headers,
+                body: response.to_string().into_bytes(),
+            })
+        }
+    }
+
+    /// The fields of `input` that are missing from `output`, with their paths added to `fields`.
+    /// Fields that are null or empty are skipped, as they're usually left out when serializing.
+    fn missing_fields(
+        input: &serde_json::Value,
+        output: &serde_json::Value,
+        path: &str,
+        fields: &mut Vec<String>,
+    ) {
+        use serde_json::Value;
+
+        match (input, output) {
+            (Value::Object(input), Value::Object(output)) => {
+                for (key, input) in input {
+                    let path = match path {
+                        "" => key.clone(),
+                        path => format!("{path}.{key}"),
+                    };
+                    let is_empty = match input {
+                        Value::Null => true,
+                        Value::String(string) => string.is_empty(),
+                        Value::Array(array) => array.is_empty(),
+                        Value::Object(object) => object.is_empty(),
+                        _ => false,
+                    };
+
+                    match output.get(key) {
+                        Some(output) => missing_fields(input, output, &path, fields),
+                        None if is_empty => {}
+                        None => fields.push(path),
+                    }
+                }
+            }
+            (Value::Array(input), Value::Array(output)) => {
+                for (index, (input, output)) in input.iter().zip(output).enumerate() {
+                    missing_fields(input, output, &format!("{path}[{index}]"), fields);
+                }
+            }
+            _ => {}
+        }
+    }
+
+    /// Deserializes `json` as `T` and serializes it back.
+    fn round_trip<T>(json: &str) -> (serde_json::Value, serde_json::Value)
+    where
+        T: DeserializeOwned + Serialize,
+    {
+        let input = serde_json::from_str(json).unwrap();
+        let output = serde_json::to_value(serde_json::from_str::<T>(json).unwrap()).unwrap();
+
+        (input, output)
+    }
+
+    /// The fields of `json` that are lost when deserializing it as `T` and serializing it back,
+    /// i.e. the fields the crate doesn't model, like `choices[0].message.refusal`.
+    ///
+    /// Meant to be run against recorded responses of the API in `fixtures`,
+    /// to notice when the API starts responding with new fields.
+    pub(crate) fn unmodeled_fields<T>(json: &str) -> Vec<String>
+    where
+        T: DeserializeOwned + Serialize,
+    {
+        let (input, output) = round_trip::<T>(json);
+        let mut fields = Vec::new();
+
+        missing_fields(&input, &output, "", &mut fields);
+        fields
+    }
+
+    /// The fields `T` serializes that aren't in `json`, the other way around than [`unmodeled_fields`].
+    ///
+    /// Run against the same recorded responses, these are fields named differently than the API names them,
+    /// which would otherwise go unnoticed when they're optional.
+    pub(crate) fn misnamed_fields<T>(json: &str) -> Vec<String>
+    where
+        T: DeserializeOwned + Serialize,
+    {
+        let (input, output) = round_trip::<T>(json);
+        let mut fields = Vec::new();
+
+        missing_fields(&output, &input, "", &mut fields);
+        fields
+    }
+
+    #[test]
+    fn unmodeled_fields_of_nested_objects() {
+        #[derive(Deserialize, Serialize)]
+        struct Modeled {
+            a: u32,
+            #[serde(skip_serializing_if = "Option::is_none")]
+            b: Option<Vec<Modeled>>,
+        }
+
+        assert_eq!(
+            unmodeled_fields::<Modeled>(
+                r#"{"a": 1, "c": 2, "d": null, "b": [{"a": 2, "e": "e"}, {"a": 3, "f": []}]}"#,
+            ),
+            ["b[0].e", "c"],
+        );
+    }
+
+    #[test]
+    fn misnamed_fields_of_nested_objects() {
+        #[derive(Deserialize, Serialize)]
+        struct Misnamed {
+            #[serde(alias = "max_tokens")]
+            max_token: u32,
+            #[serde(default, skip_serializing_if = "Vec::is_empty")]
+            inner: Vec<Misnamed>,
+        }
+
+        assert_eq!(
+            misnamed_fields::<Misnamed>(r#"{"max_tokens": 1, "inner": [{"max_token": 2}]}"#),
+            ["max_token"],
+        );
+    }
+
+    #[test]
+    fn deserialization_error() {
+        let error = match decode_response::<chat::ChatCompletion, chat::ChatCompletion>(
+            br#"{"id": 1}"#.to_vec(),
+        ) {
+            Err(RequestError::Deserialization(error)) => error,
+            _ => panic!("the response should fail to decode"),
+        };
+
+        assert_eq!(error.type_name, "openai::chat::ChatCompletion");
+        assert_eq!(error.body, r#"{"id": 1}"#);
+        // Rather than not matching any variant of `ApiResponse`, `simd-json` can't decode the body again
+        #[cfg(not(feature = "simd-json"))]
+        assert!(error
+            .error
+            .to_string()
+            .starts_with("invalid type: integer `1`"));
+
+        let long = format!(r#"{{"padding": "{}", "id": 1}}"#, "a".repeat(2000));
+        let error = serde_json::from_str::<chat::ChatCompletion>(&long).unwrap_err();
+        let error = DeserializationError::new::<chat::ChatCompletion>(long.as_bytes(), error);
+
+        assert!(error.body.starts_with('…'));
+        assert!(error.body.ends_with(r#"a", "id": 1}"#));
+        assert_eq!(error.body.len(), "…".len() + DeserializationError::BODY_LEN);
+    }
+
+    #[test]
+    fn end_user_id() {
+        let request = |user: &str| {
+            let request = chat::ChatCompletion::builder(
+                models::ModelID::Gpt3_5Turbo,
+                [chat::ChatCompletionMessage::user("Hello!")],
+            )
+            .user(user)
+            .build()
+            .unwrap();
+
+            serde_json::to_value(request).unwrap()
+        };
+
+        assert_eq!(request(" user-123\n")["user"], "user-123");
+        assert!(request("  ").get("user").is_none());
+        assert_eq!(
+            serde_json::from_str::<EndUserId>(r#"" user-123 ""#).unwrap(),
+            EndUserId::from("user-123"),
+        );
+    }
+
+    #[test]
+    fn usage_details() {
+        let usage: Usage = serde_json::from_str(
+            r#"{
+                "prompt_tokens": 1117,
+                "completion_tokens": 46,
+                "total_tokens": 1163,
+                "prompt_tokens_details": {"cached_tokens": 1024, "audio_tokens": 0},
+                "completion_tokens_details": {
+                    "reasoning_tokens": 32,
+                    "accepted_prediction_tokens": 12,
+                    "rejected_prediction_tokens": 3
+                }
+            }"#,
+        )
+        .unwrap();
+
+        assert_eq!(
+            usage.prompt_tokens_details,
+            Some(PromptTokensDetails {
+                cached_tokens: Some(1024),
+                audio_tokens: Some(0),
+            }),
+        );
+        assert_eq!(
+            usage.completion_tokens_details,
+            Some(CompletionTokensDetails {
+                reasoning_tokens: Some(32),
+                audio_tokens: None,
+                accepted_prediction_tokens: Some(12),
+                rejected_prediction_tokens: Some(3),
+            }),
+        );
+    }
+
+    #[test]
+    fn metered() {
+        let api_response: ApiResponse<Metered<chat::ChatCompletion>> = serde_json::from_str(
+            r#"{
+                "id": "chatcmpl-6wBU7HGxEXqdShNC81ZlfkOLDM0MF",
+                "object": "chat.completion",
+                "created": 1679325191,
+                "model": "gpt-3.5-turbo",
+                "choices": [],
+                "usage": {"prompt_tokens": 9, "completion_tokens": 12, "total_tokens": 21}
+            }"#,
+        )
+        .unwrap();
+
+        match api_response {
+            ApiResponse::Ok(metered) => {
+                assert_eq!(metered.usage.unwrap().total_tokens, 21);
+                assert_eq!(metered.value.usage.unwrap().total_tokens, 21);
+            }
+            ApiResponse::Err { error } => panic!("{error}"),
+        }
+    }
+
+    #[tokio::test]
+    async fn pagination() {
+        let pages = [
+            (None, vec![1, 2], Some("2"), true),
+            (Some("2"), vec![3, 4], Some("4"), true),
+            (Some("4"), vec![5], Some("5"), false),
+        ];
+        let mut requested_cursors = Vec::new();
+        let items: Vec<u32> = paginate(|cursor| {
+            requested_cursors.push(cursor.clone());
+
+            let (_, data, last_id, has_more) = pages
+                .iter()
+                .find(|(after, ..)| after.map(str::to_string) == cursor)
+                .unwrap()
+                .clone();
+
+            future::ready(Ok(Ok(Page {
+                data,
+                first_id: None,
+                last_id: last_id.map(str::to_string),
+                has_more,
+            })))
+        })
+        .map(|item| item.unwrap().unwrap())
+        .collect()
+        .await;
+
+        assert_eq!(items, [1, 2, 3, 4, 5]);
+        assert_eq!(
+            requested_cursors,
+            [None, Some("2".to_string()), Some("4".to_string())],
+        );
+    }
+
+    /// The method, `Content-Type` and body of a request.
+    type RecordedRequest = (Method, Option<HeaderValue>, Option<Vec<u8>>);
+
+    /// Answers every request with `{"id": "file-123", "object": "file", "deleted": true}` instead of sending it,
+    /// recording the method, `Content-Type` and body of each.
+    #[derive(Default, Clone)]
+    struct DeletedStub {
+        requests: Arc<Mutex<Vec<RecordedRequest>>>,
+    }
+
+    #[async_trait]
+    impl Layer for DeletedStub {
+        async fn call(
+            &self,
+            request: layer::Request,
+            _next: Next<'_>,
+        ) -> Result<layer::Response, RequestError> {
+            self.requests.lock().unwrap().push((
+                request.http.method().clone(),
+                request.http.headers().get(CONTENT_TYPE).cloned(),
+                request
+                    .http
+                    .body()
+                    .and_then(|body| body.as_bytes())
+                    .map(<[u8]>::to_vec),
+            ));
+
+            Ok(layer::Response {
+                status: StatusCode::OK,
+                headers: HeaderMap::new(),
+                body: br#"{"id": "file-123", "object": "file", "deleted": true}"#.to_vec(),
+            })
+        }
+    }
+
+    #[tokio::test]
+    async fn bodyless_requests() {
+        let stub = DeletedStub::default();
+        let client = Client::builder()
+            .credentials(Credentials::new("sk-test"))
+            .layer(stub.clone())
+            .build()
+            .unwrap();
+        let deleted: DeletedObject = openai_delete(&client, "files/file-123")
+            .await
+            .unwrap()
+            .unwrap();
+
+        assert!(deleted.deleted);
+
+        let _: DeletedObject = openai_post(&client, "files", &serde_json::json!({}))
+            .await
+            .unwrap()
+            .unwrap();
+
+        assert_eq!(
+            *stub.requests.lock().unwrap(),
+            [
+                (Method::DELETE, None, None),
+                (
+                    Method::POST,
+                    Some(HeaderValue::from_static("application/json")),
+                    Some(b"{}".to_vec()),
+                ),
+            ],
+        );
+    }
+
+    #[test]
+    fn constructed_error() {
+        let api_response: ApiResponse<Usage> = serde_json::from_str(
+            r#"{
+                "error": {
+                    "message": "The model `gpt-5` does not exist",
+                    "type": "invalid_request_error",
+                    "param": "model",
+                    "code": "model_not_found"
+                }
+            }"#,
+        )
+        .unwrap();
+        let expected =
+            OpenAiError::new("The model `gpt-5` does not exist", "invalid_request_error")
+                .param("model")
+                .code("model_not_found");
+
+        match api_response {
+            ApiResponse::Ok(_) => panic!("expected an error"),
+            ApiResponse::Err { error } => assert_eq!(error, expected),
+        }
+    }
+
+    #[test]
+    fn estimated_cost() {
+        let usage: Usage = serde_json::from_str(
+            r#"{"prompt_tokens": 1000, "completion_tokens": 500, "total_tokens": 1500}"#,
+        )
+        .unwrap();
+        let cost = usage.estimated_cost(&models::ModelID::Gpt3_5Turbo).unwrap();
+
+        assert!((cost - 0.00125).abs() < 1e-12);
+        assert!(usage
+            .estimated_cost(&models::ModelID::Custom("gpt-4o".to_string()))
+            .is_some());
+        assert!(usage
+            .estimated_cost(&models::ModelID::Custom(
+                "ft:gpt-4o:acme::abc123".to_string()
+            ))
+            .is_none());
+    }
+
+    #[test]
+    fn cached_token_ratio() {
+        let usage: Usage = serde_json::from_str(
+            r#"{
+                "prompt_tokens": 2048,
+                "completion_tokens": 46,
+                "total_tokens": 2094,
+                "prompt_tokens_details": {"cached_tokens": 1536}
+            }"#,
+        )
+        .unwrap();
+
+        assert_eq!(usage.cached_token_ratio(), Some(0.75));
+    }
+
+    #[test]
+    fn usage_without_details() {
+        let usage: Usage = serde_json::from_str(
+            r#"{"prompt_tokens": 9, "completion_tokens": 12, "total_tokens": 21}"#,
+        )
+        .unwrap();
+
+        assert!(usage.prompt_tokens_details.is_none());
+        assert!(usage.completion_tokens_details.is_none());
+        assert!(usage.cached_token_ratio().is_none());
+    }
+}
@@ -2,12 +2,12 @@
 //! You can refer to the [Models](https://beta.openai.com/docs/models)
 //! documentation to understand what models are available and the differences between them.
 
-use super::{openai_get, ApiResponseOrError};
+use super::{openai_get, paginate, ApiResponseOrError, Client, Page};
+use futures::Stream;
 use openai_proc_macros::generate_model_id_enum;
-use reqwest::Client;
 use serde::Deserialize;
 
-#[derive(Deserialize, Clone)]
+#[derive(Deserialize, Serialize, Clone)]
 pub struct Model {
     pub id: ModelID,
     pub created: u32,
@@ -17,7 +17,7 @@ pub struct Model {
     pub parent: Option<String>,
 }
 
-#[derive(Deserialize, Clone)]
+#[derive(Deserialize, Serialize, Clone)]
 pub struct ModelPermission {
     pub id: String,
     pub created: u32,
@@ -38,10 +38,128 @@ impl Model {
     pub async fn from(client: &Client, id: ModelID) -> ApiResponseOrError<Self> {
         openai_get(client, &format!("models/{id}")).await
     }
+
+    /// Lists the currently available models.
+    pub async fn list(client: &Client) -> ApiResponseOrError<Page<Self>> {
+        openai_get(client, "models").await
+    }
+
+    /// Lists the currently available models, fetching further pages as the stream is consumed.
+    pub fn list_all(client: &Client) -> impl Stream<Item = ApiResponseOrError<Self>> + Send {
+        let client = client.clone();
+
+        paginate(move |after| {
+            let client = client.clone();
+            let route = match after {
+                Some(after) => format!("models?after={after}"),
+                None => "models".to_string(),
+            };
+
+            async move { openai_get(&client, &route).await }
+        })
+    }
+}
+
+/// Prices of a model in US dollars per million tokens.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ModelPricing {
+    pub input: f64,
+    pub output: f64,
+}
+
+/// Known standard prices as published by OpenAI, as `(model ID, input, output)`.
+///
+/// Prices change over time, so this may drift from the current pricing.
+const PRICING: &[(&str, f64, f64)] = &[
+    ("gpt-4o", 2.5, 10.0),
+    ("gpt-4o-mini", 0.15, 0.6),
+    ("gpt-4o-audio-preview", 2.5, 10.0),
+    ("gpt-4-turbo", 10.0, 30.0),
+    ("gpt-4", 30.0, 60.0),
+    ("gpt-3.5-turbo", 0.5, 1.5),
+    ("o1", 15.0, 60.0),
+    ("o1-mini", 1.1, 4.4),
+    ("o3-mini", 1.1, 4.4),
+    ("davinci-002", 2.0, 2.0),
+    ("babbage-002", 0.4, 0.4),
+    ("text-embedding-3-small", 0.02, 0.0),
+    ("text-embedding-3-large", 0.13, 0.0),
+    ("text-embedding-ada-002", 0.1, 0.0),
+];
+
+/// Known context windows and output limits as published by OpenAI,
+/// as `(model ID, context window, maximum output tokens)`.
+const CONTEXT_WINDOWS: &[(&str, u32, u32)] = &[
+    ("gpt-4o", 128_000, 16_384),
+    ("gpt-4o-mini", 128_000, 16_384),
+    ("gpt-4o-audio-preview", 128_000, 16_384),
+    ("gpt-4-turbo", 128_000, 4_096),
+    ("gpt-4", 8_192, 8_192),
+    ("gpt-3.5-turbo", 16_385, 4_096),
+    ("o1", 200_000, 100_000),
+    ("o1-mini", 128_000, 65_536),
+    ("o3-mini", 200_000, 100_000),
+    ("davinci-002", 16_384, 16_384),
+    ("babbage-002", 16_384, 16_384),
+];
+
+/// Former and alternative names of models, as `(alias, model ID)`, like the names Azure OpenAI deploys models under.
+///
+/// Deserializing an alias gives the model it stands for, so it compares equal to the model's variant.
+const MODEL_ALIASES: &[(&str, &str)] = &[
+    ("gpt-35-turbo", "gpt-3.5-turbo"),
+    ("gpt-35-turbo-0301", "gpt-3.5-turbo-0301"),
+    ("gpt-35-turbo-16k", "gpt-3.5-turbo-16k"),
+    ("text-embedding-ada-002-v2", "text-embedding-ada-002"),
+];
+
+/// The model ID `id` is an alias of, see [`MODEL_ALIASES`], or `id` itself.
+fn canonical_model_id(id: &str) -> &str {
+    MODEL_ALIASES
+        .iter()
+        .find(|(alias, _)| *alias == id)
+        .map_or(id, |(_, model)| model)
 }
 
 generate_model_id_enum!();
 
+impl ModelID {
+    /// The standard price of the model, `None` if it isn't known,
+    /// e.g. for fine-tuned models or dated snapshots.
+    ///
+    /// The prices are built into this library and may drift from the current pricing,
+    /// they don't account for discounts such as cached input tokens or the batch API.
+    pub fn pricing(&self) -> Option<ModelPricing> {
+        let id = self.to_string();
+
+        PRICING
+            .iter()
+            .find(|(model, ..)| *model == id)
+            .map(|&(_, input, output)| ModelPricing { input, output })
+    }
+
+    /// How many tokens the prompt and the completion can take up together,
+    /// `None` if it isn't known, like for [`ModelID::pricing`].
+    pub fn context_window(&self) -> Option<u32> {
+        self.context_window_entry()
+            .map(|&(_, context_window, _)| context_window)
+    }
+
+    /// How many tokens the model can generate in a single completion, `None` if it isn't known.
+    ///
+    /// Can be less than what's left of the context window after the prompt.
+    pub fn max_output_tokens(&self) -> Option<u32> {
+        self.context_window_entry()
+            .map(|&(_, _, max_output_tokens)| max_output_tokens)
+    }
+
+    fn context_window_entry(&self) -> Option<&'static (&'static str, u32, u32)> {
+        let id = self.to_string();
+
+        CONTEXT_WINDOWS.iter().find(|(model, ..)| *model == id)
+    }
+}
+
 impl std::fmt::Display for ModelID {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         if let ModelID::Custom(id) = self {
@@ -58,6 +176,7 @@ impl std::fmt::Display for ModelID {
 mod tests {
     use super::*;
     use dotenvy::dotenv;
+    use futures::StreamExt;
 
     #[test]
     fn model_id_serialization() -> Result<(), serde_json::Error> {
@@ -89,22 +208,58 @@ mod tests {
         Ok(())
     }
 
+    #[test]
+    fn model_id_aliases() -> Result<(), serde_json::Error> {
+        assert_eq!(
+            serde_json::de::from_str::<ModelID>("\"gpt-35-turbo\"")?,
+            ModelID::Gpt3_5Turbo,
+        );
+        assert_eq!(
+            serde_json::de::from_str::<ModelID>("\"text-embedding-ada-002-v2\"")?,
+            ModelID::TextEmbeddingAda002,
+        );
+        assert_eq!(
+            serde_json::ser::to_string(&serde_json::de::from_str::<ModelID>("\"gpt-35-turbo\"")?)?,
+            "\"gpt-3.5-turbo\"",
+        );
+
+        Ok(())
+    }
+
     #[tokio::test]
     async fn model() {
         dotenv().ok();
 
-        let model = Model::from(&Client::new(), ModelID::TextDavinci003).await.unwrap().unwrap();
+        let model = Model::from(&crate::tests::live_client("model"), ModelID::TextDavinci003)
+            .await
+            .unwrap()
+            .unwrap();
 
         assert_eq!(model.id, ModelID::TextDavinci003,);
     }
 
+    #[tokio::test]
+    async fn list_all_models() {
+        dotenv().ok();
+
+        let models: Vec<Model> = Model::list_all(&crate::tests::live_client("models"))
+            .map(|model| model.unwrap().unwrap())
+            .collect()
+            .await;
+
+        assert!(models
+            .iter()
+            .any(|model| model.id == ModelID::TextDavinci003));
+    }
+
     #[tokio::test]
     async fn custom_model() {
         dotenv().ok();
 
-        let model = Model::from(&Client::new(), ModelID::Custom(
-            "davinci:ft-personal-2022-12-12-04-49-51".to_string(),
-        ))
+        let model = Model::from(
+            &crate::tests::live_client("custom_model"),
+            ModelID::Custom("davinci:ft-personal-2022-12-12-04-49-51".to_string()),
+        )
         .await
         .unwrap()
         .unwrap();
@@ -0,0 +1,269 @@
+//! Given a input text, outputs if the model classifies it as violating OpenAI's content policy.
+//!
+//! Related guide: [Moderations](https://platform.openai.com/docs/guides/moderation)
+
+use super::{
+    invalid_request, models::ModelID, openai_dry_run, openai_post, ApiResponseOrError, Client,
+    DryRun,
+};
+use derive_builder::Builder;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+
+#[derive(Deserialize, Serialize, Clone, Debug)]
+pub struct Moderation {
+    pub id: String,
+    pub model: ModelID,
+    pub results: Vec<ModerationResult>,
+}
+
+#[derive(Deserialize, Serialize, Clone, Debug)]
+pub struct ModerationResult {
+    /// Whether the API considers the input to violate OpenAI's content policy.
+    pub flagged: bool,
+    pub categories: ModerationCategories,
+    pub category_scores: ModerationCategoryScores,
+}
+
+#[derive(Deserialize, Serialize, Clone, Copy, Debug, PartialEq)]
+pub struct ModerationCategories {
+    pub hate: bool,
+    #[serde(rename = "hate/threatening")]
+    pub hate_threatening: bool,
+    #[serde(rename = "self-harm")]
+    pub self_harm: bool,
+    pub sexual: bool,
+    #[serde(rename = "sexual/minors")]
+    pub sexual_minors: bool,
+    pub violence: bool,
+    #[serde(rename = "violence/graphic")]
+    pub violence_graphic: bool,
+}
+
+#[derive(Deserialize, Serialize, Clone, Copy, Debug, PartialEq)]
+pub struct ModerationCategoryScores {
+    pub hate: f64,
+    #[serde(rename = "hate/threatening")]
+    pub hate_threatening: f64,
+    #[serde(rename = "self-harm")]
+    pub self_harm: f64,
+    pub sexual: f64,
+    #[serde(rename = "sexual/minors")]
+    pub sexual_minors: f64,
+    pub violence: f64,
+    #[serde(rename = "violence/graphic")]
+    pub violence_graphic: f64,
+}
+
+#[derive(Deserialize, Serialize, Clone, Copy, Debug, PartialEq, Eq, Hash)]
+#[non_exhaustive]
+pub enum ModerationCategory {
+    #[serde(rename = "hate")]
+    Hate,
+    #[serde(rename = "hate/threatening")]
+    HateThreatening,
+    #[serde(rename = "self-harm")]
+    SelfHarm,
+    #[serde(rename = "sexual")]
+    Sexual,
+    #[serde(rename = "sexual/minors")]
+    SexualMinors,
+    #[serde(rename = "violence")]
+    Violence,
+    #[serde(rename = "violence/graphic")]
+    ViolenceGraphic,
+}
+
+#[derive(Serialize, Builder, Debug, Clone)]
+#[builder(pattern = "owned")]
+#[builder(name = "ModerationBuilder")]
+#[builder(setter(strip_option, into))]
+pub struct ModerationRequest {
+    /// The input text to classify.
+    pub input: Vec<String>,
+    /// Two content moderations models are available: `text-moderation-stable` and `text-moderation-latest`.
+    ///
+    /// The default is `text-moderation-latest` which will be automatically upgraded over time.
+    /// This ensures you are always using our most accurate model.
+    /// If you use `text-moderation-stable`, we will provide advanced notice before updating the model.
+    /// Accuracy of `text-moderation-stable` may be slightly lower than for `text-moderation-latest`.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    #[builder(default)]
+    pub model: Option<ModelID>,
+}
+
+impl ModerationCategory {
+    pub const ALL: [Self; 7] = [
+        Self::Hate,
+        Self::HateThreatening,
+        Self::SelfHarm,
+        Self::Sexual,
+        Self::SexualMinors,
+        Self::Violence,
+        Self::ViolenceGraphic,
+    ];
+}
+
+impl Moderation {
+    /// Classifies if text violates OpenAI's Content Policy
+    async fn create(client: &Client, request: &ModerationRequest) -> ApiResponseOrError<Self> {
+        openai_post(client, "moderations", request).await
+    }
+
+    pub fn builder(input: impl Into<Vec<String>>) -> ModerationBuilder {
+        ModerationBuilder::create_empty().input(input)
+    }
+
+    /// Whether any of the inputs was flagged by the API.
+    pub fn is_flagged(&self) -> bool {
+        self.results.iter().any(ModerationResult::is_flagged)
+    }
+}
+
+impl ModerationBuilder {
+    pub async fn create(self, client: &Client) -> ApiResponseOrError<Moderation> {
+        match self.build() {
+            Ok(request) => Moderation::create(client, &request).await,
+            Err(error) => Ok(Err(invalid_request(error))),
+        }
+    }
+
+    /// Returns the request [`create`](Self::create) would send, without sending it.
+    pub fn dry_run(self, client: &Client) -> ApiResponseOrError<DryRun> {
+        match self.build() {
+            Ok(request) => Ok(Ok(openai_dry_run(client, "moderations", &request)?)),
+            Err(error) => Ok(Err(invalid_request(error))),
+        }
+    }
+}
+
+impl ModerationResult {
+    /// Whether the API considers the input to violate OpenAI's content policy.
+    pub fn is_flagged(&self) -> bool {
+        self.flagged
+    }
+
+    /// Returns the categories whose score is above the given threshold,
+    /// allowing for stricter (or looser) limits than the API's own `flagged` decision.
+    ///
+    /// Categories without a threshold fall back to the API's decision for that category.
+    pub fn exceeded_thresholds(
+        &self,
+        thresholds: &HashMap<ModerationCategory, f64>,
+    ) -> Vec<ModerationCategory> {
+        ModerationCategory::ALL
+            .into_iter()
+            .filter(|category| match thresholds.get(category) {
+                Some(threshold) => self.category_scores.get(*category) > *threshold,
+                None => self.categories.get(*category),
+            })
+            .collect()
+    }
+}
+
+impl ModerationCategories {
+    pub fn get(&self, category: ModerationCategory) -> bool {
+        match category {
+            ModerationCategory::Hate => self.hate,
+            ModerationCategory::HateThreatening => self.hate_threatening,
+            ModerationCategory::SelfHarm => self.self_harm,
+            ModerationCategory::Sexual => self.sexual,
+            ModerationCategory::SexualMinors => self.sexual_minors,
+            ModerationCategory::Violence => self.violence,
+            ModerationCategory::ViolenceGraphic => self.violence_graphic,
+        }
+    }
+}
+
+impl ModerationCategoryScores {
+    pub fn get(&self, category: ModerationCategory) -> f64 {
+        match category {
+            ModerationCategory::Hate => self.hate,
+            ModerationCategory::HateThreatening => self.hate_threatening,
+            ModerationCategory::SelfHarm => self.self_harm,
+            ModerationCategory::Sexual => self.sexual,
+            ModerationCategory::SexualMinors => self.sexual_minors,
+            ModerationCategory::Violence => self.violence,
+            ModerationCategory::ViolenceGraphic => self.violence_graphic,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use dotenvy::dotenv;
+
+    const RESULT: &str = r#"{
+        "flagged": false,
+        "categories": {
+            "hate": false,
+            "hate/threatening": false,
+            "self-harm": false,
+            "sexual": false,
+            "sexual/minors": false,
+            "violence": false,
+            "violence/graphic": false
+        },
+        "category_scores": {
+            "hate": 0.18805529177188873,
+            "hate/threatening": 0.0001250059431185946,
+            "self-harm": 0.0003706029092427343,
+            "sexual": 0.0008735615410842001,
+            "sexual/minors": 0.0007470346172340214,
+            "violence": 0.0041268812492489815,
+            "violence/graphic": 0.00023186142789199948
+        }
+    }"#;
+
+    #[tokio::test]
+    async fn moderation() {
+        dotenv().ok();
+
+        let moderation = Moderation::builder(["I want to kill them.".to_string()])
+            .create(&crate::tests::live_client("moderation"))
+            .await
+            .unwrap()
+            .unwrap();
+
+        assert!(moderation.is_flagged());
+        assert!(moderation.results.first().unwrap().categories.violence);
+    }
+
+    #[tokio::test]
+    async fn missing_input() {
+        let client = Client::builder()
+            .credentials(crate::Credentials::new("sk-test"))
+            .build()
+            .unwrap();
+        let error = ModerationBuilder::default()
+            .dry_run(&client)
+            .unwrap()
+            .unwrap_err();
+
+        assert_eq!(error.error_type, "invalid_request_error");
+        assert!(error.message.contains("input"), "{}", error.message);
+    }
+
+    #[test]
+    fn no_thresholds() {
+        let result: ModerationResult = serde_json::from_str(RESULT).unwrap();
+
+        assert!(!result.is_flagged());
+        assert!(result.exceeded_thresholds(&HashMap::new()).is_empty());
+    }
+
+    #[test]
+    fn custom_thresholds() {
+        let result: ModerationResult = serde_json::from_str(RESULT).unwrap();
+        let thresholds = HashMap::from([
+            (ModerationCategory::Hate, 0.1),
+            (ModerationCategory::Violence, 0.1),
+        ]);
+
+        assert_eq!(
+            result.exceeded_thresholds(&thresholds),
+            vec![ModerationCategory::Hate],
+        );
+    }
+}
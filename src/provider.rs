@@ -0,0 +1,113 @@
+//! A common interface to the OpenAI API and other providers of OpenAI-compatible chat completions.
+
+use super::{
+    chat::{ChatCompletion, ChatCompletionEvent, ChatCompletionRequest},
+    Client, OpenAiError, RequestError, StreamError,
+};
+use async_trait::async_trait;
+use futures::{stream::BoxStream, StreamExt};
+
+/// Creates chat completions, so code can be written without depending on a specific provider.
+///
+/// [`Client`] is the reference implementation, for the OpenAI API and any API compatible with it,
+/// see [`ClientBuilder::base_url`](crate::ClientBuilder::base_url).
+/// Providers with a different API can implement it themselves,
+/// translating the request from its JSON form, which [`ChatCompletionRequest`] serializes to.
+///
+/// The trait is object safe, so providers can be stored as `Box<dyn ChatProvider>`.
+#[async_trait]
+pub trait ChatProvider: Send + Sync {
+    /// Creates a completion for the request, see [`ChatCompletion::create`].
+    async fn create(
+        &self,
+        request: &ChatCompletionRequest,
+    ) -> Result<Result<ChatCompletion, OpenAiError>, RequestError>;
+
+    /// Streams a completion for the request as it is generated,
+    /// see [`ChatCompletionBuilder::create_stream`](crate::chat::ChatCompletionBuilder::create_stream).
+    fn create_stream(
+        &self,
+        request: &ChatCompletionRequest,
+    ) -> BoxStream<'static, Result<ChatCompletionEvent, StreamError>>;
+}
+
+#[async_trait]
+impl ChatProvider for Client {
+    async fn create(
+        &self,
+        request: &ChatCompletionRequest,
+    ) -> Result<Result<ChatCompletion, OpenAiError>, RequestError> {
+        ChatCompletion::create(self, request).await
+    }
+
+    fn create_stream(
+        &self,
+        request: &ChatCompletionRequest,
+    ) -> BoxStream<'static, Result<ChatCompletionEvent, StreamError>> {
+        request.create_stream_events(self).boxed()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{chat::ChatCompletionMessage, models::ModelID};
+    use futures::stream;
+
+    /// Answers with the content of the last message.
+    struct EchoProvider;
+
+    impl EchoProvider {
+        fn completion(request: &ChatCompletionRequest) -> ChatCompletion {
+            let request = serde_json::to_value(request).unwrap();
+            let content =
+                request["messages"].as_array().unwrap().last().unwrap()["content"].clone();
+
+            serde_json::from_value(serde_json::json!({
+                "id": "echo-1",
+                "object": "chat.completion",
+                "created": 0,
+                "model": request["model"],
+                "choices": [{
+                    "index": 0,
+                    "message": {"role": "assistant", "content": content},
+                    "finish_reason": "stop"
+                }]
+            }))
+            .unwrap()
+        }
+    }
+
+    #[async_trait]
+    impl ChatProvider for EchoProvider {
+        async fn create(
+            &self,
+            request: &ChatCompletionRequest,
+        ) -> Result<Result<ChatCompletion, OpenAiError>, RequestError> {
+            Ok(Ok(Self::completion(request)))
+        }
+
+        fn create_stream(
+            &self,
+            _request: &ChatCompletionRequest,
+        ) -> BoxStream<'static, Result<ChatCompletionEvent, StreamError>> {
+            stream::empty().boxed()
+        }
+    }
+
+    #[tokio::test]
+    async fn providers() {
+        let providers: Vec<Box<dyn ChatProvider>> =
+            vec![Box::new(Client::new()), Box::new(EchoProvider)];
+        let request = ChatCompletion::builder(
+            ModelID::Gpt3_5Turbo,
+            [ChatCompletionMessage::user("Hello!")],
+        )
+        .build()
+        .unwrap();
+        let completion = providers[1].create(&request).await.unwrap().unwrap();
+
+        assert_eq!(completion.content(), Some("Hello!"));
+        assert!(providers[1].create_stream(&request).next().await.is_none());
+    }
+}
@@ -0,0 +1,134 @@
+//! Count tokens locally, the way models split text into them.
+//!
+//! Requires the `tokenizer` feature.
+
+use super::{chat::ChatCompletionMessage, models::ModelID};
+use tiktoken_rs::{
+    cl100k_base_singleton, o200k_base_singleton, p50k_base_singleton, p50k_edit_singleton,
+    r50k_base_singleton, tokenizer::Tokenizer, CoreBPE,
+};
+
+/// Counts the tokens `model` splits `text` into.
+///
+/// Models without a known encoding are assumed to use `cl100k_base`,
+/// the encoding of the GPT-3.5 and GPT-4 chat models and the embedding models.
+pub fn count_tokens(model: &ModelID, text: &str) -> usize {
+    encode(model, text).len()
+}
+
+/// Estimates the prompt tokens of a chat completion request with the given messages,
+/// including the tokens that separate the messages and prime the reply.
+///
+/// The exact overhead differs between models, so this may be off by a few tokens.
+pub fn count_message_tokens(model: &ModelID, messages: &[ChatCompletionMessage]) -> usize {
+    let messages: usize = messages
+        .iter()
+        .map(|message| {
+            let name = match &message.name {
+                Some(name) => count_tokens(model, name) + 1,
+                None => 0,
+            };
+
+            3 + count_tokens(model, message.role.as_str())
+                + count_tokens(model, message.content.as_deref().unwrap_or_default())
+                + name
+        })
+        .sum();
+
+    messages + 3
+}
+
+/// Truncates `text` to at most `max_tokens` of the tokens `model` splits it into,
+/// e.g. to fit it into a context window.
+///
+/// The cut is made between tokens, or before the character a token ends within,
+/// so the result can be a little shorter than `max_tokens`.
+pub fn truncate<'a>(model: &ModelID, text: &'a str, max_tokens: usize) -> &'a str {
+    with_encoding(model, |encoding| {
+        let tokens = encoding.encode_ordinary(text);
+
+        if tokens.len() <= max_tokens {
+            return text;
+        }
+
+        // Encoding the cut text can split its end differently, so fewer tokens are kept until it fits
+        for end in (0..=max_tokens).rev() {
+            let mut len = encoding._decode_native(&tokens[..end]).len();
+
+            while !text.is_char_boundary(len) {
+                len -= 1;
+            }
+
+            if encoding.encode_ordinary(&text[..len]).len() <= max_tokens {
+                return &text[..len];
+            }
+        }
+
+        ""
+    })
+}
+
+fn encode(model: &ModelID, text: &str) -> Vec<usize> {
+    with_encoding(model, |encoding| encoding.encode_ordinary(text))
+}
+
+fn with_encoding<T>(model: &ModelID, f: impl FnOnce(&CoreBPE) -> T) -> T {
+    let encoding = match tiktoken_rs::tokenizer::get_tokenizer(&model.to_string()) {
+        Some(Tokenizer::O200kBase) => o200k_base_singleton(),
+        Some(Tokenizer::P50kBase) => p50k_base_singleton(),
+        Some(Tokenizer::P50kEdit) => p50k_edit_singleton(),
+        Some(Tokenizer::R50kBase | Tokenizer::Gpt2) => r50k_base_singleton(),
+        Some(Tokenizer::Cl100kBase) | None => cl100k_base_singleton(),
+    };
+    let result = f(&encoding.lock());
+
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn count_tokens() {
+        assert_eq!(super::count_tokens(&ModelID::Gpt3_5Turbo, "Hello world"), 2);
+        assert_eq!(super::count_tokens(&ModelID::Gpt3_5Turbo, ""), 0);
+        assert_eq!(
+            super::count_tokens(&ModelID::Custom("my-model".to_string()), "Hello world"),
+            2,
+        );
+    }
+
+    #[test]
+    fn count_message_tokens() {
+        let model = ModelID::Gpt3_5Turbo;
+        let messages = [
+            ChatCompletionMessage::system("You are a helpful assistant."),
+            ChatCompletionMessage {
+                name: Some("alice".to_string()),
+                ..ChatCompletionMessage::user("Hello world")
+            },
+        ];
+        let system = super::count_tokens(&model, "You are a helpful assistant.");
+        let name = super::count_tokens(&model, "alice");
+
+        // 3 tokens per message, its role and content, the name and 1 for it, 3 to prime the reply
+        assert_eq!(
+            super::count_message_tokens(&model, &messages),
+            (3 + 1 + system) + (3 + 1 + 2 + name + 1) + 3,
+        );
+        assert_eq!(super::count_message_tokens(&model, &[]), 3);
+    }
+
+    #[test]
+    fn truncate() {
+        let model = ModelID::Gpt3_5Turbo;
+
+        assert_eq!(super::truncate(&model, "Hello world", 1), "Hello");
+        assert_eq!(super::truncate(&model, "Hello world", 2), "Hello world");
+        assert_eq!(super::truncate(&model, "Hello world", 0), "");
+        // The crab is made of several tokens, which can't be kept apart
+        assert_eq!(super::count_tokens(&model, "🦀"), 3);
+        assert_eq!(super::truncate(&model, "Hello 🦀", 3), "Hello ");
+    }
+}